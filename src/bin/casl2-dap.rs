@@ -0,0 +1,8 @@
+use std::io;
+
+/// 標準入出力でDAPクライアントとやりとりする
+fn main() -> io::Result<()> {
+    let input = io::BufReader::new(io::stdin());
+    let mut output = io::stdout();
+    x_casl2::dap::serve(input, &mut output)
+}
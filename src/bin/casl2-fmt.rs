@@ -0,0 +1,76 @@
+use std::{fs, io::{self, Read}, process::ExitCode};
+
+use x_casl2::emurator::casl2::{formatter::{check, format_source}, source::SourceText};
+
+/// CASL2のソースを整形する
+/// `casl2-fmt [--check] [FILE]...`
+/// ファイルがなければ標準入力を整形して標準出力に出す
+/// `--check`なら書き換えずに整形されていないファイルを報告する
+/// Shift_JISやEUC-JPのファイルは元の文字コードのまま書き戻す
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let check_mode = args.iter().any(|a| a == "--check");
+    let files: Vec<&String> = args.iter().filter(|a| *a != "--check").collect();
+
+    if files.is_empty() {
+        let mut bytes = vec![];
+        if let Err(e) = io::stdin().read_to_end(&mut bytes) {
+            eprintln!("<stdin>: {}", e);
+            return ExitCode::FAILURE;
+        }
+        let src = match SourceText::decode(&bytes, None) {
+            Ok(src) => src,
+            Err(e) => {
+                eprintln!("<stdin>: {}", e);
+                return ExitCode::FAILURE;
+            }
+        };
+        return run("<stdin>", &src, check_mode, |formatted| {
+            print!("{}", formatted);
+            Ok(())
+        });
+    }
+
+    let mut code = ExitCode::SUCCESS;
+    for file in files {
+        let result = match SourceText::read(file, None) {
+            Ok(src) => run(file, &src, check_mode, |formatted| {
+                let bytes = src.encode(formatted).map_err(io::Error::other)?;
+                fs::write(file, bytes)
+            }),
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                ExitCode::FAILURE
+            }
+        };
+        if result != ExitCode::SUCCESS {
+            code = result;
+        }
+    }
+    code
+}
+
+fn run(name: &str, src: &SourceText, check_mode: bool, write: impl FnOnce(&str) -> io::Result<()>) -> ExitCode {
+    if check_mode {
+        return match check(&src.text) {
+            Ok(lines) if lines.is_empty() => ExitCode::SUCCESS,
+            Ok(lines) => {
+                for l in lines {
+                    println!("{}:{}: not formatted\n  - {}\n  + {}", name, l.line + 1, l.original, l.formatted);
+                }
+                ExitCode::FAILURE
+            }
+            Err(e) => {
+                eprintln!("{}", src.describe(name, &e));
+                ExitCode::FAILURE
+            }
+        };
+    }
+    match format_source(&src.text).map_err(|e| src.describe(name, &e)).and_then(|f| write(&f).map_err(|e| format!("{}: {}", name, e))) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
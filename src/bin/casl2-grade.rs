@@ -0,0 +1,61 @@
+use std::{fs, path::Path, process::ExitCode};
+
+use x_casl2::{emurator::casl2::source::SourceText, grader::{grade, not_assembled, report::{to_json, to_junit}, Submission, TestCase}};
+
+/// 提出されたソースをまとめて採点する
+/// `casl2-grade [--json FILE] [--junit FILE] CASES SUBMISSION...`
+/// CASESはテストケースの配列のJSON 学生の名前はファイル名から拡張子を除いたもの
+/// `--json`がなければJSONを標準出力に出す
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let mut json_file = None;
+    let mut junit_file = None;
+    let mut positional = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json_file = args.next(),
+            "--junit" => junit_file = args.next(),
+            _ => positional.push(arg),
+        }
+    }
+    let Some((cases_file, files)) = positional.split_first() else {
+        eprintln!("usage: casl2-grade [--json FILE] [--junit FILE] CASES SUBMISSION...");
+        return ExitCode::FAILURE;
+    };
+
+    let cases = match fs::read_to_string(cases_file).map_err(|e| e.to_string()).and_then(|t| TestCase::list_from_json(&t).map_err(|e| e.to_string())) {
+        Ok(cases) => cases,
+        Err(e) => {
+            eprintln!("{}: {}", cases_file, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut results = vec![];
+    for file in files {
+        let student = Path::new(file).file_stem().map_or(file.clone(), |s| s.to_string_lossy().to_string());
+        let result = match SourceText::read(file, None) {
+            Ok(src) => grade(&Submission { student, source: src.text }, &cases),
+            Err(e) => not_assembled(&student, &cases, &e.to_string()),
+        };
+        results.push(result);
+    }
+
+    for r in &results {
+        eprintln!("{}: {}/{}", r.student, r.passed(), r.cases.len());
+    }
+
+    let json = serde_json::to_string_pretty(&to_json(&results)).unwrap();
+    let written = match &json_file {
+        Some(path) => fs::write(path, json + "\n"),
+        None => {
+            println!("{}", json);
+            Ok(())
+        }
+    }
+    .and_then(|_| junit_file.as_ref().map_or(Ok(()), |path| fs::write(path, to_junit(&results))));
+    if let Err(e) = written {
+        eprintln!("{}", e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
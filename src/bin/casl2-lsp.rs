@@ -0,0 +1,8 @@
+use std::io;
+
+/// 標準入出力でLSPクライアントとやりとりする
+fn main() -> io::Result<()> {
+    let mut input = io::stdin().lock();
+    let mut output = io::stdout();
+    x_casl2::lsp::serve(&mut input, &mut output)
+}
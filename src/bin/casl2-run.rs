@@ -0,0 +1,202 @@
+use std::{fs::{self, File}, io::BufWriter, process::ExitCode};
+
+use x_casl2::emurator::{casl2::{backtrace::{describe_fault, describe_mismatch}, chrome_trace::chrome_trace, code_gen::CodeGenerator, convention, shadow, coverage::CoverageReport, profile::ProfileReport, source::{SourceEncoding, SourceText}}, commet2::{console::StdioConsole, convention::ConventionChecker, shadow::Location, stack::StackGuard, coverage::Coverage, cpu::CPUExecution, profile::Profile, prefix::machine_cycle, trace::TraceRecorder, vcd::VcdWriter}, loader::Loader};
+
+const USAGE: &str = "usage: casl2-run [--encoding ENCODING] [--coverage FILE] [--profile FILE] [--vcd FILE] [--trace FILE [--trace-instructions]] [--protect] [--uninit] [--stack] [--convention [--allow LABEL=GR1,...]...] FILE";
+
+/// CASL2のソースをアセンブルして実行する IN/OUTは標準入出力につなぐ
+/// `casl2-run [--encoding ENCODING] [--coverage FILE] [--profile FILE] [--vcd FILE] [--trace FILE [--trace-instructions]] [--protect] [--uninit] [--stack] [--convention [--allow LABEL=GR1,...]...] FILE`
+/// 文字コードを指定しなければUTF-8/Shift_JIS/EUC-JPを判定する
+/// `--coverage`なら行と分岐のカバレッジを書き出す 拡張子が`.html`ならHTML、`.info`か`.lcov`ならlcov、それ以外は注釈付きのリスト
+/// `--profile`ならルーチンごとのサイクル数の表を標準エラーに出し、flame graph用の折りたたんだスタックを書き出す
+/// `--vcd`ならマイクロサイクルごとのレジスタの変化をVCDで書き出す
+/// `--trace`ならCALL/RETとSVCをChrome trace-eventのJSONで書き出す `--trace-instructions`で命令も1つずつ入れる
+/// `--protect`なら命令の書き換えとデータの実行で止める
+/// `--uninit`なら未初期化の値を使った所を標準エラーに出す
+/// `--stack`ならスタックがプログラムにあふれたら止め、ルーチンごとの最大の深さを標準エラーに出す
+/// `--convention`ならGR1からGR7を壊して戻ったルーチンを標準エラーに出す `--allow SUB=GR1`でSUBはGR1を変えてよい
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let mut encoding = None;
+    let mut coverage_file = None;
+    let mut profile_file = None;
+    let mut vcd_file = None;
+    let mut trace_file = None;
+    let mut trace_instructions = false;
+    let mut protect = false;
+    let mut uninit = false;
+    let mut stack = false;
+    let mut convention = false;
+    let mut allow = vec![];
+    let mut file = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--encoding" => {
+                let name = args.next().unwrap_or_default();
+                let Some(e) = SourceEncoding::from_name(&name) else {
+                    eprintln!("unknown encoding: {}", name);
+                    return ExitCode::FAILURE;
+                };
+                encoding = Some(e);
+            }
+            "--coverage" => coverage_file = args.next(),
+            "--profile" => profile_file = args.next(),
+            "--vcd" => vcd_file = args.next(),
+            "--trace" => trace_file = args.next(),
+            "--trace-instructions" => trace_instructions = true,
+            "--protect" => protect = true,
+            "--uninit" => uninit = true,
+            "--stack" => stack = true,
+            "--convention" => convention = true,
+            "--allow" => {
+                convention = true;
+                allow.extend(args.next());
+            }
+            _ if file.is_none() => file = Some(arg),
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let Some(file) = file else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+
+    let src = match SourceText::read(&file, encoding) {
+        Ok(src) => src,
+        Err(e) => {
+            eprintln!("{}: {}", file, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let loader = Loader::new();
+    let loaded = CodeGenerator::from_source(&file, &src.text)
+        .and_then(|mut code_gen| {
+            code_gen.base = loader.base;
+            Ok((code_gen.generate_standalone()?, code_gen))
+        })
+        .and_then(|(objects, code_gen)| Ok((loader.load_objects(&objects)?, code_gen, loader.base + objects.iter().map(|o| o.code.len()).sum::<usize>() as u16)));
+    let (mut cpu, code_gen, image_end) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{}", src.describe(&file, &e));
+            return ExitCode::FAILURE;
+        }
+    };
+    cpu.supervisor = Some(Box::new(StdioConsole));
+    if coverage_file.is_some() {
+        cpu.coverage = Some(Coverage::new());
+    }
+    if profile_file.is_some() {
+        cpu.profile = Some(Profile::new());
+    }
+    if protect {
+        cpu.protection = Some(code_gen.protection());
+    }
+    if stack {
+        cpu.stack = Some(StackGuard::new(image_end, cpu.state.sp));
+    }
+    if convention {
+        let checker = allow.iter().try_fold(ConventionChecker::new(), |checker, spec| convention::allow(&code_gen, checker, spec));
+        match checker {
+            Ok(checker) => cpu.convention = Some(checker),
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    if uninit {
+        let mut shadow = code_gen.shadow();
+        // ローダーが積んだ戻り番地
+        shadow.define(Location::Memory(cpu.state.sp));
+        cpu.shadow = Some(shadow);
+    }
+    let recorder = TraceRecorder::new(trace_instructions);
+    if trace_file.is_some() {
+        cpu.tracer = Some(Box::new(recorder.clone()));
+    }
+    let entry = cpu.state.pr;
+    let mut vcd = match &vcd_file {
+        Some(path) => match File::create(path).and_then(|f| VcdWriter::new(BufWriter::new(f), &cpu.state)) {
+            Ok(vcd) => Some(vcd),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+    while cpu.state.machine_cycle != machine_cycle::END {
+        let notify = cpu.commet2_step();
+        if let Some(vcd) = &mut vcd
+            && let Err(e) = vcd.record(&cpu.state, &notify)
+        {
+            eprintln!("{}: {}", vcd_file.as_deref().unwrap_or_default(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+    if let Some(Err(e)) = vcd.map(|vcd| vcd.finish()) {
+        eprintln!("{}: {}", vcd_file.as_deref().unwrap_or_default(), e);
+        return ExitCode::FAILURE;
+    }
+
+    if let Some(shadow) = &cpu.shadow {
+        for warning in &shadow.warnings {
+            eprint!("{}", shadow::describe(&code_gen, warning));
+        }
+    }
+    if let Some(stack) = &cpu.stack {
+        eprintln!("stack: max {} words", stack.max_depth());
+        let mut usage: Vec<_> = stack.usage.iter().collect();
+        usage.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (&addr, &depth) in usage {
+            eprintln!("  {:<10} {:>5}", code_gen.symbolize(addr), depth);
+        }
+    }
+    if let Some(checker) = &cpu.convention {
+        for clobbered in &checker.violations {
+            eprint!("{}", convention::describe(&code_gen, clobbered));
+        }
+    }
+    for mismatch in &cpu.calls.mismatches {
+        eprintln!("{}: {}", file, describe_mismatch(&code_gen, mismatch));
+    }
+    if let Some(fault) = &cpu.fault {
+        eprint!("{}: {}", file, describe_fault(&code_gen, fault, &cpu.calls));
+    }
+    if let (Some(path), Some(coverage)) = (coverage_file, &cpu.coverage) {
+        let report = CoverageReport::new(&code_gen, coverage);
+        let text = match path.rsplit_once('.').map(|(_, ext)| ext) {
+            Some("html") => report.html(),
+            Some("info" | "lcov") => report.lcov(),
+            _ => report.listing(),
+        };
+        if let Err(e) = fs::write(&path, text) {
+            eprintln!("{}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+        eprintln!("{}", report.summary());
+    }
+    if let (Some(path), Some(profile)) = (profile_file, &cpu.profile) {
+        let report = ProfileReport::new(&code_gen, profile);
+        if let Err(e) = fs::write(&path, report.folded()) {
+            eprintln!("{}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+        eprint!("{}", report.table());
+    }
+    if let Some(path) = trace_file {
+        let trace = chrome_trace(&code_gen, &recorder.events(), entry, cpu.state.cycle);
+        if let Err(e) = fs::write(&path, trace.to_string()) {
+            eprintln!("{}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    }
+    if cpu.fault.is_some() {
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,46 @@
+use std::{fs, path::Path, process::ExitCode};
+
+use x_casl2::{emurator::casl2::source::SourceText, grader::{unit::{discover, parse, run_tests}, Outcome}};
+
+/// `.cas`の隣の`.test`ファイルを探して単体テストを実行する
+/// `casl2-test [PATH]...` PATHがなければカレントディレクトリの下を探す
+fn main() -> ExitCode {
+    let mut paths: Vec<String> = std::env::args().skip(1).collect();
+    if paths.is_empty() {
+        paths.push(".".to_string());
+    }
+
+    let (mut passed, mut failed) = (0, 0);
+    for path in &paths {
+        for (test_file, source_file) in discover(Path::new(path)) {
+            let name = test_file.display().to_string();
+            let loaded = fs::read_to_string(&test_file)
+                .map_err(|e| e.to_string())
+                .and_then(|t| parse(&t).map_err(|e| e.to_string()))
+                .and_then(|tests| SourceText::read(&source_file, None).map(|src| (tests, src.text)).map_err(|e| e.to_string()));
+            let (tests, src) = match loaded {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    println!("{}: {}", name, e);
+                    failed += 1;
+                    continue;
+                }
+            };
+            for result in run_tests(&source_file.display().to_string(), &src, &tests) {
+                if result.outcome == Outcome::Passed {
+                    println!("test {}::{} ... ok", name, result.name);
+                    passed += 1;
+                } else {
+                    println!("test {}::{} ... {}", name, result.name, result.outcome.status());
+                    for msg in result.outcome.messages() {
+                        println!("    {}", msg);
+                    }
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    println!("\n{} passed; {} failed", passed, failed);
+    if failed == 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
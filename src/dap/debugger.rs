@@ -0,0 +1,174 @@
+use std::collections::HashSet;
+
+use crate::emurator::{casl2::{code_gen::CodeGenerator, err::Casl2AssemblerError}, commet2::{console::BufferConsole, cpu::{CPUExecution, CPU}, prefix::{instruction, machine_cycle, svc}, stack::StackGuard}, loader::Loader};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunMode {
+    Continue,
+    /// 1命令進める
+    StepIn,
+    /// 呼び出しの深さが指定以下になるまで進める
+    StepOver(usize),
+    /// 呼び出しの深さが指定未満になるまで進める
+    StepOut(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Step,
+    Breakpoint,
+    /// INを実行しようとしたが入力がない
+    Input,
+    /// CPUが止まった 原因は`cpu.fault`
+    Exception,
+    Terminated,
+}
+
+/// アセンブルしたプログラムをCPUで動かしながら状態を見るやつ
+pub struct Debugger {
+    pub cpu: Box<CPU>,
+    pub code_gen: CodeGenerator,
+    /// プログラムを置いたローダー
+    pub loader: Loader,
+    /// ブレークポイントのアドレス
+    pub breakpoints: HashSet<u16>,
+    /// 最後に止まった番地 そこから再開する最初の1命令だけブレークポイントを見ない
+    pub resume_at: Option<u16>,
+    pub terminated: bool,
+    /// IN/OUTの入出力 デバッグコンソールから入力を足す
+    pub console: BufferConsole,
+}
+
+impl Debugger {
+    /// ソースをアセンブルしてローダーの`base`に配置し、実行開始アドレスで止まった状態にする
+    /// スタックがプログラムまで伸びたら止める
+    pub fn launch(file: &str, src: &str) -> Result<Self, Casl2AssemblerError> {
+        let mut code_gen = CodeGenerator::from_source(file, src)?;
+        let loader = Loader::new();
+        code_gen.base = loader.base;
+        let bin = code_gen.generate()?;
+        let mut cpu = loader.load_words(&bin, code_gen.entry)?;
+        let console = BufferConsole::default();
+        cpu.supervisor = Some(Box::new(console.clone()));
+        cpu.stack = Some(StackGuard::new(loader.base + bin.len() as u16, cpu.state.sp));
+        Ok(Debugger {
+            cpu,
+            code_gen,
+            loader,
+            breakpoints: HashSet::new(),
+            resume_at: None,
+            terminated: false,
+            console,
+        })
+    }
+
+    /// 1命令実行する 呼び出しの並びはCPUの`calls`が持つ
+    /// 実行できない命令はCPUのfaultにして止める
+    pub fn step(&mut self) {
+        if self.terminated || self.cpu.fault.is_some() {
+            return;
+        }
+        self.cpu.casl_step();
+        if self.cpu.state.machine_cycle == machine_cycle::END && self.cpu.fault.is_none() {
+            self.terminated = true;
+        }
+    }
+
+    /// 呼び出しの深さ
+    pub fn depth(&self) -> usize {
+        self.cpu.calls.frames.len()
+    }
+
+    /// 実行中の命令の番地 faultで止まったならその命令
+    pub fn pc(&self) -> u16 {
+        self.cpu.fault.as_ref().map(|f| f.pr).unwrap_or(self.cpu.state.pr)
+    }
+
+    /// `mode`に従って止まるまで実行する
+    /// ブレークポイントは命令を実行する前に見る
+    /// `budget`命令実行しても止まらなければ`None`
+    pub fn run(&mut self, mode: RunMode, budget: usize) -> Option<StopReason> {
+        let stop = self.run_until(mode, budget);
+        if stop.is_some() {
+            self.resume_at = Some(self.cpu.state.pr);
+        }
+        stop
+    }
+
+    fn run_until(&mut self, mode: RunMode, budget: usize) -> Option<StopReason> {
+        for _ in 0..budget {
+            // 止まった後に進めようとしたら終わり
+            if self.cpu.fault.is_some() {
+                self.terminated = true;
+                return Some(StopReason::Terminated);
+            }
+            let pr = self.cpu.state.pr;
+            if self.resume_at.take() != Some(pr) && self.breakpoints.contains(&pr) {
+                return Some(StopReason::Breakpoint);
+            }
+            if self.waiting_for_input() {
+                return Some(StopReason::Input);
+            }
+            self.step();
+            if self.cpu.fault.is_some() {
+                return Some(StopReason::Exception);
+            }
+            if self.terminated {
+                return Some(StopReason::Terminated);
+            }
+            let depth = self.depth();
+            let stepped = match mode {
+                RunMode::Continue => false,
+                RunMode::StepIn => true,
+                RunMode::StepOver(d) => depth <= d,
+                RunMode::StepOut(d) => depth < d,
+            };
+            if stepped {
+                return Some(StopReason::Step);
+            }
+        }
+        None
+    }
+
+    /// PRの命令が SVC 1 (IN) で入力がまだないか
+    pub fn waiting_for_input(&self) -> bool {
+        let memory = &self.cpu.state.memory.0;
+        let pr = self.cpu.state.pr;
+        let op = (memory[pr as usize] >> 8) as u8;
+        op == instruction::w2::SVC
+            && memory[pr.wrapping_add(1) as usize] == svc::IN
+            && self.console.pending_input() == 0
+    }
+
+    /// PRの命令に対するステップオーバーのモード
+    /// CALLなら戻ってくるまで、それ以外は1命令
+    pub fn step_over_mode(&self) -> RunMode {
+        let op = (self.cpu.state.memory.0[self.cpu.state.pr as usize] >> 8) as u8;
+        if op == instruction::w2::CALL {
+            RunMode::StepOver(self.depth())
+        } else {
+            RunMode::StepIn
+        }
+    }
+
+    /// アドレスを含む行 (0始まり)
+    pub fn line_of(&self, addr: u16) -> Option<usize> {
+        self.code_gen.source_map.lookup(addr).map(|e| e.line)
+    }
+
+    /// 行 (0始まり) に置けるブレークポイントのアドレス
+    /// 命令がない行なら次の命令の行にずらす
+    pub fn breakpoint_at(&self, line: usize) -> Option<(usize, u16)> {
+        self.code_gen
+            .mem_lines
+            .iter()
+            .filter(|m| m.line >= line && m.node.is_instruction())
+            .min_by_key(|m| m.line)
+            .map(|m| (m.line, self.code_gen.base.wrapping_add(m.addr)))
+    }
+
+    /// アドレスをいちばん近い手前のラベルからの相対で表す
+    pub fn symbolize(&self, addr: u16) -> String {
+        self.code_gen.symbolize(addr)
+    }
+}
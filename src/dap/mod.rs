@@ -0,0 +1,458 @@
+pub mod debugger;
+
+use std::{io::{self, BufRead, Write}, path::Path, sync::mpsc, thread};
+
+use serde_json::{json, Value};
+
+use crate::{dap::debugger::{Debugger, RunMode, StopReason}, emurator::{casl2::{backtrace::describe_fault, code_gen::CodeGenerator, source::{SourceEncoding, SourceText}}, jisx0201}};
+
+pub use crate::rpc::{read_message, write_message};
+
+/// 実行中に一度に進める命令数 この間隔でpauseなどの要求を確認する
+pub const RUN_CHUNK: usize = 10000;
+
+/// 変数参照番号 レジスタ
+const REF_REGISTERS: i64 = 1;
+/// 変数参照番号 ラベル
+const REF_LABELS: i64 = 2;
+/// 変数参照番号 この値+アドレスでラベル以降のメモリを展開する
+const REF_MEMORY: i64 = 0x10000;
+
+/// Debug Adapter Protocol のデバッグセッション
+/// 要求を受けて応答とイベントを返す 入出力は持たない
+pub struct Session {
+    pub debugger: Option<Debugger>,
+    /// 起動したプログラムのパス
+    program: String,
+    /// 設定済みのブレークポイント (0始まりの行)
+    breakpoint_lines: Vec<usize>,
+    lines_start_at1: bool,
+    stop_on_entry: bool,
+    configured: bool,
+    /// 実行中なら進め方
+    running: Option<RunMode>,
+    seq: i64,
+    /// disconnectを受け取った
+    pub exit: bool,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session {
+            debugger: None,
+            program: String::new(),
+            breakpoint_lines: Vec::new(),
+            lines_start_at1: true,
+            stop_on_entry: false,
+            configured: false,
+            running: None,
+            seq: 0,
+            exit: false,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    fn next_seq(&mut self) -> i64 {
+        self.seq += 1;
+        self.seq
+    }
+
+    fn response(&mut self, req: &Value, success: bool, body: Value) -> Value {
+        let mut res = json!({
+            "seq": self.next_seq(),
+            "type": "response",
+            "request_seq": req["seq"],
+            "success": success,
+            "command": req["command"],
+            "body": body,
+        });
+        if !success {
+            res["message"] = body["error"]["format"].clone();
+        }
+        res
+    }
+
+    fn error(&mut self, req: &Value, msg: String) -> Value {
+        self.response(req, false, json!({ "error": { "id": 1, "format": msg } }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> Value {
+        json!({
+            "seq": self.next_seq(),
+            "type": "event",
+            "event": event,
+            "body": body,
+        })
+    }
+
+    fn to_client_line(&self, line: usize) -> usize {
+        if self.lines_start_at1 { line + 1 } else { line }
+    }
+
+    fn to_server_line(&self, line: usize) -> usize {
+        if self.lines_start_at1 { line.saturating_sub(1) } else { line }
+    }
+
+    fn source(&self) -> Value {
+        let name = Path::new(&self.program)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        json!({ "name": name, "path": self.program })
+    }
+
+    /// 要求を1つ処理して応答とイベントを返す
+    pub fn handle(&mut self, req: &Value) -> Vec<Value> {
+        let args = &req["arguments"];
+        let command = req["command"].as_str().unwrap_or_default();
+        match command {
+            "initialize" => {
+                self.lines_start_at1 = args["linesStartAt1"].as_bool().unwrap_or(true);
+                vec![self.response(req, true, json!({
+                    "supportsConfigurationDoneRequest": true,
+                }))]
+            }
+            "launch" => {
+                let program = args["program"].as_str().unwrap_or_default().to_string();
+                // Shift_JISやEUC-JPのソースも読める "encoding"がなければ判定する
+                let encoding = args["encoding"].as_str().and_then(SourceEncoding::from_name);
+                let src = match SourceText::read(&program, encoding) {
+                    Ok(src) => src,
+                    Err(e) => return vec![self.error(req, format!("cannot read {}: {}", program, e))],
+                };
+                self.program = program;
+                self.launch_source(req, &src.text)
+            }
+            "setBreakpoints" => {
+                let lines: Vec<usize> = args["breakpoints"]
+                    .as_array()
+                    .map(|bps| bps.iter().filter_map(|bp| bp["line"].as_u64()).map(|l| self.to_server_line(l as usize)).collect())
+                    .unwrap_or_default();
+                self.breakpoint_lines = lines.clone();
+                let breakpoints: Vec<Value> = lines
+                    .iter()
+                    .map(|&line| self.resolve_breakpoint(line))
+                    .collect();
+                self.apply_breakpoints();
+                vec![self.response(req, true, json!({ "breakpoints": breakpoints }))]
+            }
+            "setExceptionBreakpoints" => {
+                vec![self.response(req, true, json!({ "breakpoints": [] }))]
+            }
+            "configurationDone" => {
+                self.configured = true;
+                let mut out = vec![self.response(req, true, Value::Null)];
+                out.extend(self.start());
+                out
+            }
+            "threads" => {
+                vec![self.response(req, true, json!({ "threads": [{ "id": 1, "name": "COMET2" }] }))]
+            }
+            "stackTrace" => {
+                let frames = self.stack_frames();
+                let total = frames.len();
+                vec![self.response(req, true, json!({ "stackFrames": frames, "totalFrames": total }))]
+            }
+            "scopes" => {
+                vec![self.response(req, true, json!({ "scopes": [
+                    { "name": "Registers", "variablesReference": REF_REGISTERS, "expensive": false },
+                    { "name": "Labels", "variablesReference": REF_LABELS, "expensive": false },
+                ] }))]
+            }
+            "variables" => {
+                let variables = self.variables(args["variablesReference"].as_i64().unwrap_or(0));
+                vec![self.response(req, true, json!({ "variables": variables }))]
+            }
+            "continue" => self.resume_with(req, RunMode::Continue),
+            "stepIn" => self.resume_with(req, RunMode::StepIn),
+            "next" => {
+                let mode = match &self.debugger {
+                    Some(debugger) => debugger.step_over_mode(),
+                    None => RunMode::StepIn,
+                };
+                self.resume_with(req, mode)
+            }
+            "stepOut" => {
+                let depth = self.debugger.as_ref().map(|d| d.depth()).unwrap_or(0);
+                self.resume_with(req, RunMode::StepOut(depth))
+            }
+            "pause" => {
+                let mut out = vec![self.response(req, true, Value::Null)];
+                if self.running.take().is_some() {
+                    out.push(self.event("stopped", json!({ "reason": "pause", "threadId": 1, "allThreadsStopped": true })));
+                }
+                out
+            }
+            "evaluate" => {
+                // デバッグコンソールに打った行はINの入力にする
+                let Some(debugger) = &self.debugger else {
+                    return vec![self.error(req, "no program is running".to_string())];
+                };
+                let line = args["expression"].as_str().unwrap_or_default();
+                debugger.console.push_input(line);
+                let result = format!("IN <- {}", line);
+                vec![self.response(req, true, json!({ "result": result, "variablesReference": 0 }))]
+            }
+            "disconnect" | "terminate" => {
+                self.running = None;
+                self.exit = true;
+                vec![self.response(req, true, Value::Null)]
+            }
+            _ => vec![self.error(req, format!("unsupported request: {}", command))],
+        }
+    }
+
+    /// ソースを直接アセンブルして起動する
+    /// `"protect": true`なら命令の書き換えとデータの実行で止める
+    pub fn launch_source(&mut self, req: &Value, src: &str) -> Vec<Value> {
+        self.stop_on_entry = req["arguments"]["stopOnEntry"].as_bool().unwrap_or(false);
+        match Debugger::launch(&self.program, src) {
+            Ok(mut debugger) => {
+                if req["arguments"]["protect"].as_bool().unwrap_or(false) {
+                    debugger.cpu.protection = Some(debugger.code_gen.protection());
+                }
+                self.debugger = Some(debugger);
+                self.apply_breakpoints();
+                let mut out = vec![
+                    self.response(req, true, Value::Null),
+                    self.event("initialized", Value::Null),
+                ];
+                out.extend(self.start());
+                out
+            }
+            Err(e) => vec![self.error(req, e.to_string())],
+        }
+    }
+
+    /// 起動と設定が揃ったら実行を始める
+    fn start(&mut self) -> Vec<Value> {
+        if !self.configured || self.debugger.is_none() {
+            return Vec::new();
+        }
+        if self.stop_on_entry {
+            if let Some(debugger) = self.debugger.as_mut() {
+                debugger.resume_at = Some(debugger.cpu.state.pr);
+            }
+            vec![self.event("stopped", json!({ "reason": "entry", "threadId": 1, "allThreadsStopped": true }))]
+        } else {
+            self.running = Some(RunMode::Continue);
+            Vec::new()
+        }
+    }
+
+    fn resume_with(&mut self, req: &Value, mode: RunMode) -> Vec<Value> {
+        if self.debugger.is_none() {
+            return vec![self.error(req, "no program is running".to_string())];
+        }
+        self.running = Some(mode);
+        let body = if mode == RunMode::Continue { json!({ "allThreadsContinued": true }) } else { Value::Null };
+        vec![self.response(req, true, body)]
+    }
+
+    /// 実行中なら`budget`命令まで進めて、止まったらイベントを返す
+    /// OUTの出力はoutputイベントにする
+    pub fn resume(&mut self, budget: usize) -> Vec<Value> {
+        let (Some(mode), Some(debugger)) = (self.running, self.debugger.as_mut()) else {
+            return Vec::new();
+        };
+        let stop = debugger.run(mode, budget);
+        let fault = debugger.cpu.fault.as_ref().map(|f| (f.to_string(), describe_fault(&debugger.code_gen, f, &debugger.cpu.calls)));
+        let output = debugger.console.take_output();
+        let mut out: Vec<Value> = output
+            .into_iter()
+            .map(|line| self.event("output", json!({ "category": "stdout", "output": format!("{}\n", line) })))
+            .collect();
+        match stop {
+            None => {}
+            Some(StopReason::Terminated) => {
+                self.running = None;
+                // faultで止まったなら失敗
+                let code = if fault.is_some() { 1 } else { 0 };
+                out.push(self.event("terminated", Value::Null));
+                out.push(self.event("exited", json!({ "exitCode": code })));
+            }
+            Some(StopReason::Exception) => {
+                self.running = None;
+                let (text, backtrace) = fault.unwrap_or_default();
+                out.push(self.event("output", json!({ "category": "stderr", "output": backtrace })));
+                out.push(self.event("stopped", json!({ "reason": "exception", "description": "Paused on fault", "text": text, "threadId": 1, "allThreadsStopped": true })));
+            }
+            Some(StopReason::Input) => {
+                self.running = None;
+                out.push(self.event("output", json!({ "category": "console", "output": "IN: waiting for input. Type a line in the debug console.\n" })));
+                out.push(self.event("stopped", json!({ "reason": "pause", "description": "Waiting for input", "threadId": 1, "allThreadsStopped": true })));
+            }
+            Some(reason) => {
+                self.running = None;
+                let reason = match reason {
+                    StopReason::Breakpoint => "breakpoint",
+                    _ => "step",
+                };
+                out.push(self.event("stopped", json!({ "reason": reason, "threadId": 1, "allThreadsStopped": true })));
+            }
+        }
+        out
+    }
+
+    fn resolve_breakpoint(&self, line: usize) -> Value {
+        match self.debugger.as_ref().and_then(|d| d.breakpoint_at(line)) {
+            Some((actual, _)) => json!({ "verified": true, "line": self.to_client_line(actual) }),
+            None => json!({ "verified": false, "line": self.to_client_line(line) }),
+        }
+    }
+
+    fn apply_breakpoints(&mut self) {
+        if let Some(debugger) = self.debugger.as_mut() {
+            let addrs: Vec<u16> = self
+                .breakpoint_lines
+                .iter()
+                .filter_map(|&line| debugger.breakpoint_at(line).map(|(_, addr)| addr))
+                .collect();
+            debugger.breakpoints = addrs.into_iter().collect();
+        }
+    }
+
+    fn stack_frames(&self) -> Vec<Value> {
+        let Some(debugger) = &self.debugger else {
+            return Vec::new();
+        };
+        // 先頭は現在位置 以降はCALLした位置を新しい順に
+        let calls = &debugger.cpu.calls;
+        let pcs = calls.backtrace(debugger.pc());
+        let mut entries: Vec<u16> = calls.frames.iter().rev().map(|f| f.target).collect();
+        entries.push(debugger.code_gen.base.wrapping_add(debugger.code_gen.entry));
+        pcs.iter()
+            .zip(entries)
+            .enumerate()
+            .map(|(id, (&pc, entry))| {
+                let line = debugger.line_of(pc).map(|l| self.to_client_line(l)).unwrap_or(0);
+                json!({
+                    "id": id,
+                    "name": format!("{} @ {}", debugger.symbolize(entry), debugger.symbolize(pc)),
+                    "source": self.source(),
+                    "line": line,
+                    "column": 1,
+                    "instructionPointerReference": format!("#{:04X}", pc),
+                })
+            })
+            .collect()
+    }
+
+    fn variables(&self, reference: i64) -> Vec<Value> {
+        let Some(debugger) = &self.debugger else {
+            return Vec::new();
+        };
+        let state = &debugger.cpu.state;
+        // 文字として読めるなら添える
+        let word = |name: String, val: u16, reference: i64| json!({
+            "name": name,
+            "value": match jisx0201::printable(val) {
+                Some(c) => format!("#{:04X} ({}) '{}'", val, val as i16, c),
+                None => format!("#{:04X} ({})", val, val as i16),
+            },
+            "variablesReference": reference,
+        });
+        match reference {
+            REF_REGISTERS => {
+                let mut vars: Vec<Value> = (0..8)
+                    .map(|i| word(format!("GR{}", i), state.gr.get(i), 0))
+                    .collect();
+                vars.push(word("SP".to_string(), state.sp, 0));
+                vars.push(word("PR".to_string(), state.pr, 0));
+                vars.push(json!({
+                    "name": "FR",
+                    "value": format!("OF={} SF={} ZF={}", state.fr[0] as u8, state.fr[1] as u8, state.fr[2] as u8),
+                    "variablesReference": 0,
+                }));
+                vars
+            }
+            REF_LABELS => {
+                let mut labels = debugger.code_gen.symbols();
+                labels.sort_by_key(|(name, addr)| (*addr, name.clone()));
+                labels
+                    .into_iter()
+                    .map(|(name, addr)| {
+                        // 複数語の領域なら中身を展開できるようにする
+                        let size = self.area_size(addr);
+                        let child = if size > 1 { REF_MEMORY + addr as i64 } else { 0 };
+                        word(name, state.memory.0[addr as usize], child)
+                    })
+                    .collect()
+            }
+            r if r >= REF_MEMORY => {
+                let addr = (r - REF_MEMORY) as u16;
+                (0..self.area_size(addr))
+                    .map(|i| {
+                        let a = addr.wrapping_add(i);
+                        word(format!("[{}] #{:04X}", i, a), state.memory.0[a as usize], 0)
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// ラベルのついた行が占める語数
+    fn area_size(&self, addr: u16) -> u16 {
+        let Some(debugger) = &self.debugger else {
+            return 0;
+        };
+        debugger
+            .code_gen
+            .mem_lines
+            .iter()
+            .find(|m| debugger.code_gen.base.wrapping_add(m.addr) == addr && !m.node.is_instruction())
+            .map(|m| CodeGenerator::node_size(&m.node).unwrap_or(0))
+            .unwrap_or(0)
+    }
+}
+
+/// 入力と出力をつないでセッションを回す
+/// 入力は別スレッドで読み、実行中も`RUN_CHUNK`ごとに要求を確認する
+pub fn serve<R, W>(input: R, output: &mut W) -> io::Result<()>
+where
+    R: BufRead + Send + 'static,
+    W: Write,
+{
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = input;
+        while let Ok(Some(msg)) = read_message(&mut input) {
+            if tx.send(msg).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut session = Session::new();
+    while !session.exit {
+        let msg = if session.is_running() {
+            for out in session.resume(RUN_CHUNK) {
+                write_message(output, &out)?;
+            }
+            match rx.try_recv() {
+                Ok(msg) => msg,
+                Err(mpsc::TryRecvError::Empty) => continue,
+                Err(mpsc::TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match rx.recv() {
+                Ok(msg) => msg,
+                Err(_) => break,
+            }
+        };
+        for out in session.handle(&msg) {
+            write_message(output, &out)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use crate::emurator::{casl2::code_gen::CodeGenerator, commet2::{callstack::{CallStack, MismatchedRet}, cpu::Fault}};
+
+/// 呼び出しの並びを1行ずつ 内側から
+///
+/// ```text
+/// in DIVIDE+3 (line 20: ADDA GR1,GR2)
+/// called from MAIN+12 (line 5: CALL DIVIDE)
+/// ```
+pub fn backtrace(code_gen: &CodeGenerator, pc: u16, calls: &CallStack) -> Vec<String> {
+    calls
+        .backtrace(pc)
+        .into_iter()
+        .enumerate()
+        .map(|(i, addr)| {
+            let place = if i == 0 { "in" } else { "called from" };
+            match code_gen.source_map.describe(addr) {
+                Some(line) => format!("{} {} ({})", place, code_gen.symbolize(addr), line),
+                None => format!("{} {}", place, code_gen.symbolize(addr)),
+            }
+        })
+        .collect()
+}
+
+/// `fault in DIVIDE+3 called from MAIN+12` の形の1行と、その下に行つきの呼び出しの並び
+pub fn describe_fault(code_gen: &CodeGenerator, fault: &Fault, calls: &CallStack) -> String {
+    let route: Vec<String> = calls.backtrace(fault.pr).into_iter().map(|a| code_gen.symbolize(a)).collect();
+    let mut out = format!("{} ({} {}) in {}\n", fault.reason, fault.kind, code_gen.symbolize(fault.addr), route.join(" called from "));
+    for line in backtrace(code_gen, fault.pr, calls) {
+        out += &format!("    {}\n", line);
+    }
+    out
+}
+
+/// 合わなかったRETの説明
+pub fn describe_mismatch(code_gen: &CodeGenerator, mismatch: &MismatchedRet) -> String {
+    let expected = match mismatch.expected {
+        Some(addr) => format!("expected {}", code_gen.symbolize(addr)),
+        None => "no matching CALL".to_string(),
+    };
+    format!(
+        "RET at {} returned to {} ({}): the stack is probably corrupted",
+        code_gen.symbolize(mismatch.pr),
+        code_gen.symbolize(mismatch.to),
+        expected
+    )
+}
@@ -0,0 +1,75 @@
+use serde_json::{json, Value};
+
+use crate::emurator::{casl2::code_gen::CodeGenerator, commet2::{cpu::ExecEvent, prefix::{opecode_to_4char, svc}}};
+
+/// 呼び出しを並べるスレッド
+const CALL_TID: u32 = 1;
+/// 命令を並べるスレッド
+const INSTRUCTION_TID: u32 = 2;
+
+/// 記録した出来事をChrome trace-eventのJSONにする chrome://tracingやPerfettoで読める
+/// 時刻はサイクル数 `entry`は最初のルーチンの番地で、`end`まで開いているスパンは`end`で閉じる
+/// CALL/RETは入れ子のスパン、SVCは瞬間のイベント、命令は次の命令までの長さのスパンにする
+/// RETで閉じるスパンはCPUの`calls`の段数に合わせる
+pub fn chrome_trace(code_gen: &CodeGenerator, events: &[(u64, ExecEvent)], entry: u16, end: u64) -> Value {
+    let thread = |tid: u32, name: &str| json!({"name": "thread_name", "ph": "M", "pid": 1, "tid": tid, "args": {"name": name}});
+    let mut out = vec![thread(CALL_TID, "calls")];
+    if events.iter().any(|(_, e)| matches!(e, ExecEvent::Fetch { .. })) {
+        out.push(thread(INSTRUCTION_TID, "instructions"));
+    }
+    let span = |ph: &str, ts: u64, name: &str| json!({"name": name, "ph": ph, "ts": ts, "pid": 1, "tid": CALL_TID});
+
+    // 開いているスパンの数 いちばん外のルーチンの分を含む
+    let mut open: usize = 1;
+    out.push(span("B", 0, &code_gen.symbolize(entry)));
+    for (i, &(ts, event)) in events.iter().enumerate() {
+        match event {
+            ExecEvent::Fetch { addr, opcode } => {
+                let next = events[i + 1..].iter().find(|(_, e)| matches!(e, ExecEvent::Fetch { .. }));
+                let dur = next.map_or(end, |&(t, _)| t) - ts;
+                let mnemonic: String = opecode_to_4char(opcode).iter().collect();
+                out.push(json!({
+                    "name": format!("{} {}", mnemonic.trim_end(), code_gen.symbolize(addr)),
+                    "ph": "X",
+                    "ts": ts,
+                    "dur": dur,
+                    "pid": 1,
+                    "tid": INSTRUCTION_TID,
+                }));
+            }
+            ExecEvent::Call { from, to } => {
+                let mut call = span("B", ts, &code_gen.symbolize(to));
+                call["args"] = json!({"from": code_gen.symbolize(from)});
+                out.push(call);
+                open += 1;
+            }
+            ExecEvent::Ret { depth, outermost, .. } => {
+                let keep = if outermost { 0 } else { (depth + 1).min(open.saturating_sub(1)) };
+                while open > keep {
+                    out.push(span("E", ts, ""));
+                    open -= 1;
+                }
+            }
+            ExecEvent::Svc { addr, code } => {
+                let name = match code {
+                    svc::IN => "IN".to_string(),
+                    svc::OUT => "OUT".to_string(),
+                    _ => format!("SVC {}", code),
+                };
+                out.push(json!({
+                    "name": name,
+                    "ph": "i",
+                    "s": "t",
+                    "ts": ts,
+                    "pid": 1,
+                    "tid": CALL_TID,
+                    "args": {"at": code_gen.symbolize(addr)},
+                }));
+            }
+        }
+    }
+    for _ in 0..open {
+        out.push(span("E", end, ""));
+    }
+    json!({"traceEvents": out, "displayTimeUnit": "ns"})
+}
@@ -1,23 +1,626 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 
-use crate::emurator::{casl2::{err::Casl2AssemblerError, parser::ASTNode, prefix::assembler_instructions}, commet2::prefix::opecode_to_binary};
+use crate::emurator::{jisx0201, casl2::{err::Casl2AssemblerError, lexer::split_fields, object::{ObjectFile, Symbol}, parser::ASTNode, prefix::assembler_instructions, source_map::{MapEntry, Origin, SourceMap}}, commet2::{prefix::opecode_to_binary, protection::{MemoryProtection, Region}, shadow::ShadowMemory}};
 
 
+/// 生成した語の値の決まり方
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    /// 定数
+    Absolute(u16),
+    /// 先頭からの番地 配置する場所でずれる
+    Relative(u16),
+    /// 他の単位のラベル
+    External(String),
+}
+
 pub struct MemLine {
     pub addr: u16,
+    /// ソース上の行番号 (0始まり)
+    pub line: usize,
+    /// `CodeGenerator::programs`の添字
+    pub program: usize,
     pub node: ASTNode,
+    /// マクロ展開やリテラルから生成された行か
+    pub origin: Origin,
+}
+
+/// START から END までの1プログラム
+/// ラベルはプログラムの中だけで有効で、STARTのラベルだけが外から見える
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    /// STARTのラベル STARTの外の命令なら空
+    pub name: String,
+    /// STARTとENDの行 (0始まり)
+    pub start_line: usize,
+    pub end_line: usize,
+    /// 先頭番地と語数 65536語になることもある
+    pub addr: u16,
+    pub size: usize,
+    /// 実行開始番地
+    pub entry: u16,
+    /// プログラム内のラベル
+    pub labels: HashMap<String, u16>,
+    /// リテラルの表記と定数のアドレス
+    pub literals: HashMap<String, u16>,
 }
 
 /// 実際のバイナリを生成する
 /// 
 /// ## 手順
-/// 1. DC命令をすべて集め、mem_linesにアドレスを決めて格納 同時にlabel_mapにラベルとアドレスを登録
-/// 2. 各命令をおなじくmem_linesにアドレスを決めて格納 label_mapにラベルとアドレスを登録
-/// 3. 先頭からバイナリを生成。labelはlabel_mapからアドレスを取得していく
+/// 1. 先頭から各行の語数を数えてmem_linesにアドレスを決めて格納 同時にプログラムごとにラベルとアドレスを登録
+///    マクロ命令は機械語命令に展開し、リテラルはそのプログラムのENDの位置に定数として置く
+///    プログラムは書いた順に続けて置く
+/// 2. STARTのラベルにそれぞれの実行開始アドレスを登録
+/// 3. 先頭からバイナリとソースマップを生成。ラベルはそのプログラムのラベル、なければSTARTのラベルから探す
 pub struct CodeGenerator {
     pub nodes: Vec<ASTNode>,
+    /// STARTのラベル どのプログラムからも見える
     pub label_map: HashMap<String, u16>,
+    pub programs: Vec<Program>,
     pub mem_lines: Vec<MemLine>,
+    /// 最初のプログラムの実行開始アドレス
+    pub entry: u16,
+    /// 最初のプログラムのSTARTのラベル
+    pub start_label: Option<String>,
+    /// ソースファイル名
+    pub file: String,
+    /// ソースの各行 列の計算に使う
+    pub source: Vec<String>,
+    /// 生成した語とソース上の位置の対応
+    pub source_map: SourceMap,
+    /// ローダーがプログラムを置く先頭番地 生成する前に決める
+    /// `label_map`、`programs`、`mem_lines`の番地は先頭からの位置のまま、
+    /// ラベルの一覧、ソースマップ、保護、影の番地はここから数える
+    pub base: u16,
+}
+
+impl CodeGenerator {
+    pub fn new(nodes: Vec<ASTNode>) -> Self {
+        CodeGenerator {
+            nodes,
+            label_map: HashMap::new(),
+            programs: Vec::new(),
+            mem_lines: Vec::new(),
+            entry: 0,
+            start_label: None,
+            file: String::new(),
+            source: Vec::new(),
+            source_map: SourceMap::new(),
+            base: 0,
+        }
+    }
+
+    /// ソースを解析してファイル名と行を覚えておく
+    pub fn from_source(file: &str, src: &str) -> Result<Self, Casl2AssemblerError> {
+        let mut code_gen = Self::new(ASTNode::de(src)?);
+        code_gen.file = file.to_string();
+        code_gen.source = src.lines().map(|l| l.to_string()).collect();
+        Ok(code_gen)
+    }
+
+    /// ソースを解析してバイナリを生成する
+    pub fn assemble(src: &str) -> Result<(Self, Vec<u16>), Casl2AssemblerError> {
+        let mut code_gen = Self::from_source("", src)?;
+        let bin = code_gen.generate()?;
+        Ok((code_gen, bin))
+    }
+
+    /// `base`から配置したバイナリを生成する
+    pub fn generate(&mut self) -> Result<Vec<u16>, Casl2AssemblerError> {
+        let base = self.base;
+        self.emit()?
+            .into_iter()
+            .map(|word| match word {
+                Operand::Absolute(v) => Ok(v),
+                Operand::Relative(v) => Ok(v.wrapping_add(base)),
+                Operand::External(label) => Err(Casl2AssemblerError::UnknownLabel(label)),
+            })
+            .collect()
+    }
+
+    /// STARTからENDまでのプログラムごとに再配置できるオブジェクトを生成する
+    /// 同じファイルの別のプログラムのSTARTのラベルと、解決できないラベルは外部参照として残す
+    pub fn generate_object(&mut self) -> Result<Vec<ObjectFile>, Casl2AssemblerError> {
+        let words = self.emit()?;
+        let mut objects = Vec::new();
+        for program in &self.programs {
+            let start = program.addr as usize;
+            let end = start + program.size;
+            let mut object = ObjectFile {
+                name: program.name.clone(),
+                entry: program.entry - program.addr,
+                ..ObjectFile::default()
+            };
+            if !program.name.is_empty() {
+                object.exports.push(Symbol { name: program.name.clone(), offset: object.entry });
+            }
+            for (i, word) in words[start..end].iter().enumerate() {
+                let offset = i as u16;
+                let external = match word {
+                    Operand::Absolute(v) => {
+                        object.code.push(*v);
+                        continue;
+                    }
+                    Operand::Relative(v) if (start..end).contains(&(*v as usize)) => {
+                        object.relocations.push(offset);
+                        object.code.push(v - program.addr);
+                        continue;
+                    }
+                    // 別のプログラムの番地はSTARTのラベルでしか参照できない
+                    Operand::Relative(v) => self.program_entry_name(*v),
+                    Operand::External(name) => name.clone(),
+                };
+                object.externals.push(Symbol { name: external, offset });
+                object.code.push(0);
+            }
+            objects.push(object);
+        }
+        Ok(objects)
+    }
+
+    /// 1つのファイルだけで実行するオブジェクトを生成する
+    /// ファイルのどこにもないラベルはそれを使った位置のアセンブルエラーにする
+    pub fn generate_standalone(&mut self) -> Result<Vec<ObjectFile>, Casl2AssemblerError> {
+        let objects = self.generate_object()?;
+        for (object, program) in objects.iter().zip(&self.programs) {
+            let undefined = object.externals.iter().find(|e| !objects.iter().any(|o| o.exports.iter().any(|s| s.name == e.name)));
+            if let Some(external) = undefined {
+                let error = Casl2AssemblerError::UnknownLabel(external.name.clone());
+                let addr = self.base.wrapping_add(program.addr).wrapping_add(external.offset);
+                return Err(match self.source_map.lookup(addr) {
+                    Some(entry) => error.at(entry.line, entry.column),
+                    None => error,
+                });
+            }
+        }
+        Ok(objects)
+    }
+
+    /// 実行開始番地が`addr`のプログラムの名前 語のあるプログラムを優先する
+    fn program_entry_name(&self, addr: u16) -> String {
+        self.programs
+            .iter()
+            .filter(|p| !p.name.is_empty() && p.entry == addr)
+            .max_by_key(|p| p.size)
+            .map(|p| p.name.clone())
+            .unwrap_or_default()
+    }
+
+    /// 語とソースマップを生成する
+    fn emit(&mut self) -> Result<Vec<Operand>, Casl2AssemblerError> {
+        self.allocate()?;
+        let mut source_map = SourceMap::new();
+        let file = source_map.add_file(&self.file, self.source.clone());
+        let mut bin = Vec::new();
+        for mem_line in &self.mem_lines {
+            let words = self.encode(mem_line.program, &mem_line.node)?;
+            let columns = self.word_columns(mem_line, words.len());
+            for (i, column) in columns.into_iter().enumerate() {
+                source_map.push(MapEntry {
+                    addr: self.base.wrapping_add(mem_line.addr + i as u16),
+                    file,
+                    line: mem_line.line,
+                    column,
+                    origin: mem_line.origin.clone(),
+                });
+            }
+            bin.extend(words);
+        }
+        self.source_map = source_map;
+        Ok(bin)
+    }
+
+    /// 各行にアドレスを割り当ててラベルを登録する
+    fn allocate(&mut self) -> Result<(), Casl2AssemblerError> {
+        self.label_map.clear();
+        self.programs.clear();
+        self.mem_lines.clear();
+        let mut addr: usize = 0;
+        // 開いているプログラムがあるか
+        let mut open = false;
+        // 各プログラムのSTARTのオペランド
+        let mut start_operands: Vec<String> = Vec::new();
+        // まだ置いていないリテラルと最初に使われた行
+        let mut literals: Vec<(String, usize)> = Vec::new();
+        for (line, node) in self.nodes.iter().enumerate() {
+            match node {
+                ASTNode::START { label, addr: start_addr } => {
+                    if open {
+                        return Err(Casl2AssemblerError::AnalyzeError(format!("START before END, line: {}", line + 1)).at(line, 0));
+                    }
+                    if !label.is_empty() && self.label_map.insert(label.clone(), addr as u16).is_some() {
+                        return Err(Casl2AssemblerError::AnalyzeError(format!("Duplicate label: {}, line: {}", label, line + 1)).at(line, 0));
+                    }
+                    self.programs.push(Program {
+                        name: label.clone(),
+                        start_line: line,
+                        addr: addr as u16,
+                        ..Program::default()
+                    });
+                    start_operands.push(start_addr.clone());
+                    open = true;
+                    continue;
+                }
+                ASTNode::END => {
+                    if open {
+                        // リテラルはENDの位置に置く
+                        addr = Self::close_program(&mut self.programs, &mut self.mem_lines, line, addr, std::mem::take(&mut literals))?;
+                        open = false;
+                    }
+                    continue;
+                }
+                ASTNode::EMPTY => continue,
+                _ => {}
+            }
+            if !open {
+                // STARTの外の命令は名前のないプログラムにする
+                self.programs.push(Program {
+                    start_line: line,
+                    addr: addr as u16,
+                    ..Program::default()
+                });
+                start_operands.push(String::new());
+                open = true;
+            }
+            let program = self.programs.len() - 1;
+
+            let label = match node {
+                ASTNode::Machine1wInstruction { label, .. }
+                | ASTNode::Machine2wInstruction { label, .. }
+                | ASTNode::MacroInstruction { label, .. } => label.clone(),
+                ASTNode::AssemblerInstruction { label, .. } if !label.is_empty() => Some(label.clone()),
+                _ => None,
+            };
+            if let Some(label) = label
+                && self.programs[program].labels.insert(label.clone(), addr as u16).is_some()
+            {
+                return Err(Casl2AssemblerError::AnalyzeError(format!("Duplicate label: {}, line: {}", label, line + 1)).at(line, 0));
+            }
+            if let ASTNode::Machine2wInstruction { addr: operand, .. } = node
+                && operand.starts_with('=')
+                && !literals.iter().any(|(l, _)| l == operand)
+            {
+                literals.push((operand.clone(), line));
+            }
+            let expanded = match node {
+                ASTNode::MacroInstruction { opcode, operands, .. } => Self::expand_macro(opcode, operands)
+                    .into_iter()
+                    .map(|n| (n, Origin::Macro(opcode.clone())))
+                    .collect(),
+                _ => vec![(node.clone(), Origin::Source)],
+            };
+            for (node, origin) in expanded {
+                addr = Self::place(&mut self.mem_lines, addr, line, program, node, origin)?;
+            }
+        }
+        // ENDがなければ最後に置く
+        if open {
+            let last = self.nodes.len().saturating_sub(1);
+            Self::close_program(&mut self.programs, &mut self.mem_lines, last, addr, literals)?;
+        }
+
+        // STARTのオペランドがあればそこから、なければ先頭から実行する
+        for (program, start_addr) in start_operands.iter().enumerate() {
+            let entry = if start_addr.is_empty() {
+                self.programs[program].addr
+            } else {
+                self.resolve(program, start_addr)?
+            };
+            self.programs[program].entry = entry;
+            let name = &self.programs[program].name;
+            if !name.is_empty() {
+                self.label_map.insert(name.clone(), entry);
+            }
+        }
+        self.entry = self.programs.first().map(|p| p.entry).unwrap_or(0);
+        self.start_label = self.programs.first().map(|p| p.name.clone()).filter(|n| !n.is_empty());
+        Ok(())
+    }
+
+    /// 最後のプログラムにリテラルを置いて閉じる 次のアドレスを返す
+    fn close_program(programs: &mut [Program], mem_lines: &mut Vec<MemLine>, end_line: usize, addr: usize, literals: Vec<(String, usize)>) -> Result<usize, Casl2AssemblerError> {
+        let index = programs.len() - 1;
+        let program = &mut programs[index];
+        let addr = Self::place_literals(mem_lines, &mut program.literals, index, addr, literals)?;
+        program.end_line = end_line;
+        program.size = addr - program.addr as usize;
+        Ok(addr)
+    }
+
+    /// 語数のある行を`addr`に置いて次のアドレスを返す
+    fn place(mem_lines: &mut Vec<MemLine>, addr: usize, line: usize, program: usize, node: ASTNode, origin: Origin) -> Result<usize, Casl2AssemblerError> {
+        let size = Self::node_size(&node)? as usize;
+        if size > 0 {
+            mem_lines.push(MemLine {
+                addr: addr as u16,
+                line,
+                program,
+                node,
+                origin,
+            });
+        }
+        if addr + size > 0x10000 {
+            return Err(Casl2AssemblerError::OutOfMemory);
+        }
+        Ok(addr + size)
+    }
+
+    /// リテラルを定数として`addr`から置いて次のアドレスを返す
+    fn place_literals(mem_lines: &mut Vec<MemLine>, literal_map: &mut HashMap<String, u16>, program: usize, mut addr: usize, literals: Vec<(String, usize)>) -> Result<usize, Casl2AssemblerError> {
+        for (literal, line) in literals {
+            let node = ASTNode::AssemblerInstruction {
+                label: String::new(),
+                opcode: assembler_instructions::DC.to_string(),
+                operands: vec![literal[1..].to_string()],
+                comment: None,
+            };
+            literal_map.insert(literal.clone(), addr as u16);
+            addr = Self::place(mem_lines, addr, line, program, node, Origin::Literal(literal))?;
+        }
+        Ok(addr)
+    }
+
+    /// マクロ命令を機械語命令に展開する
+    pub fn expand_macro(opcode: &str, operands: &[String]) -> Vec<ASTNode> {
+        let m1 = |opcode: &str, r1: u8| ASTNode::Machine1wInstruction {
+            label: None,
+            opcode: opcode.to_string(),
+            r1,
+            r2: 0,
+            comment: None,
+        };
+        let m2 = |opcode: &str, r: u8, addr: &str, x: u8| ASTNode::Machine2wInstruction {
+            label: None,
+            opcode: opcode.to_string(),
+            r,
+            x,
+            addr: addr.to_string(),
+            comment: None,
+        };
+        match opcode {
+            assembler_instructions::IN | assembler_instructions::OUT => {
+                // SVC 1 が入力、SVC 2 が出力
+                let svc = if opcode == assembler_instructions::IN { "1" } else { "2" };
+                vec![
+                    m2(assembler_instructions::PUSH, 0, "0", 1),
+                    m2(assembler_instructions::PUSH, 0, "0", 2),
+                    m2(assembler_instructions::LAD, 1, &operands[0], 0),
+                    m2(assembler_instructions::LAD, 2, &operands[1], 0),
+                    m2(assembler_instructions::SVC, 0, svc, 0),
+                    m1(assembler_instructions::POP, 2),
+                    m1(assembler_instructions::POP, 1),
+                ]
+            }
+            assembler_instructions::RPUSH => (1..=7).map(|x| m2(assembler_instructions::PUSH, 0, "0", x)).collect(),
+            assembler_instructions::RPOP => (1..=7).rev().map(|r| m1(assembler_instructions::POP, r)).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// ノードが占める語数
+    pub fn node_size(node: &ASTNode) -> Result<u16, Casl2AssemblerError> {
+        match node {
+            ASTNode::Machine1wInstruction { .. } => Ok(1),
+            ASTNode::Machine2wInstruction { .. } => Ok(2),
+            ASTNode::AssemblerInstruction { opcode, operands, .. } => match opcode.as_str() {
+                assembler_instructions::DC => Ok(operands
+                    .iter()
+                    .map(|o| match Self::string_constant(o) {
+                        Some(s) => s.chars().count() as u16,
+                        None => 1,
+                    })
+                    .sum()),
+                assembler_instructions::DS => operands[0]
+                    .parse::<u16>()
+                    .map_err(|_| Casl2AssemblerError::AnalyzeError(format!("Invalid DS size: {}", operands[0]))),
+                _ => Err(Casl2AssemblerError::InvalidInstruction(opcode.clone())),
+            },
+            ASTNode::MacroInstruction { opcode, operands, .. } => Self::expand_macro(opcode, operands)
+                .iter()
+                .map(Self::node_size)
+                .sum(),
+            ASTNode::START { .. } | ASTNode::END | ASTNode::EMPTY => Ok(0),
+        }
+    }
+
+    /// 1行分の語を生成する
+    fn encode(&self, program: usize, node: &ASTNode) -> Result<Vec<Operand>, Casl2AssemblerError> {
+        match node {
+            ASTNode::Machine1wInstruction { opcode, r1, r2, .. } => {
+                let op = opecode_to_binary(opcode, false);
+                if op == 0xFF {
+                    return Err(Casl2AssemblerError::InvalidInstruction(opcode.clone()));
+                }
+                Ok(vec![Operand::Absolute(((op as u16) << 8) | ((*r1 as u16) << 4) | *r2 as u16)])
+            }
+            ASTNode::Machine2wInstruction { opcode, r, x, addr, .. } => {
+                let op = opecode_to_binary(opcode, true);
+                if op == 0xFF {
+                    return Err(Casl2AssemblerError::InvalidInstruction(opcode.clone()));
+                }
+                Ok(vec![Operand::Absolute(((op as u16) << 8) | ((*r as u16) << 4) | *x as u16), self.operand(program, addr)?])
+            }
+            ASTNode::AssemblerInstruction { opcode, operands, .. } if opcode == assembler_instructions::DC => {
+                let mut words = Vec::new();
+                for operand in operands {
+                    match Self::string_constant(operand) {
+                        Some(s) => {
+                            let codes = jisx0201::encode(&s).map_err(|c| {
+                                Casl2AssemblerError::AnalyzeError(format!("Character not in JIS X 0201: '{}' in {}", c, operand))
+                            })?;
+                            words.extend(codes.into_iter().map(Operand::Absolute));
+                        }
+                        None => words.push(self.operand(program, operand)?),
+                    }
+                }
+                Ok(words)
+            }
+            ASTNode::AssemblerInstruction { .. } => {
+                // DS 領域は0で埋める
+                Ok(vec![Operand::Absolute(0); Self::node_size(node)? as usize])
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// 生成した各語に対応するソース上の列
+    fn word_columns(&self, mem_line: &MemLine, len: usize) -> Vec<usize> {
+        let Some(text) = self.source.get(mem_line.line) else {
+            return vec![0; len];
+        };
+        let fields = split_fields(mem_line.line, text);
+        let opcode = fields.opcode.as_ref().map(|t| t.start).unwrap_or(0);
+        let operand = |text: &str| fields.operands.iter().find(|t| t.text == text).map(|t| t.start).unwrap_or(opcode);
+        match (&mem_line.origin, &mem_line.node) {
+            (Origin::Literal(literal), _) => vec![operand(literal); len],
+            (Origin::Macro(_), _) => vec![opcode; len],
+            (_, ASTNode::Machine2wInstruction { addr, .. }) => vec![opcode, operand(addr)],
+            (_, ASTNode::AssemblerInstruction { opcode: op, .. }) if op == assembler_instructions::DC => {
+                // 文字定数は1文字ずつ列を進める
+                let mut columns = Vec::new();
+                for token in &fields.operands {
+                    match Self::string_constant(&token.text) {
+                        Some(s) => columns.extend((0..s.chars().count()).map(|i| token.start + 1 + i)),
+                        None => columns.push(token.start),
+                    }
+                }
+                columns.resize(len, opcode);
+                columns
+            }
+            (_, ASTNode::AssemblerInstruction { .. }) => {
+                let ds = fields.operands.first().map(|t| t.start).unwrap_or(opcode);
+                vec![ds; len]
+            }
+            _ => vec![opcode; len],
+        }
+    }
+
+    /// 10進定数、16進定数(#FFFF)、ラベル、リテラル(=10)をプログラムの中でアドレス値に解決する
+    pub fn resolve(&self, program: usize, operand: &str) -> Result<u16, Casl2AssemblerError> {
+        match self.operand(program, operand)? {
+            Operand::Absolute(v) | Operand::Relative(v) => Ok(v),
+            Operand::External(label) => Err(Casl2AssemblerError::UnknownLabel(label)),
+        }
+    }
+
+    /// オペランドが定数か、ラベルやリテラルの番地か、外部のラベルかを調べる
+    pub fn operand(&self, program: usize, operand: &str) -> Result<Operand, Casl2AssemblerError> {
+        let program = &self.programs[program];
+        if operand.starts_with('=') {
+            return program
+                .literals
+                .get(operand)
+                .map(|&addr| Operand::Relative(addr))
+                .ok_or_else(|| Casl2AssemblerError::UnknownLabel(operand.to_string()));
+        }
+        if let Some(hex) = operand.strip_prefix('#') {
+            return u16::from_str_radix(hex, 16)
+                .map(Operand::Absolute)
+                .map_err(|_| Casl2AssemblerError::AnalyzeError(format!("Invalid hex constant: {}", operand)));
+        }
+        if let Ok(val) = operand.parse::<i32>() {
+            if (-32768..=65535).contains(&val) {
+                return Ok(Operand::Absolute(val as u16));
+            }
+            return Err(Casl2AssemblerError::AnalyzeError(format!("Constant out of range: {}", operand)));
+        }
+        // プログラム内のラベルが優先
+        Ok(match program.labels.get(operand).or_else(|| self.label_map.get(operand)) {
+            Some(&addr) => Operand::Relative(addr),
+            None => Operand::External(operand.to_string()),
+        })
+    }
+
+    /// STARTのラベルとプログラム内のラベルの一覧 番地は`base`から
+    pub fn symbols(&self) -> Vec<(String, u16)> {
+        let globals = self.label_map.iter();
+        let locals = self.programs.iter().flat_map(|p| p.labels.iter());
+        globals.chain(locals).map(|(name, &addr)| (name.clone(), self.base.wrapping_add(addr))).collect()
+    }
+
+    /// ラベルのアドレス `PROG.LABEL`ならそのプログラムのラベル
+    /// 名前だけならSTARTのラベル、なければ最初に見つかったプログラムのラベル
+    /// 番地は`base`から
+    pub fn lookup(&self, name: &str) -> Option<u16> {
+        let addr = match name.split_once('.') {
+            Some((prog, label)) => self.programs.iter().find(|p| p.name == prog)?.labels.get(label),
+            None => self.label_map.get(name).or_else(|| self.programs.iter().find_map(|p| p.labels.get(name))),
+        };
+        addr.map(|&addr| self.base.wrapping_add(addr))
+    }
+
+    /// アドレスをいちばん近い手前のラベルからの相対で表す
+    /// 例: `DIVIDE+3`
+    pub fn symbolize(&self, addr: u16) -> String {
+        let symbols = self.symbols();
+        let label = symbols
+            .iter()
+            .filter(|(_, a)| *a <= addr)
+            .max_by_key(|(name, a)| (*a, std::cmp::Reverse(name.len())));
+        match label {
+            Some((name, a)) if *a == addr => name.clone(),
+            Some((name, a)) => format!("{}+{}", name, addr - a),
+            None => format!("#{:04X}", addr),
+        }
+    }
+
+    /// 命令を読み出し専用、DCやDSとプログラムの外を実行禁止にした保護
+    /// 自分の命令を書き換えたりデータを実行したりすると止まる
+    pub fn protection(&self) -> MemoryProtection {
+        let mut protection = MemoryProtection::new();
+        let mut next = 0u32;
+        for mem_line in &self.mem_lines {
+            let size = Self::node_size(&mem_line.node).unwrap_or(0) as u32;
+            if size == 0 {
+                continue;
+            }
+            let start = self.base.wrapping_add(mem_line.addr);
+            let end = (start as u32 + size - 1) as u16;
+            // マクロ命令も命令に展開される
+            let code = !matches!(mem_line.node, ASTNode::AssemblerInstruction { .. });
+            if (start as u32) > next {
+                protection.regions.push(Region::new(next as u16, start - 1).no_execute());
+            }
+            // 同じ種類が続けばまとめる
+            match protection.regions.last_mut() {
+                Some(last) if last.end as u32 + 1 == start as u32 && last.read_only == code => last.end = end,
+                _ if code => protection.regions.push(Region::new(start, end).read_only()),
+                _ => protection.regions.push(Region::new(start, end).no_execute()),
+            }
+            next = end as u32 + 1;
+        }
+        if next <= 0xFFFF {
+            match protection.regions.last_mut() {
+                Some(last) if !last.read_only => last.end = 0xFFFF,
+                _ => protection.regions.push(Region::new(next as u16, 0xFFFF).no_execute()),
+            }
+        }
+        protection
+    }
+
+    /// 命令とDCを初期化済みにした影 DSとプログラムの外とGRは未初期化
+    pub fn shadow(&self) -> ShadowMemory {
+        let mut shadow = ShadowMemory::new();
+        for mem_line in &self.mem_lines {
+            let ds = matches!(&mem_line.node, ASTNode::AssemblerInstruction { opcode, .. } if opcode == assembler_instructions::DS);
+            if !ds {
+                shadow.define_range(self.base.wrapping_add(mem_line.addr), Self::node_size(&mem_line.node).unwrap_or(0));
+            }
+        }
+        shadow
+    }
+
+    /// 行を含むプログラム
+    pub fn program_at(&self, line: usize) -> Option<&Program> {
+        self.programs.iter().find(|p| p.start_line <= line && line <= p.end_line)
+    }
+
+    /// 'ABC' 形式の文字定数なら中身を返す
+    pub fn string_constant(operand: &str) -> Option<String> {
+        let inner = operand.strip_prefix('\'')?.strip_suffix('\'')?;
+        Some(inner.replace("''", "'"))
+    }
 }
 
 pub struct Routine {
@@ -0,0 +1,38 @@
+use crate::emurator::{casl2::{code_gen::CodeGenerator, err::Casl2AssemblerError, prefix::GR_LIST}, commet2::convention::{Clobbered, ConventionChecker}};
+
+/// `LABEL=GR1,GR2`を読んで、そのルーチンで変えてよいGRを足す
+pub fn allow(code_gen: &CodeGenerator, checker: ConventionChecker, spec: &str) -> Result<ConventionChecker, Casl2AssemblerError> {
+    let (label, registers) = spec.split_once('=').unwrap_or((spec, ""));
+    let routine = code_gen.lookup(label).ok_or_else(|| Casl2AssemblerError::UnknownLabel(label.to_string()))?;
+    let registers = registers
+        .split(',')
+        .filter(|r| !r.is_empty())
+        .map(|r| {
+            GR_LIST
+                .iter()
+                .position(|gr| gr.eq_ignore_ascii_case(r.trim()))
+                .map(|n| n as u8)
+                .ok_or_else(|| Casl2AssemblerError::ParseError(format!("{}: not a register", r)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(checker.allow(routine, &registers))
+}
+
+/// 規約の違反をソースの行で説明する
+///
+/// ```text
+/// line 9: RET: DIVIDE clobbers GR2 (#0003 -> #0000)
+///     called from line 3: CALL DIVIDE
+/// ```
+pub fn describe(code_gen: &CodeGenerator, clobbered: &Clobbered) -> String {
+    let line = |addr: u16| code_gen.source_map.describe(addr).unwrap_or_else(|| format!("#{:04X}", addr));
+    format!(
+        "{}: {} clobbers GR{} (#{:04X} -> #{:04X})\n    called from {}\n",
+        line(clobbered.ret),
+        code_gen.symbolize(clobbered.routine),
+        clobbered.register,
+        clobbered.before,
+        clobbered.after,
+        line(clobbered.call_site)
+    )
+}
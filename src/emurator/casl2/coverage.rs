@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use crate::emurator::{casl2::{code_gen::CodeGenerator, parser::ASTNode}, commet2::coverage::Coverage};
+
+/// 条件分岐の命令
+pub const CONDITIONAL_JUMPS: [&str; 5] = ["JMI", "JNZ", "JZE", "JPL", "JOV"];
+
+/// 条件分岐1つの結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchCoverage {
+    /// ソースの行 (0始まり)
+    pub line: usize,
+    pub addr: u16,
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// カバレッジをソースの行に対応させたもの
+#[derive(Debug, Clone)]
+pub struct CoverageReport {
+    pub file: String,
+    pub source: Vec<String>,
+    /// 命令のある行 (0始まり) と実行した回数
+    /// マクロ命令の行は展開した命令のうちいちばん多く実行したもの
+    pub lines: BTreeMap<usize, u64>,
+    pub branches: Vec<BranchCoverage>,
+}
+
+impl CoverageReport {
+    pub fn new(code_gen: &CodeGenerator, coverage: &Coverage) -> Self {
+        let mut lines = BTreeMap::new();
+        let mut branches = vec![];
+        for mem_line in code_gen.mem_lines.iter().filter(|m| m.node.is_instruction()) {
+            let addr = code_gen.base.wrapping_add(mem_line.addr);
+            let hits = coverage.hits(addr);
+            let entry = lines.entry(mem_line.line).or_insert(0);
+            *entry = hits.max(*entry);
+            if let ASTNode::Machine2wInstruction { opcode, .. } = &mem_line.node
+                && CONDITIONAL_JUMPS.contains(&opcode.as_str())
+            {
+                let (taken, not_taken) = coverage.branches.get(&addr).copied().unwrap_or_default();
+                branches.push(BranchCoverage { line: mem_line.line, addr, taken, not_taken });
+            }
+        }
+        CoverageReport { file: code_gen.file.clone(), source: code_gen.source.clone(), lines, branches }
+    }
+
+    /// (実行した行, 命令のある行)
+    pub fn line_summary(&self) -> (usize, usize) {
+        (self.lines.values().filter(|&&n| n > 0).count(), self.lines.len())
+    }
+
+    /// (通った分岐, 分岐の数) 条件分岐1つにつき分岐するとしないの2つ
+    pub fn branch_summary(&self) -> (usize, usize) {
+        let hit = self.branches.iter().map(|b| (b.taken > 0) as usize + (b.not_taken > 0) as usize).sum();
+        (hit, self.branches.len() * 2)
+    }
+
+    /// `lines: 8/10 (80.0%), branches: 3/4 (75.0%)`
+    pub fn summary(&self) -> String {
+        let percent = |(hit, found): (usize, usize)| {
+            let rate = if found == 0 { 100.0 } else { hit as f64 * 100.0 / found as f64 };
+            format!("{}/{} ({:.1}%)", hit, found, rate)
+        };
+        format!("lines: {}, branches: {}", percent(self.line_summary()), percent(self.branch_summary()))
+    }
+
+    fn branches_on(&self, line: usize) -> impl Iterator<Item = &BranchCoverage> {
+        self.branches.iter().filter(move |b| b.line == line)
+    }
+
+    /// gcovのような注釈付きのリスト
+    /// 行頭は実行した回数 `#####`は実行していない命令、`-`は命令のない行
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+        for (i, text) in self.source.iter().enumerate() {
+            let count = match self.lines.get(&i) {
+                Some(0) => "#####".to_string(),
+                Some(n) => n.to_string(),
+                None => "-".to_string(),
+            };
+            out += &format!("{:>9}:{:>5}:{}\n", count, i + 1, text);
+            for b in self.branches_on(i) {
+                out += &format!("{:>9}  branch taken {}, not taken {}\n", "", b.taken, b.not_taken);
+            }
+        }
+        out += &self.summary();
+        out.push('\n');
+        out
+    }
+
+    /// lcovのトレースファイル
+    pub fn lcov(&self) -> String {
+        let mut out = format!("TN:\nSF:{}\n", self.file);
+        for (i, b) in self.branches.iter().enumerate() {
+            // 実行していない行の分岐は`-`
+            let executed = self.lines.get(&b.line).is_some_and(|&n| n > 0);
+            for (branch, count) in [b.taken, b.not_taken].into_iter().enumerate() {
+                let count = if executed { count.to_string() } else { "-".to_string() };
+                out += &format!("BRDA:{},{},{},{}\n", b.line + 1, i, branch, count);
+            }
+        }
+        let (branch_hit, branch_found) = self.branch_summary();
+        out += &format!("BRF:{}\nBRH:{}\n", branch_found, branch_hit);
+        for (&line, &hits) in &self.lines {
+            out += &format!("DA:{},{}\n", line + 1, hits);
+        }
+        let (line_hit, line_found) = self.line_summary();
+        out += &format!("LF:{}\nLH:{}\nend_of_record\n", line_found, line_hit);
+        out
+    }
+
+    /// 1ファイルのHTML 実行した行は緑、実行していない行は赤、分岐の片方だけ通った行は黄色
+    pub fn html(&self) -> String {
+        let title = if self.file.is_empty() { "coverage".to_string() } else { escape(&self.file) };
+        let mut out = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n\
+             body {{ font-family: monospace; }}\n\
+             table {{ border-collapse: collapse; }}\n\
+             td {{ padding: 0 8px; white-space: pre; tab-size: 8; }}\n\
+             .hit {{ background: #d4f8d4; }}\n\
+             .miss {{ background: #f8d4d4; }}\n\
+             .partial {{ background: #f8f0c0; }}\n\
+             .count, .line {{ text-align: right; color: #666; }}\n\
+             </style>\n</head>\n<body>\n<h1>{}</h1>\n<p>{}</p>\n<table>\n",
+            title,
+            title,
+            escape(&self.summary())
+        );
+        for (i, text) in self.source.iter().enumerate() {
+            let branches: Vec<&BranchCoverage> = self.branches_on(i).collect();
+            let (class, count) = match self.lines.get(&i) {
+                Some(0) => ("miss", "0".to_string()),
+                Some(n) if branches.iter().any(|b| b.taken == 0 || b.not_taken == 0) => ("partial", n.to_string()),
+                Some(n) => ("hit", n.to_string()),
+                None => ("", String::new()),
+            };
+            let note = branches
+                .iter()
+                .map(|b| format!("taken {}, not taken {}", b.taken, b.not_taken))
+                .collect::<Vec<_>>()
+                .join("; ");
+            out += &format!(
+                "<tr class=\"{}\"><td class=\"line\">{}</td><td class=\"count\">{}</td><td>{}</td><td>{}</td></tr>\n",
+                class,
+                i + 1,
+                count,
+                escape(text),
+                escape(&note)
+            );
+        }
+        out += "</table>\n</body>\n</html>\n";
+        out
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -9,6 +9,10 @@ pub enum Casl2AssemblerError {
     OutOfMemory,
     UnknownLabel(String),
     RuntimeError(String),
+    LintError(String),
+    LinkError(String),
+    /// ソース上の位置がわかっているエラー 行と列(UTF-16)は0始まり
+    At { line: usize, column: usize, error: Box<Casl2AssemblerError> },
 }
 
 impl fmt::Display for Casl2AssemblerError {
@@ -21,6 +25,27 @@ impl fmt::Display for Casl2AssemblerError {
             Casl2AssemblerError::OutOfMemory => write!(f, "Out of memory"),
             Casl2AssemblerError::UnknownLabel(label) => write!(f, "Unknown label: {}", label),
             Casl2AssemblerError::RuntimeError(msg) => write!(f, "Runtime error: {}", msg),
+            Casl2AssemblerError::LintError(msg) => write!(f, "Lint error: {}", msg),
+            Casl2AssemblerError::LinkError(msg) => write!(f, "Link error: {}", msg),
+            Casl2AssemblerError::At { error, .. } => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Casl2AssemblerError {
+    /// ソース上の位置をつける すでについていればそのまま
+    pub fn at(self, line: usize, column: usize) -> Self {
+        match self {
+            Casl2AssemblerError::At { .. } => self,
+            error => Casl2AssemblerError::At { line, column, error: Box::new(error) },
+        }
+    }
+
+    /// 位置を除いたエラー
+    pub fn inner(&self) -> &Self {
+        match self {
+            Casl2AssemblerError::At { error, .. } => error.inner(),
+            error => error,
         }
     }
 }
@@ -0,0 +1,131 @@
+use crate::emurator::casl2::{err::Casl2AssemblerError, lexer::{split_fields, LineFields}, parser::ASTNode, prefix::GR_LIST};
+
+/// タブ幅
+pub const TAB_WIDTH: usize = 8;
+/// 命令欄の列 ラベルは8文字まで
+pub const OPCODE_COLUMN: usize = 8;
+/// オペランド欄の列
+pub const OPERAND_COLUMN: usize = 16;
+/// コメント欄の列
+pub const COMMENT_COLUMN: usize = 32;
+
+/// 整形されていない行
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unformatted {
+    /// 行 (0始まり)
+    pub line: usize,
+    pub original: String,
+    pub formatted: String,
+}
+
+/// 列がcolumnに届くまでタブを足す 最低1つは入れる
+fn pad_to(out: &mut String, width: usize, column: usize) -> usize {
+    let mut width = width;
+    loop {
+        out.push('\t');
+        width = (width / TAB_WIDTH + 1) * TAB_WIDTH;
+        if width >= column {
+            return width;
+        }
+    }
+}
+
+/// オペランドを1つ正規化する
+/// レジスタと16進定数は大文字、文字定数はそのまま
+fn normalize_operand(operand: &str) -> String {
+    if operand.starts_with('\'') {
+        return operand.to_string();
+    }
+    let upper = operand.to_uppercase();
+    if GR_LIST.contains(&upper.as_str()) || operand.starts_with('#') {
+        upper
+    } else {
+        operand.to_string()
+    }
+}
+
+/// 欄に分けた1行を決まった列に並べる
+/// 前の欄が次の欄の列に届いていたらタブ1つで次のタブ位置に置く
+/// 8文字のラベルなら命令は16列、オペランドは24列になり、コメントは32列に届かなければ32列のまま
+fn format_fields(line: &str, fields: &LineFields) -> String {
+    let mut out = String::new();
+    let comment = fields.comment.as_ref().map(|c| {
+        let text = c.text.trim_start_matches(';').trim();
+        if text.is_empty() { ";".to_string() } else { format!("; {}", text) }
+    });
+
+    let Some(opcode) = &fields.opcode else {
+        // コメントだけの行 字下げされていれば命令欄にそろえる
+        if let Some(comment) = comment {
+            if line.starts_with(char::is_whitespace) {
+                pad_to(&mut out, 0, OPCODE_COLUMN);
+            }
+            out.push_str(&comment);
+        }
+        return out;
+    };
+
+    let mut width = 0;
+    if let Some(label) = &fields.label {
+        out.push_str(&label.text);
+        width = label.text.chars().count();
+    }
+    width = pad_to(&mut out, width, OPCODE_COLUMN);
+    let opcode = opcode.text.to_uppercase();
+    out.push_str(&opcode);
+    width += opcode.len();
+
+    if !fields.operands.is_empty() {
+        let operands: Vec<String> = fields.operands.iter().map(|t| normalize_operand(&t.text)).collect();
+        let operands = operands.join(",");
+        width = pad_to(&mut out, width, OPERAND_COLUMN);
+        out.push_str(&operands);
+        width += operands.chars().count();
+    }
+    if let Some(comment) = comment {
+        pad_to(&mut out, width, COMMENT_COLUMN);
+        out.push_str(&comment);
+    }
+    out
+}
+
+/// ソースを整形する
+/// 整形した行を`ASTNode::analyze`で解析しなおすので、解析できないソースはエラーになる
+/// ラベルだけの行も消さずにエラーにする
+pub fn format_source(src: &str) -> Result<String, Casl2AssemblerError> {
+    let mut out = String::new();
+    for (i, line) in src.lines().enumerate() {
+        let fields = split_fields(i, line);
+        if fields.label.is_some() && fields.opcode.is_none() {
+            return Err(Casl2AssemblerError::AnalyzeError(format!("Label without instruction, line: {}\n\t{}", i + 1, line)).at(i, 0));
+        }
+        let formatted = format_fields(line, &fields);
+        ASTNode::analyze(i, &formatted).map_err(|e| e.at(i, fields.opcode.as_ref().map_or(0, |t| t.start)))?;
+        out.push_str(&formatted);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// 整形されていない行を返す 空なら整形済み
+pub fn check(src: &str) -> Result<Vec<Unformatted>, Casl2AssemblerError> {
+    let formatted = format_source(src)?;
+    let mut unformatted: Vec<Unformatted> = src
+        .lines()
+        .zip(formatted.lines())
+        .enumerate()
+        .filter(|(_, (a, b))| a != b)
+        .map(|(line, (a, b))| Unformatted {
+            line,
+            original: a.to_string(),
+            formatted: b.to_string(),
+        })
+        .collect();
+    // 末尾の改行がない
+    if unformatted.is_empty() && !src.is_empty() && !src.ends_with('\n') {
+        let line = src.lines().count() - 1;
+        let last = src.lines().last().unwrap_or_default().to_string();
+        unformatted.push(Unformatted { line, original: last.clone(), formatted: last });
+    }
+    Ok(unformatted)
+}
@@ -0,0 +1,107 @@
+use crate::emurator::casl2::prefix::GR_LIST;
+
+/// 行の中の1語 列はUTF-16単位
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub text: String,
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Token {
+    pub fn contains(&self, line: usize, character: usize) -> bool {
+        self.line == line && self.start <= character && character <= self.end
+    }
+}
+
+/// 1行をラベル、命令、オペランド、コメントの欄に分けたもの
+#[derive(Debug, Default)]
+pub struct LineFields {
+    pub label: Option<Token>,
+    pub opcode: Option<Token>,
+    pub operands: Vec<Token>,
+    pub comment: Option<Token>,
+}
+
+/// ラベルとして参照されうるオペランドか
+pub fn is_label_operand(text: &str) -> bool {
+    text.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && !GR_LIST.contains(&text)
+}
+
+/// UTF-16での長さ
+pub fn utf16_len(s: &str) -> usize {
+    s.chars().map(char::len_utf16).sum()
+}
+
+/// 文字定数の外にある`;`の位置 (バイト単位)
+pub fn comment_start(line: &str) -> Option<usize> {
+    let mut in_quote = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\'' => in_quote = !in_quote,
+            ';' if !in_quote => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// 行を欄に分ける 文字定数の中の`,`と`;`は区切りにしない
+pub fn split_fields(line_no: usize, line: &str) -> LineFields {
+    let token = |byte: usize, text: &str| Token {
+        text: text.to_string(),
+        line: line_no,
+        start: utf16_len(&line[..byte]),
+        end: utf16_len(&line[..byte]) + utf16_len(text),
+    };
+    let mut fields = LineFields::default();
+
+    // コメント
+    let body_end = comment_start(line).unwrap_or(line.len());
+    if body_end < line.len() {
+        fields.comment = Some(token(body_end, &line[body_end..]));
+    }
+    let body = &line[..body_end];
+
+    // 空白で区切られた次の語の (開始, 終了)
+    let next_word = |from: usize| -> Option<(usize, usize)> {
+        let start = from + body[from..].find(|c: char| !c.is_whitespace())?;
+        let end = body[start..].find(char::is_whitespace).map(|e| start + e).unwrap_or(body.len());
+        Some((start, end))
+    };
+
+    // ラベルは1桁目から
+    let mut pos = 0;
+    if body.starts_with(|c: char| !c.is_whitespace())
+        && let Some((start, end)) = next_word(0)
+    {
+        fields.label = Some(token(start, &body[start..end]));
+        pos = end;
+    }
+    let Some((start, end)) = next_word(pos) else {
+        return fields;
+    };
+    fields.opcode = Some(token(start, &body[start..end]));
+
+    // オペランドは`,`で区切る
+    let mut in_quote = false;
+    let mut operand_start = end;
+    for (i, c) in body[end..].char_indices().map(|(i, c)| (end + i, c)).chain(std::iter::once((body.len(), ','))) {
+        match c {
+            '\'' => in_quote = !in_quote,
+            ',' if !in_quote || i == body.len() => {
+                let raw = &body[operand_start..i];
+                let trimmed = raw.trim();
+                if !trimmed.is_empty() {
+                    let offset = operand_start + (raw.len() - raw.trim_start().len());
+                    fields.operands.push(token(offset, trimmed));
+                }
+                operand_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    fields
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+
+use crate::emurator::casl2::{err::Casl2AssemblerError, object::ObjectFile};
+
+/// リンクした結果
+#[derive(Debug, Clone)]
+pub struct LinkedImage {
+    /// 配置した先頭番地
+    pub base: u16,
+    /// baseから置く語
+    pub code: Vec<u16>,
+    /// 最初のオブジェクトの実行開始番地
+    pub entry: u16,
+    /// 公開ラベルの番地
+    pub symbols: HashMap<String, u16>,
+    /// 各オブジェクトの名前と先頭番地
+    pub units: Vec<(String, u16)>,
+}
+
+/// オブジェクトをbaseから順に並べて外部参照と再配置を解決する
+/// 最初のオブジェクトがメインプログラムになる
+pub fn link(objects: &[ObjectFile], base: u16) -> Result<LinkedImage, Casl2AssemblerError> {
+    // 配置と公開ラベル
+    let mut units = Vec::new();
+    let mut symbols: HashMap<String, u16> = HashMap::new();
+    let mut owner: HashMap<String, &str> = HashMap::new();
+    let mut addr = base as usize;
+    for object in objects {
+        if addr + object.code.len() > 0x10000 {
+            return Err(Casl2AssemblerError::OutOfMemory);
+        }
+        for export in &object.exports {
+            if let Some(prev) = owner.insert(export.name.clone(), &object.name) {
+                return Err(Casl2AssemblerError::LinkError(format!("Duplicate symbol: {} (defined in {} and {})", export.name, prev, object.name)));
+            }
+            symbols.insert(export.name.clone(), (addr + export.offset as usize) as u16);
+        }
+        units.push((object.name.clone(), addr as u16));
+        addr += object.code.len();
+    }
+
+    // 再配置と外部参照
+    let mut code = Vec::with_capacity(addr - base as usize);
+    let mut missing = Vec::new();
+    for (object, &(_, unit_base)) in objects.iter().zip(units.iter()) {
+        let mut words = object.code.clone();
+        for &offset in &object.relocations {
+            let word = &mut words[offset as usize];
+            *word = word.wrapping_add(unit_base);
+        }
+        for external in &object.externals {
+            match symbols.get(&external.name) {
+                Some(&target) => words[external.offset as usize] = target,
+                None => missing.push(format!("{} (referenced from {})", external.name, object.name)),
+            }
+        }
+        code.extend(words);
+    }
+    if !missing.is_empty() {
+        return Err(Casl2AssemblerError::LinkError(format!("Missing symbol: {}", missing.join(", "))));
+    }
+
+    let entry = match (objects.first(), units.first()) {
+        (Some(object), Some(&(_, unit_base))) => unit_base.wrapping_add(object.entry),
+        _ => base,
+    };
+    Ok(LinkedImage {
+        base,
+        code,
+        entry,
+        symbols,
+        units,
+    })
+}
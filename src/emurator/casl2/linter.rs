@@ -0,0 +1,319 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::emurator::casl2::{err::Casl2AssemblerError, lexer::{is_label_operand, split_fields, LineFields}, parser::ASTNode, prefix::assembler_instructions as inst};
+
+/// リントの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// 定義したが使っていないラベル
+    UnusedLabel,
+    /// 定義していないラベルの参照
+    UndefinedLabel,
+    /// JUMPやRETの後ろでラベルのない命令
+    UnreachableCode,
+    /// GR0を指標レジスタに書いている (指標なしになる)
+    Gr0Index,
+    /// DCで定義した定数への書き込み
+    WriteToConstant,
+    /// RETせずに END や次のルーチンに落ちる
+    MissingRet,
+    /// 直線的なコードでPUSHとPOPが釣り合わない
+    UnbalancedStack,
+    /// 命令の流れがそのままDC/DSに入る
+    ReachableData,
+}
+
+pub const ALL_LINTS: [Lint; 8] = [
+    Lint::UnusedLabel,
+    Lint::UndefinedLabel,
+    Lint::UnreachableCode,
+    Lint::Gr0Index,
+    Lint::WriteToConstant,
+    Lint::MissingRet,
+    Lint::UnbalancedStack,
+    Lint::ReachableData,
+];
+
+impl Lint {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedLabel => "unused-label",
+            Lint::UndefinedLabel => "undefined-label",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::Gr0Index => "gr0-index",
+            Lint::WriteToConstant => "write-to-constant",
+            Lint::MissingRet => "missing-ret",
+            Lint::UnbalancedStack => "unbalanced-stack",
+            Lint::ReachableData => "reachable-data",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        ALL_LINTS.into_iter().find(|l| l.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintLevel {
+    /// 報告しない
+    Allow,
+    /// 警告として返す
+    Warn,
+    /// エラーにする
+    Deny,
+}
+
+/// リントごとの報告レベル 指定がなければWarn
+#[derive(Debug, Clone, Default)]
+pub struct LintConfig {
+    levels: HashMap<Lint, LintLevel>,
+}
+
+impl LintConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, lint: Lint, level: LintLevel) -> &mut Self {
+        self.levels.insert(lint, level);
+        self
+    }
+
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        self.levels.get(&lint).copied().unwrap_or(LintLevel::Warn)
+    }
+}
+
+/// 見つかった問題
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintWarning {
+    pub lint: Lint,
+    pub level: LintLevel,
+    /// 行 (0始まり)
+    pub line: usize,
+    /// 桁 (0始まり UTF-16) 行全体の警告は0
+    pub column: usize,
+    pub message: String,
+}
+
+impl LintWarning {
+    /// `メッセージ [リント名], line: N` の形
+    pub fn describe(&self) -> String {
+        format!("{} [{}], line: {}", self.message, self.lint.name(), self.line + 1)
+    }
+}
+
+impl From<LintWarning> for Casl2AssemblerError {
+    fn from(w: LintWarning) -> Self {
+        Casl2AssemblerError::LintError(w.describe())
+    }
+}
+
+fn opcode(node: &ASTNode) -> Option<&str> {
+    match node {
+        ASTNode::Machine1wInstruction { opcode, .. }
+        | ASTNode::Machine2wInstruction { opcode, .. }
+        | ASTNode::MacroInstruction { opcode, .. } => Some(opcode),
+        _ => None,
+    }
+}
+
+/// 後ろに流れない命令か
+fn is_unconditional(node: &ASTNode) -> bool {
+    matches!(opcode(node), Some(inst::JUMP | inst::RET))
+}
+
+fn is_data(node: &ASTNode) -> bool {
+    matches!(node, ASTNode::AssemblerInstruction { opcode, .. } if opcode == inst::DC || opcode == inst::DS)
+}
+
+/// スタックに積む語数の増減
+fn stack_effect(node: &ASTNode) -> i32 {
+    match opcode(node) {
+        Some(inst::PUSH) => 1,
+        Some(inst::POP) => -1,
+        Some(inst::RPUSH) => 7,
+        Some(inst::RPOP) => -7,
+        _ => 0,
+    }
+}
+
+/// ソースを解析してリントをかける
+/// Denyのリントが見つかればエラー、それ以外は警告を返す
+pub fn lint(src: &str, config: &LintConfig) -> Result<Vec<LintWarning>, Casl2AssemblerError> {
+    let nodes = ASTNode::de(src)?;
+    let fields: Vec<LineFields> = src.lines().enumerate().map(|(i, l)| split_fields(i, l)).collect();
+    let mut found: Vec<(Lint, usize, usize, String)> = Vec::new();
+
+    // ラベルの定義と参照
+    // STARTのラベルは(None, 名前)、プログラム内のラベルは(Some(プログラム), 名前)で持つ
+    let mut scopes: Vec<Option<usize>> = Vec::new();
+    let mut programs: usize = 0;
+    for node in &nodes {
+        if let ASTNode::START { .. } = node {
+            programs += 1;
+        }
+        scopes.push(programs.checked_sub(1));
+    }
+    type Key = (Option<usize>, String);
+    let mut defined: HashMap<Key, usize> = HashMap::new();
+    let mut constants: HashSet<Key> = HashSet::new();
+    for (i, node) in nodes.iter().enumerate() {
+        let Some(label) = &fields[i].label else { continue };
+        let scope = if matches!(node, ASTNode::START { .. }) { None } else { scopes[i] };
+        defined.entry((scope, label.text.clone())).or_insert(i);
+        if matches!(node, ASTNode::AssemblerInstruction { opcode, .. } if opcode == inst::DC) {
+            constants.insert((scope, label.text.clone()));
+        }
+    }
+    // プログラム内のラベルが優先
+    let resolve = |line: usize, name: &str| -> Option<Key> {
+        [(scopes[line], name.to_string()), (None, name.to_string())].into_iter().find(|k| defined.contains_key(k))
+    };
+    let mut entries: HashSet<Key> = HashSet::new();
+    let mut referenced: HashSet<Key> = HashSet::new();
+    for (i, node) in nodes.iter().enumerate() {
+        match node {
+            ASTNode::START { label, addr } => {
+                // STARTのラベルは外から呼ばれる
+                entries.insert((None, label.clone()));
+                referenced.insert((None, label.clone()));
+                if let Some(key) = resolve(i, addr) {
+                    entries.insert(key);
+                }
+            }
+            ASTNode::Machine2wInstruction { opcode, addr, .. } if opcode == inst::CALL => {
+                entries.extend(resolve(i, addr));
+            }
+            ASTNode::EMPTY | ASTNode::END => continue,
+            _ => {}
+        }
+        for token in fields[i].operands.iter().filter(|t| is_label_operand(&t.text)) {
+            match resolve(i, &token.text) {
+                Some(key) => {
+                    referenced.insert(key);
+                }
+                None => found.push((Lint::UndefinedLabel, i, token.start, format!("Undefined label: {}", token.text))),
+            }
+        }
+    }
+    let mut unused: Vec<(&str, usize)> = defined.iter().filter(|(k, _)| !referenced.contains(*k)).map(|((_, l), i)| (l.as_str(), *i)).collect();
+    unused.sort_by_key(|(_, i)| *i);
+    for (label, i) in unused {
+        found.push((Lint::UnusedLabel, i, 0, format!("Unused label: {}", label)));
+    }
+
+    // 命令ごとの形
+    for (i, node) in nodes.iter().enumerate() {
+        let operands = &fields[i].operands;
+        if matches!(node, ASTNode::Machine2wInstruction { .. })
+            && operands.len() >= 2
+            && let Some(index) = operands.last().filter(|t| t.text == "GR0")
+        {
+            found.push((Lint::Gr0Index, i, index.start, "GR0 cannot be used as an index register (it means no index)".to_string()));
+        }
+        let written: Vec<&str> = match node {
+            ASTNode::Machine2wInstruction { opcode, addr, .. } if opcode == inst::ST => vec![addr],
+            ASTNode::MacroInstruction { opcode, operands, .. } if opcode == inst::IN => operands.iter().map(|o| o.as_str()).collect(),
+            _ => Vec::new(),
+        };
+        for target in written.into_iter().filter(|t| resolve(i, t).is_some_and(|k| constants.contains(&k))) {
+            let column = operands.iter().find(|t| t.text == target).map_or(0, |t| t.start);
+            found.push((Lint::WriteToConstant, i, column, format!("Write into constant defined by DC: {}", target)));
+        }
+    }
+
+    // 命令の流れ
+    // falls_through: 前の命令からそのまま流れてくる
+    // depth: ルーチンの入口からのスタックの深さ 合流点ではわからない
+    let mut routine: Option<&str> = None;
+    let is_entry = |line: usize, label: &str| resolve(line, label).is_some_and(|k| entries.contains(&k));
+    let mut falls_through = false;
+    let mut reported_unreachable = false;
+    let mut depth: Option<i32> = None;
+    for (i, node) in nodes.iter().enumerate() {
+        let label = fields[i].label.as_ref().map(|t| t.text.as_str());
+        match node {
+            ASTNode::EMPTY => continue,
+            ASTNode::START { label, addr } => {
+                routine = Some(label);
+                falls_through = addr.is_empty();
+                depth = falls_through.then_some(0);
+                continue;
+            }
+            ASTNode::END => {
+                if falls_through {
+                    let name = routine.unwrap_or_default();
+                    found.push((Lint::MissingRet, i, 0, format!("{} falls off the end without RET", name)));
+                }
+                falls_through = false;
+                continue;
+            }
+            _ => {}
+        }
+        if is_data(node) {
+            if falls_through {
+                found.push((Lint::ReachableData, i, 0, "Data can be reached by falling through from the previous instruction".to_string()));
+            }
+            falls_through = false;
+            continue;
+        }
+        if opcode(node).is_none() {
+            continue;
+        }
+
+        // ルーチンの入口
+        if let Some(label) = label
+            && is_entry(i, label)
+        {
+            if falls_through && routine != Some(label) {
+                let name = routine.unwrap_or_default();
+                found.push((Lint::MissingRet, i, 0, format!("{} falls into {} without RET", name, label)));
+            }
+            routine = Some(label);
+            depth = Some(0);
+        } else if label.is_some() {
+            depth = None;
+        }
+
+        let reachable = falls_through || label.is_some();
+        if !reachable && !reported_unreachable {
+            found.push((Lint::UnreachableCode, i, 0, "Unreachable code".to_string()));
+        }
+        reported_unreachable = !reachable;
+
+        if let Some(d) = depth {
+            let d = d + stack_effect(node);
+            if d < 0 {
+                found.push((Lint::UnbalancedStack, i, 0, "POP without matching PUSH".to_string()));
+                depth = None;
+            } else if opcode(node) == Some(inst::RET) && d != 0 {
+                found.push((Lint::UnbalancedStack, i, 0, format!("RET with {} word(s) left on the stack", d)));
+                depth = None;
+            } else {
+                depth = Some(d);
+            }
+        }
+        falls_through = reachable && !is_unconditional(node);
+        if is_unconditional(node) {
+            depth = None;
+        }
+    }
+
+    // ファイルの上から順に並べる
+    found.sort_by_key(|(_, line, column, _)| (*line, *column));
+    let warnings: Vec<LintWarning> = found
+        .into_iter()
+        .map(|(lint, line, column, message)| LintWarning { lint, level: config.level(lint), line, column, message })
+        .filter(|w| w.level != LintLevel::Allow)
+        .collect();
+    let denied: Vec<String> = warnings
+        .iter()
+        .filter(|w| w.level == LintLevel::Deny)
+        .map(|w| w.describe())
+        .collect();
+    if !denied.is_empty() {
+        return Err(Casl2AssemblerError::LintError(denied.join("\n")));
+    }
+    Ok(warnings)
+}
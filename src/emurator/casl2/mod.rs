@@ -1,4 +1,17 @@
 pub mod parser;
 pub mod err;
 pub mod prefix;
-pub mod code_gen;
\ No newline at end of file
+pub mod code_gen;
+pub mod lexer;
+pub mod source_map;
+pub mod formatter;
+pub mod linter;
+pub mod object;
+pub mod linker;
+pub mod source;
+pub mod coverage;
+pub mod profile;
+pub mod chrome_trace;
+pub mod shadow;
+pub mod backtrace;
+pub mod convention;
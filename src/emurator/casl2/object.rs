@@ -0,0 +1,95 @@
+use crate::emurator::casl2::err::Casl2AssemblerError;
+
+/// 単位の中の番地についた名前
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    /// 単位の先頭からの語数
+    pub offset: u16,
+}
+
+/// 再配置できるオブジェクト
+/// START から END までの1単位を0番地に置いたものとして持つ
+///
+/// ## テキスト形式
+/// ```text
+/// OBJECT MAIN
+/// ENTRY 0000
+/// EXPORT MAIN 0000
+/// EXTERN SUB 0003
+/// RELOC 0005
+/// CODE 1210 0002 8000 0000
+/// ```
+/// 数値は16進4桁 CODEは何行あってもよい
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectFile {
+    /// STARTのラベル
+    pub name: String,
+    pub code: Vec<u16>,
+    /// 実行開始の位置
+    pub entry: u16,
+    /// 他の単位から参照できるラベル
+    pub exports: Vec<Symbol>,
+    /// 他の単位のラベルの番地を入れる語
+    pub externals: Vec<Symbol>,
+    /// 単位内の番地が入っている語 配置した先頭番地を足す
+    pub relocations: Vec<u16>,
+}
+
+/// CODE 1行あたりの語数
+const WORDS_PER_LINE: usize = 8;
+
+fn hex(s: &str, line: usize) -> Result<u16, Casl2AssemblerError> {
+    u16::from_str_radix(s, 16).map_err(|_| Casl2AssemblerError::ParseError(format!("Invalid hex number: {}, line: {}", s, line + 1)))
+}
+
+impl ObjectFile {
+    /// テキスト形式に書き出す
+    pub fn to_text(&self) -> String {
+        let mut out = format!("OBJECT {}\nENTRY {:04X}\n", self.name, self.entry);
+        for s in &self.exports {
+            out.push_str(&format!("EXPORT {} {:04X}\n", s.name, s.offset));
+        }
+        for s in &self.externals {
+            out.push_str(&format!("EXTERN {} {:04X}\n", s.name, s.offset));
+        }
+        for r in &self.relocations {
+            out.push_str(&format!("RELOC {:04X}\n", r));
+        }
+        for chunk in self.code.chunks(WORDS_PER_LINE) {
+            let words: Vec<String> = chunk.iter().map(|w| format!("{:04X}", w)).collect();
+            out.push_str(&format!("CODE {}\n", words.join(" ")));
+        }
+        out
+    }
+
+    /// テキスト形式を読む
+    pub fn from_text(text: &str) -> Result<Self, Casl2AssemblerError> {
+        let mut object = ObjectFile::default();
+        for (i, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let invalid = || Casl2AssemblerError::ParseError(format!("Invalid object line: {}, line: {}", line, i + 1));
+            match fields.as_slice() {
+                [] => {}
+                ["OBJECT", name] => object.name = name.to_string(),
+                ["OBJECT"] => object.name = String::new(),
+                ["ENTRY", offset] => object.entry = hex(offset, i)?,
+                ["EXPORT", name, offset] => object.exports.push(Symbol { name: name.to_string(), offset: hex(offset, i)? }),
+                ["EXTERN", name, offset] => object.externals.push(Symbol { name: name.to_string(), offset: hex(offset, i)? }),
+                ["RELOC", offset] => object.relocations.push(hex(offset, i)?),
+                ["CODE", words @ ..] => {
+                    for w in words {
+                        object.code.push(hex(w, i)?);
+                    }
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        let len = object.code.len();
+        let out_of_range = object.relocations.iter().chain(object.externals.iter().map(|s| &s.offset)).any(|&o| o as usize >= len);
+        if out_of_range {
+            return Err(Casl2AssemblerError::ParseError(format!("Offset out of code in object {}", object.name)));
+        }
+        Ok(object)
+    }
+}
@@ -1,4 +1,4 @@
-use crate::emurator::casl2::{err::Casl2AssemblerError, prefix::{assembler_instructions, GR_LIST}};
+use crate::emurator::casl2::{err::Casl2AssemblerError, lexer::{comment_start, split_fields}, prefix::{assembler_instructions, GR_LIST}};
 
 #[derive(Debug, Clone)]
 pub enum ASTNode {
@@ -23,6 +23,14 @@ pub enum ASTNode {
         operands: Vec<String>,
         comment: Option<String>,
     },
+    /// IN, OUT, RPUSH, RPOP
+    /// コード生成時に機械語命令に展開される
+    MacroInstruction {
+        label: Option<String>,
+        opcode: String,
+        operands: Vec<String>,
+        comment: Option<String>,
+    },
     START {
         label: String,
         addr: String,
@@ -32,13 +40,20 @@ pub enum ASTNode {
 }
 
 impl ASTNode {
+    /// 機械語命令の行か
+    pub fn is_instruction(&self) -> bool {
+        matches!(self, Self::Machine1wInstruction { .. } | Self::Machine2wInstruction { .. })
+    }
+
     /// casl2を解析してASTノードのベクタを生成する
     pub fn de(str: &str) -> Result<Vec<Self>, Casl2AssemblerError> {
         let lines = str.lines();
         let mut nodes = Vec::new();
 
         for (i, line) in lines.enumerate() {
-            nodes.push(Self::analyze(i, line)?);
+            // 命令の欄を指す
+            let column = || split_fields(i, line).opcode.map_or(0, |t| t.start);
+            nodes.push(Self::analyze(i, line).map_err(|e| e.at(i, column()))?);
         }
         Ok(nodes)
     }
@@ -49,27 +64,33 @@ impl ASTNode {
     /// 存在する命令しか処理しないことを保証しますが論理エラーなどは解析できない
     /// あとエラーメッセージも不十分なので todo エラーメッセージをわかりやすくする
     pub fn analyze(line_number: usize, str: &str) -> Result<Self, Casl2AssemblerError> {
-        if str.trim().is_empty() {
+        if str.trim().is_empty() || str.trim_start().starts_with(';') {
+            // 空行とコメントだけの行
             return Ok(Self::EMPTY);
         }
 
+        // 文字定数の中の`;`と`,`で切らないように、コメントとオペランドは字句解析で分ける
+        let (body, comment) = match comment_start(str) {
+            Some(i) => (&str[..i], Some(str[i + 1..].trim_start().to_string())),
+            None => (str, None),
+        };
+        // 欄の区切りはタブでも空白でもよい
         let re = regex::Regex::new(
-            r"^\s*(?:(?P<label>\w{1,4})\t)?(?P<opcode>\w+)(?:\t(?P<operand>[^\t;]*))?(?:\s*;\s*(?P<comment>.*))?$"
+            r"^(?P<label>\w{1,8})?\s+(?P<opcode>\w+)(?:\s+(?P<operand>.*?))?\s*$"
         ).unwrap();
 
-        if let Some(cap) = re.captures(str) {
+        if let Some(cap) = re.captures(body) {
             let label = cap.name("label").map(|m| m.as_str().to_string());
             let opcode = cap.name("opcode").unwrap().as_str().to_string();
             let operand = cap.name("operand").map(|m| m.as_str().to_string()).unwrap_or_default();
-            let comment = cap.name("comment").map(|m| m.as_str().to_string());
-            println!("{:?} {:?} {:?} {:?}", label, opcode, operand, comment);
+            let split: Vec<String> = split_fields(line_number, body).operands.into_iter().map(|t| t.text).collect();
 
             match opcode.as_str() {
                 assembler_instructions::DC => {
                     // アセンブラ命令
-                    let operands: Vec<String> = operand.split(',').map(|s| s.trim().to_string()).collect();
-                    if label.is_none() && operands.is_empty() {
-                        return Err(Casl2AssemblerError::AnalyzeError(format!("Invalid DC instruction, line: {}\n\t{}", line_number, str)));
+                    let operands = split;
+                    if operands.is_empty() {
+                        return Err(Casl2AssemblerError::AnalyzeError(format!("Invalid DC instruction, line: {}\n\t{}", line_number + 1, str)));
                     }
                     Ok(Self::AssemblerInstruction {
                         label: label.unwrap_or_default(),
@@ -80,9 +101,9 @@ impl ASTNode {
                 },
                 assembler_instructions::DS => {
                     // アセンブラ命令
-                    let operands: Vec<String> = operand.split(',').map(|s| s.trim().to_string()).collect();
-                    if operands.len() != 1 || !operands[0].parse::<u16>().is_ok() {
-                        return Err(Casl2AssemblerError::AnalyzeError(format!("Invalid DS instruction, line: {}\n\t{}", line_number, str)));
+                    let operands = split;
+                    if operands.len() != 1 || operands[0].parse::<u16>().is_err() {
+                        return Err(Casl2AssemblerError::AnalyzeError(format!("Invalid DS instruction, line: {}\n\t{}", line_number + 1, str)));
                     }
                     Ok(Self::AssemblerInstruction {
                         label: label.unwrap_or_default(),
@@ -93,23 +114,58 @@ impl ASTNode {
                 },
                 assembler_instructions::START => {
                     // START命令
-                    let operands: Vec<String> = operand.split(',').map(|s| s.trim().to_string()).collect();
-                    if operands.len() != 1 {
-                        return Err(Casl2AssemblerError::AnalyzeError(format!("Invalid START instruction, line: {}\n\t{}", line_number, str)));
+                    let operands = split;
+                    if operands.len() > 1 {
+                        return Err(Casl2AssemblerError::AnalyzeError(format!("Invalid START instruction, line: {}\n\t{}", line_number + 1, str)));
                     }
                     Ok(Self::START {
                         label: label.unwrap_or_default(),
-                        addr: operands[0].clone(),
+                        addr: operands.first().cloned().unwrap_or_default(),
                     })
                 },
                 assembler_instructions::END => {
                     // END命令
                     if label.is_some() || !operand.is_empty() {
-                        return Err(Casl2AssemblerError::AnalyzeError(format!("Invalid END instruction, line: {}\n\t{}", line_number, str)));
+                        return Err(Casl2AssemblerError::AnalyzeError(format!("Invalid END instruction, line: {}\n\t{}", line_number + 1, str)));
                     }
                     Ok(Self::END)
                 },
+                assembler_instructions::IN
+                | assembler_instructions::OUT
+                | assembler_instructions::RPUSH
+                | assembler_instructions::RPOP => {
+                    // マクロ命令
+                    let operands = split;
+                    let expected = match opcode.as_str() {
+                        assembler_instructions::IN | assembler_instructions::OUT => 2,
+                        _ => 0,
+                    };
+                    if operands.len() != expected {
+                        return Err(Casl2AssemblerError::AnalyzeError(format!("Invalid number of operands for {} instruction, line: {}\n\t{}", opcode, line_number + 1, str)));
+                    }
+                    Ok(Self::MacroInstruction {
+                        label,
+                        opcode,
+                        operands,
+                        comment,
+                    })
+                },
+                #[cfg(feature = "interrupts")]
+                assembler_instructions::RETI => {
+                    // オペランドなし
+                    if !operand.trim().is_empty() {
+                        return Err(Casl2AssemblerError::AnalyzeError(format!("Invalid number of operands for {} instruction, line: {}\n\t{}", opcode, line_number + 1, str)));
+                    }
+                    Ok(Self::Machine1wInstruction {
+                        label,
+                        opcode,
+                        r1: 0,
+                        r2: 0,
+                        comment,
+                    })
+                },
                 assembler_instructions::NOP
+                | assembler_instructions::RET
                 | assembler_instructions::LD
                 | assembler_instructions::ADDA
                 | assembler_instructions::SUBA
@@ -136,12 +192,13 @@ impl ASTNode {
                 | assembler_instructions::PUSH
                 | assembler_instructions::CALL
                 | assembler_instructions::SVC => {
-                    let operands: Vec<String> = operand.split(',').map(|s| s.trim().to_string()).collect();
+                    let operands = split;
+                    let gr = |s: &str| GR_LIST.iter().position(|&x| x == s).map(|r| r as u8);
                     match operands.len() {
                         0 => {
-                            // ラベルとオペコードのみ
+                            // オペコードのみ NOP, RET
                             Ok(Self::Machine1wInstruction {
-                                label: label,
+                                label,
                                 opcode,
                                 r1: 0,
                                 r2: 0,
@@ -150,10 +207,9 @@ impl ASTNode {
                         }
                         1 => {
                             // オペランドがレジスタ番号か確認
-                            if let Some(r) = GR_LIST.iter().position(|&x| x == operands[0]) {
-                                let r = r as u8;
+                            if let Some(r) = gr(&operands[0]) {
                                 Ok(Self::Machine1wInstruction {
-                                    label: label,
+                                    label,
                                     opcode,
                                     r1: r,
                                     r2: 0,
@@ -162,7 +218,7 @@ impl ASTNode {
                             } else {
                                 // 出ない場合labelのはず JUMP addr みたいな
                                 Ok(Self::Machine2wInstruction {
-                                    label: label,
+                                    label,
                                     opcode,
                                     r: 0,
                                     x: 0,
@@ -172,44 +228,51 @@ impl ASTNode {
                             }
                         }
                         2 => {
-                            if let Some(r1) = GR_LIST.iter().position(|&x| x == operands[0]) {
-                                // [0] がレジスタ番号か確認
-                                // GRn GRm になってるはず
-                                let r1 = r1 as u8;
-                                if let Some(r2) = GR_LIST.iter().position(|&x| x == operands[1]) {
-                                    // [1] もレジスタ番号か確認
-                                    let r2 = r2 as u8;
+                            match (gr(&operands[0]), gr(&operands[1])) {
+                                (Some(r1), Some(r2)) => {
+                                    // GRn GRm になってるはず
                                     Ok(Self::Machine1wInstruction {
-                                        label: label,
+                                        label,
                                         opcode,
                                         r1,
                                         r2,
                                         comment,
                                     })
-                                } else {
+                                }
+                                (Some(r), None) => {
                                     // GRn addr になってるはず
                                     Ok(Self::Machine2wInstruction {
-                                        label: label,
+                                        label,
+                                        opcode,
+                                        r,
+                                        x: 0,
+                                        addr: operands[1].clone(),
+                                        comment,
+                                    })
+                                }
+                                (None, Some(x)) => {
+                                    // addr GRx になってるはず JUMP addr,GR1 みたいな
+                                    Ok(Self::Machine2wInstruction {
+                                        label,
                                         opcode,
                                         r: 0,
-                                        x: r1,
+                                        x,
                                         addr: operands[0].clone(),
                                         comment,
                                     })
                                 }
-                            } else {
-                                return Err(Casl2AssemblerError::AnalyzeError(format!("Invalid first operand for {} instruction, line: {}\n\t{}", opcode, line_number, str)));
-                            } 
+                                (None, None) => {
+                                    Err(Casl2AssemblerError::AnalyzeError(format!("Invalid first operand for {} instruction, line: {}\n\t{}", opcode, line_number + 1, str)))
+                                }
+                            }
                         }
                         3 => {
-                            if let Some(r1) = GR_LIST.iter().position(|&x| x == operands[0]) {
+                            if let Some(r1) = gr(&operands[0]) {
                                 // [0] がレジスタ番号か確認
-                                let r1 = r1 as u8;
-                                if let Some(r2) = GR_LIST.iter().position(|&x| x == operands[2]) {
-                                    // [1] もレジスタ番号か確認
-                                    let r2 = r2 as u8;
+                                if let Some(r2) = gr(&operands[2]) {
+                                    // [2] もレジスタ番号か確認
                                     Ok(Self::Machine2wInstruction {
-                                        label: label,
+                                        label,
                                         opcode,
                                         r: r1,
                                         x: r2,
@@ -217,19 +280,19 @@ impl ASTNode {
                                         comment,
                                     })
                                 } else {
-                                    return Err(Casl2AssemblerError::AnalyzeError(format!("Invalid second operand for {} instruction, line: {}\n\t{}", opcode, line_number, str)));
+                                    Err(Casl2AssemblerError::AnalyzeError(format!("Invalid second operand for {} instruction, line: {}\n\t{}", opcode, line_number + 1, str)))
                                 }
                             } else {
-                                return Err(Casl2AssemblerError::AnalyzeError(format!("Invalid first operand for {} instruction, line: {}\n\t{}", opcode, line_number, str)));
+                                Err(Casl2AssemblerError::AnalyzeError(format!("Invalid first operand for {} instruction, line: {}\n\t{}", opcode, line_number + 1, str)))
                             }
                         }
                         _ => {
-                            return Err(Casl2AssemblerError::AnalyzeError(format!("Invalid number of operands for {} instruction, line: {}\n\t{}", opcode, line_number, str)));
+                            Err(Casl2AssemblerError::AnalyzeError(format!("Invalid number of operands for {} instruction, line: {}\n\t{}", opcode, line_number + 1, str)))
                         }
                     }
                 },
                 _ => {
-                    todo!()
+                    Err(Casl2AssemblerError::InvalidInstruction(format!("{}, line: {}\n\t{}", opcode, line_number + 1, str)))
                 }
             }
 
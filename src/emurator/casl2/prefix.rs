@@ -5,6 +5,8 @@ pub mod assembler_instructions {
     pub const END: &str = "END";
     pub const NOP: &str = "NOP";
     pub const RET: &str = "RET";
+    #[cfg(feature = "interrupts")]
+    pub const RETI: &str = "RETI";
     pub const LD: &str = "LD";
     pub const ADDA: &str = "ADDA";
     pub const SUBA: &str = "SUBA";
@@ -31,6 +33,10 @@ pub mod assembler_instructions {
     pub const PUSH: &str = "PUSH";
     pub const CALL: &str = "CALL";
     pub const SVC: &str = "SVC";
+    pub const IN: &str = "IN";
+    pub const OUT: &str = "OUT";
+    pub const RPUSH: &str = "RPUSH";
+    pub const RPOP: &str = "RPOP";
 }
 
 pub const GR_LIST: [&str; 8] = [
@@ -0,0 +1,72 @@
+use crate::emurator::{casl2::code_gen::CodeGenerator, commet2::profile::Profile};
+
+/// ルーチン1つの集計
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileRow {
+    /// ラベル ラベルがなければ`LABEL+n`や`#0100`
+    pub name: String,
+    pub addr: u16,
+    /// ルーチン自身のサイクル数
+    pub self_cycles: u64,
+    /// 呼んだ先も含めたサイクル数
+    pub total_cycles: u64,
+    pub calls: u64,
+}
+
+/// プロファイルにラベルの名前をつけたもの
+#[derive(Debug, Clone)]
+pub struct ProfileReport {
+    /// 自身のサイクル数の多い順
+    pub rows: Vec<ProfileRow>,
+    /// `MAIN;SORT;SWAP`の形の呼び出しの並びとサイクル数
+    pub folded: Vec<(String, u64)>,
+    pub cycles: u64,
+}
+
+impl ProfileReport {
+    pub fn new(code_gen: &CodeGenerator, profile: &Profile) -> Self {
+        let mut rows: Vec<ProfileRow> = profile
+            .calls
+            .iter()
+            .map(|(&addr, &calls)| {
+                let (self_cycles, total_cycles) = profile.cycles_of(addr);
+                ProfileRow { name: code_gen.symbolize(addr), addr, self_cycles, total_cycles, calls }
+            })
+            .collect();
+        rows.sort_by(|a, b| b.self_cycles.cmp(&a.self_cycles).then(a.addr.cmp(&b.addr)));
+        let folded = profile
+            .stacks
+            .iter()
+            .map(|(stack, &n)| (stack.iter().map(|&a| code_gen.symbolize(a)).collect::<Vec<_>>().join(";"), n))
+            .collect();
+        ProfileReport { rows, folded, cycles: profile.cycles }
+    }
+
+    /// ルーチンごとの表
+    pub fn table(&self) -> String {
+        let percent = |n: u64| if self.cycles == 0 { 0.0 } else { n as f64 * 100.0 / self.cycles as f64 };
+        let width = self.rows.iter().map(|r| r.name.len()).max().unwrap_or(0).max(5);
+        let mut out = format!(
+            "{:<width$} {:>10} {:>7} {:>10} {:>7} {:>6}\n",
+            "label", "self", "self%", "total", "total%", "calls"
+        );
+        for r in &self.rows {
+            out += &format!(
+                "{:<width$} {:>10} {:>6.1}% {:>10} {:>6.1}% {:>6}\n",
+                r.name,
+                r.self_cycles,
+                percent(r.self_cycles),
+                r.total_cycles,
+                percent(r.total_cycles),
+                r.calls
+            );
+        }
+        out += &format!("total {} cycles\n", self.cycles);
+        out
+    }
+
+    /// flamegraph.plやspeedscopeで読める折りたたんだスタック
+    pub fn folded(&self) -> String {
+        self.folded.iter().map(|(stack, n)| format!("{} {}\n", stack, n)).collect()
+    }
+}
@@ -0,0 +1,30 @@
+use crate::emurator::{casl2::code_gen::CodeGenerator, commet2::{prefix::opecode_to_4char, shadow::{Location, UninitRead}}};
+
+/// 未初期化の値を使った警告をソースの行で説明する
+///
+/// ```text
+/// line 6: ADDA GR1,GR2: ADDA reads uninitialised GR2
+///     origin: BUF+1 (line 9: BUF DS 3)
+///     via: line 5: LD GR2,BUF+1
+/// ```
+pub fn describe(code_gen: &CodeGenerator, warning: &UninitRead) -> String {
+    let line = |addr: u16| code_gen.source_map.describe(addr).unwrap_or_else(|| format!("#{:04X}", addr));
+    let name = |location: Location| match location {
+        Location::Memory(addr) => code_gen.symbolize(addr),
+        Location::Register(r) => format!("GR{}", r),
+    };
+    let mnemonic: String = opecode_to_4char(warning.opcode).iter().collect();
+    let mut out = format!("{}: {} reads uninitialised {}\n", line(warning.pr), mnemonic.trim_end(), name(warning.location));
+    let origin = warning.value.origin;
+    out += &match origin {
+        Location::Memory(addr) => match code_gen.source_map.lookup(addr) {
+            Some(_) => format!("    origin: {} ({})\n", name(origin), line(addr)),
+            None => format!("    origin: {} (never written)\n", name(origin)),
+        },
+        Location::Register(_) => format!("    origin: {} (never set)\n", name(origin)),
+    };
+    for &addr in &warning.value.moves {
+        out += &format!("    via: {}\n", line(addr));
+    }
+    out
+}
@@ -0,0 +1,169 @@
+// ソースファイルの読み込み
+//
+// 教科書のサンプルや古い教材はShift_JISやEUC-JPで保存されていることが多いので、
+// 文字コードを判定(または指定)してデコードする
+// 診断のために、デコード後の行と列から元のファイルのバイト位置を引けるようにしておく
+
+use std::{fmt, fs, path::Path};
+
+use encoding_rs::{EUC_JP, Encoding, SHIFT_JIS, UTF_8};
+
+use crate::emurator::casl2::err::Casl2AssemblerError;
+
+/// ソースの文字コード
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceEncoding {
+    Utf8,
+    ShiftJis,
+    EucJp,
+}
+
+impl SourceEncoding {
+    /// `utf-8`, `shift_jis`, `sjis`, `cp932`, `euc-jp`など 大文字小文字は区別しない
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Some(SourceEncoding::Utf8),
+            "shift-jis" | "sjis" | "cp932" | "windows-31j" => Some(SourceEncoding::ShiftJis),
+            "euc-jp" | "eucjp" => Some(SourceEncoding::EucJp),
+            _ => None,
+        }
+    }
+
+    fn encoding(self) -> &'static Encoding {
+        match self {
+            SourceEncoding::Utf8 => UTF_8,
+            SourceEncoding::ShiftJis => SHIFT_JIS,
+            SourceEncoding::EucJp => EUC_JP,
+        }
+    }
+
+    /// 先頭のバイトから1文字のバイト数を決める
+    fn char_len(self, bytes: &[u8]) -> usize {
+        let len = match (self, bytes[0]) {
+            (SourceEncoding::Utf8, b) if b >= 0xF0 => 4,
+            (SourceEncoding::Utf8, b) if b >= 0xE0 => 3,
+            (SourceEncoding::Utf8, b) if b >= 0xC0 => 2,
+            (SourceEncoding::ShiftJis, 0x81..=0x9F | 0xE0..=0xFC) => 2,
+            (SourceEncoding::EucJp, 0x8F) => 3,
+            (SourceEncoding::EucJp, 0x8E | 0xA1..=0xFE) => 2,
+            _ => 1,
+        };
+        len.min(bytes.len())
+    }
+}
+
+impl fmt::Display for SourceEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.encoding().name())
+    }
+}
+
+/// デコードしたソース
+#[derive(Debug, Clone)]
+pub struct SourceText {
+    pub text: String,
+    pub encoding: SourceEncoding,
+    /// 先頭にBOMがあったか
+    pub bom: bool,
+    /// 行ごとの (UTF-16の列, 元のバイト位置) 行末の位置も入れておく
+    offsets: Vec<Vec<(usize, usize)>>,
+}
+
+impl SourceText {
+    /// バイト列をデコードする 文字コードがNoneなら判定する
+    /// 指定した文字コードとして正しくないバイトがあればエラー
+    pub fn decode(bytes: &[u8], encoding: Option<SourceEncoding>) -> Result<Self, Casl2AssemblerError> {
+        let bom = bytes.starts_with(b"\xEF\xBB\xBF");
+        let encoding = match encoding {
+            Some(encoding) => encoding,
+            None if bom => SourceEncoding::Utf8,
+            None => detect(bytes),
+        };
+        let body = if bom && encoding == SourceEncoding::Utf8 { &bytes[3..] } else { bytes };
+        let head = bytes.len() - body.len();
+
+        let mut text = String::with_capacity(body.len());
+        let mut offsets = vec![];
+        let mut line = vec![];
+        let mut column = 0;
+        let mut i = 0;
+        while i < body.len() {
+            let len = encoding.char_len(&body[i..]);
+            let chunk = &body[i..i + len];
+            let Some(decoded) = encoding.encoding().decode_without_bom_handling_and_without_replacement(chunk) else {
+                return Err(Casl2AssemblerError::ParseError(format!(
+                    "Invalid {} byte sequence at byte {}, line: {}",
+                    encoding,
+                    head + i,
+                    offsets.len() + 1
+                )));
+            };
+            line.push((column, head + i));
+            text.push_str(&decoded);
+            column += decoded.encode_utf16().count();
+            i += len;
+            if chunk == b"\n" {
+                offsets.push(std::mem::take(&mut line));
+                column = 0;
+            }
+        }
+        line.push((column, head + i));
+        offsets.push(line);
+        Ok(SourceText { text, encoding, bom, offsets })
+    }
+
+    /// ファイルを読んでデコードする
+    pub fn read(path: impl AsRef<Path>, encoding: Option<SourceEncoding>) -> Result<Self, Casl2AssemblerError> {
+        Self::decode(&fs::read(path)?, encoding)
+    }
+
+    /// 行と列(UTF-16)から元のファイルのバイト位置を引く 行は0始まり
+    pub fn byte_offset(&self, line: usize, column: usize) -> Option<usize> {
+        let line = self.offsets.get(line)?;
+        line.iter().find(|&&(c, _)| c >= column).map(|&(_, b)| b)
+    }
+
+    /// エラーに`file:行:列 (byte N)`をつける 行と列は1から、バイト位置は元のファイルの0から
+    /// 位置のわからないエラーは`file: `だけ
+    pub fn describe(&self, file: &str, error: &Casl2AssemblerError) -> String {
+        match error {
+            Casl2AssemblerError::At { line, column, error } => match self.byte_offset(*line, *column) {
+                Some(byte) => format!("{}:{}:{} (byte {}): {}", file, line + 1, column + 1, byte, error),
+                None => format!("{}:{}:{}: {}", file, line + 1, column + 1, error),
+            },
+            error => format!("{}: {}", file, error),
+        }
+    }
+
+    /// 元の文字コードに戻す 整形したソースを書き戻すときに使う
+    /// その文字コードで表せない文字があればエラー
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, Casl2AssemblerError> {
+        let (bytes, _, unmappable) = self.encoding.encoding().encode(text);
+        if unmappable {
+            return Err(Casl2AssemblerError::ParseError(format!("Cannot encode in {}", self.encoding)));
+        }
+        let mut out = if self.bom { b"\xEF\xBB\xBF".to_vec() } else { vec![] };
+        out.extend_from_slice(&bytes);
+        Ok(out)
+    }
+}
+
+/// 文字コードを判定する
+/// UTF-8として正しければUTF-8、そうでなければShift_JISとEUC-JPのうちエラーなくデコードできる方
+/// どちらでも読めるときは半角カナが少ない方にする (EUC-JPをShift_JISで読むと半角カナだらけになる)
+pub fn detect(bytes: &[u8]) -> SourceEncoding {
+    if std::str::from_utf8(bytes).is_ok() {
+        return SourceEncoding::Utf8;
+    }
+    let kana = |encoding: SourceEncoding| {
+        encoding
+            .encoding()
+            .decode_without_bom_handling_and_without_replacement(bytes)
+            .map(|text| text.chars().filter(|c| ('\u{FF61}'..='\u{FF9F}').contains(c)).count())
+    };
+    match (kana(SourceEncoding::ShiftJis), kana(SourceEncoding::EucJp)) {
+        (Some(sjis), Some(euc)) if euc < sjis => SourceEncoding::EucJp,
+        (None, Some(_)) => SourceEncoding::EucJp,
+        _ => SourceEncoding::ShiftJis,
+    }
+}
@@ -0,0 +1,116 @@
+use crate::emurator::casl2::lexer::split_fields;
+
+/// 生成された語がどこから来たか
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    /// ソースに書かれた命令や定数
+    Source,
+    /// マクロ命令の展開 (マクロ名)
+    Macro(String),
+    /// リテラルの定数領域 (リテラルの表記)
+    Literal(String),
+}
+
+/// 1語分の対応
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapEntry {
+    pub addr: u16,
+    /// `SourceMap::files`の添字
+    pub file: usize,
+    /// 行 (0始まり)
+    pub line: usize,
+    /// 列 (0始まり)
+    pub column: usize,
+    pub origin: Origin,
+}
+
+/// ソースファイル名と行
+#[derive(Debug, Clone, Default)]
+pub struct SourceFile {
+    pub name: String,
+    pub lines: Vec<String>,
+}
+
+/// メモリアドレスとソース上の位置の対応表
+/// アドレスからも行からも引ける
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    pub files: Vec<SourceFile>,
+    /// アドレス順に並ぶ
+    pub entries: Vec<MapEntry>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// ファイルを登録して添字を返す
+    pub fn add_file(&mut self, name: &str, lines: Vec<String>) -> usize {
+        self.files.push(SourceFile {
+            name: name.to_string(),
+            lines,
+        });
+        self.files.len() - 1
+    }
+
+    /// 語を追加する アドレス順に追加すること
+    pub fn push(&mut self, entry: MapEntry) {
+        self.entries.push(entry);
+    }
+
+    /// アドレスの語がどこから来たか
+    pub fn lookup(&self, addr: u16) -> Option<&MapEntry> {
+        self.entries
+            .binary_search_by_key(&addr, |e| e.addr)
+            .ok()
+            .map(|i| &self.entries[i])
+    }
+
+    /// 行から生成された語のアドレス
+    pub fn addresses(&self, file: usize, line: usize) -> Vec<u16> {
+        self.entries
+            .iter()
+            .filter(|e| e.file == file && e.line == line)
+            .map(|e| e.addr)
+            .collect()
+    }
+
+    /// 行から生成された最初の語のアドレス
+    pub fn line_addr(&self, file: usize, line: usize) -> Option<u16> {
+        self.entries
+            .iter()
+            .find(|e| e.file == file && e.line == line)
+            .map(|e| e.addr)
+    }
+
+    /// ソースの行そのもの
+    pub fn source_line(&self, entry: &MapEntry) -> Option<&str> {
+        self.files
+            .get(entry.file)?
+            .lines
+            .get(entry.line)
+            .map(|l| l.as_str())
+    }
+
+    /// アドレスを `line 12: ADDA GR1,DATA` の形で説明する
+    pub fn describe(&self, addr: u16) -> Option<String> {
+        let entry = self.lookup(addr)?;
+        // ラベルとコメントは落とす
+        let fields = split_fields(entry.line, self.source_line(entry).unwrap_or_default());
+        let opcode = fields.opcode.map(|t| t.text).unwrap_or_default();
+        let operands: Vec<String> = fields.operands.into_iter().map(|t| t.text).collect();
+        let code = format!("{} {}", opcode, operands.join(",")).trim_end().to_string();
+        let file = &self.files[entry.file].name;
+        let place = if file.is_empty() {
+            format!("line {}", entry.line + 1)
+        } else {
+            format!("{}:{}", file, entry.line + 1)
+        };
+        match &entry.origin {
+            Origin::Source => Some(format!("{}: {}", place, code)),
+            Origin::Macro(name) => Some(format!("{}: {} (expanded from {})", place, code, name)),
+            Origin::Literal(lit) => Some(format!("{}: {} (literal {})", place, code, lit)),
+        }
+    }
+}
@@ -103,7 +103,7 @@ impl ALUExecution for ALU {
     /// 論理加算演算 (ADDL)
     fn addl(&mut self, a: u16, b: u16) -> Self::Return {
         let result_of = a.overflowing_add(b);
-        let result = result_of.0 as u16; // Convert back to u16
+        let result = result_of.0;
         let overflow = result_of.1; // Overflow occurs if the addition overflows
         let sign = (result & 0x8000) != 0;
         let zero = result == 0;
@@ -116,7 +116,7 @@ impl ALUExecution for ALU {
     /// 論理減算演算 (SUBL)
     fn subl(&mut self, a: u16, b: u16) -> Self::Return {
         let result_of = a.overflowing_sub(b);
-        let result = result_of.0 as u16; // Convert back to u16
+        let result = result_of.0;
         let overflow = result_of.1; // Overflow occurs if the subtraction overflows
         let sign = (result & 0x8000) != 0;
         let zero = result == 0;
@@ -167,7 +167,7 @@ impl ALUExecution for ALU {
     /// 論理左シフト演算 (SLL)
     fn sll(&mut self, a: u16, b: u16) -> Self::Return {
         let result_of = a.overflowing_shl(b as u32);
-        let result = result_of.0 as u16; // Convert back to u16
+        let result = result_of.0;
         let overflow = if (0..=16).contains(&b) {
             result_of.1 // Overflow occurs if the shift overflows
         } else {
@@ -184,7 +184,7 @@ impl ALUExecution for ALU {
     /// 論理右シフト演算 (SRL)
     fn srl(&mut self, a: u16, b: u16) -> Self::Return {
         let result_of = a.overflowing_shr(b as u32);
-        let result = result_of.0 as u16; // Convert back to u16
+        let result = result_of.0;
         let overflow = if (0..=16).contains(&b) {
             result_of.1 // Overflow occurs if the shift overflows
         } else {
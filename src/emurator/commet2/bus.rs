@@ -0,0 +1,233 @@
+use std::{fmt, sync::{Arc, Mutex}};
+
+use crate::emurator::{casl2::err::Casl2AssemblerError, commet2::{console::{string_to_words, words_to_string, Console}, state::Memory}};
+
+/// メモリへのアクセスの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    /// 命令の取り出し 2語命令は2回
+    Fetch,
+    Read,
+    Write,
+}
+
+impl fmt::Display for AccessKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessKind::Fetch => write!(f, "fetch"),
+            AccessKind::Read => write!(f, "read"),
+            AccessKind::Write => write!(f, "write"),
+        }
+    }
+}
+
+/// 1回のアクセス COMET2は1語ずつしか読み書きしないので幅はいつも1語
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    /// バスでは番地、デバイスには割り当てた先頭からの位置
+    pub addr: u16,
+    pub kind: AccessKind,
+    /// アクセスしたサイクル `CPUState::cycle`
+    pub cycle: u64,
+}
+
+/// CPUのメモリの読み書きを仲立ちする
+/// `ram`は`CPUState::memory` どのデバイスにも割り当てていない番地はここを読み書きする
+pub trait Bus {
+    fn read(&mut self, ram: &mut Memory, access: Access) -> u16;
+    fn write(&mut self, ram: &mut Memory, access: Access, value: u16);
+    /// 1サイクル進むごとに呼ばれる `cycle`は進んだ後のサイクル数
+    fn tick(&mut self, _cycle: u64) {}
+}
+
+/// 番地の範囲に割り当てるデバイス
+pub trait Device {
+    /// 占める語数
+    fn size(&self) -> u16;
+    fn read(&mut self, access: Access) -> u16;
+    fn write(&mut self, access: Access, value: u16);
+    /// 1サイクル進むごとに呼ばれる
+    fn tick(&mut self, _cycle: u64) {}
+}
+
+/// デバイスの割り当て表 割り当てのない番地はRAM
+#[derive(Default)]
+pub struct MemoryMap {
+    devices: Vec<(u16, Box<dyn Device>)>,
+}
+
+impl MemoryMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `start`から`device.size()`語を割り当てる 重なればエラー
+    pub fn map(&mut self, start: u16, device: Box<dyn Device>) -> Result<(), Casl2AssemblerError> {
+        let end = start as u32 + device.size() as u32;
+        if device.size() == 0 || end > 0x10000 {
+            return Err(Casl2AssemblerError::RuntimeError(format!("Device at #{:04X} does not fit in memory", start)));
+        }
+        for (s, d) in &self.devices {
+            if (start as u32) < *s as u32 + d.size() as u32 && (*s as u32) < end {
+                return Err(Casl2AssemblerError::RuntimeError(format!("Device at #{:04X} overlaps the one at #{:04X}", start, s)));
+            }
+        }
+        self.devices.push((start, device));
+        Ok(())
+    }
+
+    /// `addr`を受け持つデバイスとその中の位置
+    fn device(&mut self, addr: u16) -> Option<(&mut Box<dyn Device>, u16)> {
+        self.devices
+            .iter_mut()
+            .find(|(start, d)| addr >= *start && ((addr - *start) as u32) < d.size() as u32)
+            .map(|(start, d)| (d, addr - *start))
+    }
+}
+
+impl Bus for MemoryMap {
+    fn read(&mut self, ram: &mut Memory, access: Access) -> u16 {
+        match self.device(access.addr) {
+            Some((device, offset)) => device.read(Access { addr: offset, ..access }),
+            None => ram.0[access.addr as usize],
+        }
+    }
+
+    fn write(&mut self, ram: &mut Memory, access: Access, value: u16) {
+        match self.device(access.addr) {
+            Some((device, offset)) => device.write(Access { addr: offset, ..access }, value),
+            None => ram.0[access.addr as usize] = value,
+        }
+    }
+
+    fn tick(&mut self, cycle: u64) {
+        for (_, device) in &mut self.devices {
+            device.tick(cycle);
+        }
+    }
+}
+
+/// 読み出し専用 書き込みは捨てる
+pub struct Rom {
+    pub words: Vec<u16>,
+}
+
+impl Rom {
+    pub fn new(words: Vec<u16>) -> Self {
+        Rom { words }
+    }
+}
+
+impl Device for Rom {
+    fn size(&self) -> u16 {
+        self.words.len() as u16
+    }
+
+    fn read(&mut self, access: Access) -> u16 {
+        self.words[access.addr as usize]
+    }
+
+    fn write(&mut self, _access: Access, _value: u16) {}
+}
+
+/// 1語の入出力ポート
+/// 読むと入力の次の1文字 行の終わりは改行(#000A)、入力の終わりは#FFFF
+/// 書くと1文字出す 改行で1行にして`Console`に渡す
+pub struct ConsolePort {
+    console: Box<dyn Console>,
+    input: Vec<u16>,
+    output: Vec<u16>,
+}
+
+impl ConsolePort {
+    pub fn new(console: Box<dyn Console>) -> Self {
+        ConsolePort { console, input: Vec::new(), output: Vec::new() }
+    }
+}
+
+impl Device for ConsolePort {
+    fn size(&self) -> u16 {
+        1
+    }
+
+    fn read(&mut self, _access: Access) -> u16 {
+        if self.input.is_empty() {
+            match self.console.read_line() {
+                Some(line) => {
+                    self.input = string_to_words(&line);
+                    self.input.push(0x0A);
+                    self.input.reverse();
+                }
+                None => return 0xFFFF,
+            }
+        }
+        self.input.pop().unwrap_or(0xFFFF)
+    }
+
+    fn write(&mut self, _access: Access, value: u16) {
+        if value == 0x0A {
+            let line = words_to_string(&self.output);
+            self.console.write_line(&line);
+            self.output.clear();
+        } else {
+            self.output.push(value);
+        }
+    }
+}
+
+/// 読み出し専用のサイクルカウンタ 2語で下位、上位の順 書き込みは捨てる
+pub struct TimerRegister;
+
+impl Device for TimerRegister {
+    fn size(&self) -> u16 {
+        2
+    }
+
+    fn read(&mut self, access: Access) -> u16 {
+        (access.cycle >> (16 * access.addr as u64)) as u16
+    }
+
+    fn write(&mut self, _access: Access, _value: u16) {}
+}
+
+/// 1語1文字のテキスト画面 文字はJIS X 0201
+/// CPUに渡した後も`clone`したもので読める
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    pub width: u16,
+    pub height: u16,
+    cells: Arc<Mutex<Vec<u16>>>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u16, height: u16) -> Self {
+        let size = width as usize * height as usize;
+        Framebuffer { width, height, cells: Arc::new(Mutex::new(vec![0; size])) }
+    }
+
+    /// 今の画面 0は空白にする
+    pub fn lines(&self) -> Vec<String> {
+        let cells = self.cells.lock().unwrap();
+        cells
+            .chunks(self.width.max(1) as usize)
+            .map(|row| {
+                let row: Vec<u16> = row.iter().map(|&c| if c == 0 { b' ' as u16 } else { c }).collect();
+                words_to_string(&row)
+            })
+            .collect()
+    }
+}
+
+impl Device for Framebuffer {
+    fn size(&self) -> u16 {
+        (self.width as usize * self.height as usize) as u16
+    }
+
+    fn read(&mut self, access: Access) -> u16 {
+        self.cells.lock().unwrap()[access.addr as usize]
+    }
+
+    fn write(&mut self, access: Access, value: u16) {
+        self.cells.lock().unwrap()[access.addr as usize] = value;
+    }
+}
@@ -0,0 +1,94 @@
+use crate::emurator::commet2::state::CPUState;
+
+/// 呼び出し1段
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// CALLの番地
+    pub call_site: u16,
+    /// 呼んだ先
+    pub target: u16,
+    /// 戻るはずの番地
+    pub return_addr: u16,
+    /// 入った時のSP 戻り番地を指す
+    pub sp: u16,
+    /// 入った時のGR0からGR7
+    pub gr: [u16; 8],
+}
+
+/// 呼び出しの並びに合わないRET スタックが壊れている見込みが高い
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MismatchedRet {
+    /// RETの番地
+    pub pr: u16,
+    /// 実際に戻った番地
+    pub to: u16,
+    /// 戻るはずだった番地 呼ばれていないRETならNone
+    pub expected: Option<u16>,
+}
+
+/// RETで戻った先
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Return {
+    /// 呼び出しの段から戻った
+    Frame(Frame),
+    /// いちばん外のルーチンから出た OSに戻ったか、段がないのに違う所に戻った
+    Outermost,
+}
+
+/// CALLとRETから組み立てた呼び出しの並び CPUはいつも持っている
+/// いちばん外のルーチンはOSから呼ばれたので段に入らない
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallStack {
+    /// 古い順 最後が実行中のルーチン
+    pub frames: Vec<Frame>,
+    pub mismatches: Vec<MismatchedRet>,
+    /// いちばん外のルーチンから出た回数 増えたら次に実行するのは新しいいちばん外のルーチン
+    pub exits: u64,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `call_site`のCALLで`target`に入る時に呼ぶ 戻り番地はもう積んである
+    pub fn call(&mut self, call_site: u16, target: u16, return_addr: u16, state: &CPUState) {
+        self.frames.push(Frame {
+            call_site,
+            target,
+            return_addr,
+            sp: state.sp,
+            gr: std::array::from_fn(|r| state.gr.get(r as u8)),
+        });
+    }
+
+    /// `pr`のRETで`to`に戻った 戻った段を返す
+    /// 合わなければ`mismatches`に残し、もっと外の段に合えばそこまで降ろす
+    pub fn ret(&mut self, pr: u16, to: u16, state: &CPUState) -> Return {
+        let expected = self.frames.last().map(|f| f.return_addr);
+        let frame = if expected == Some(to) {
+            self.frames.pop()
+        } else if expected.is_none() && state.exit_addr.is_none_or(|exit| exit == to) {
+            // OSに戻った
+            None
+        } else {
+            self.mismatches.push(MismatchedRet { pr, to, expected });
+            if let Some(i) = self.frames.iter().rposition(|f| f.return_addr == to) {
+                self.frames.truncate(i + 1);
+            }
+            self.frames.pop()
+        };
+        match frame {
+            Some(frame) => Return::Frame(frame),
+            None => {
+                self.exits += 1;
+                Return::Outermost
+            }
+        }
+    }
+
+    /// `pc`から外に向かって、実行中の番地と呼んだ所の番地
+    pub fn backtrace(&self, pc: u16) -> Vec<u16> {
+        std::iter::once(pc).chain(self.frames.iter().rev().map(|f| f.call_site)).collect()
+    }
+}
@@ -0,0 +1,134 @@
+use std::{collections::VecDeque, io::{self, BufRead, Write}, sync::{Arc, Mutex}};
+
+use crate::emurator::{commet2::{bus::AccessKind, cpu::{Supervisor, CPU}, prefix::svc}, jisx0201};
+
+/// INで読める最大の文字数
+pub const MAX_RECORD: usize = 256;
+
+/// IN/OUTの入出力先
+pub trait Console {
+    /// 1行読む 入力の終わりならNone
+    fn read_line(&mut self) -> Option<String>;
+    /// 1行書く
+    fn write_line(&mut self, line: &str);
+}
+
+/// 1語1文字のメモリから文字列にする 文字はJIS X 0201
+pub fn words_to_string(words: &[u16]) -> String {
+    jisx0201::decode_lossy(words)
+}
+
+/// 文字列を1語1文字にする JIS X 0201にない文字は`?`
+pub fn string_to_words(text: &str) -> Vec<u16> {
+    jisx0201::encode_lossy(text)
+}
+
+/// SVC 1: 1行読んでGR1の領域に入れ、文字数をGR2の番地に入れる 入力の終わりなら-1
+pub fn svc_in(console: &mut dyn Console, cpu: &mut CPU) {
+    let buf = cpu.state.gr.get(1);
+    let len_addr = cpu.state.gr.get(2);
+    let len = match console.read_line() {
+        Some(line) => {
+            let words = string_to_words(&line);
+            let words = &words[..words.len().min(MAX_RECORD)];
+            for (i, &w) in words.iter().enumerate() {
+                cpu.write_word(buf.wrapping_add(i as u16), w);
+            }
+            words.len() as u16
+        }
+        None => 0xFFFF,
+    };
+    cpu.write_word(len_addr, len);
+}
+
+/// SVC 2: GR1の領域からGR2の番地にある文字数だけ書き出す
+pub fn svc_out(console: &mut dyn Console, cpu: &mut CPU) {
+    let buf = cpu.state.gr.get(1);
+    let len = cpu.read_word(cpu.state.gr.get(2), AccessKind::Read).min(MAX_RECORD as u16);
+    let words: Vec<u16> = (0..len).map(|i| cpu.read_word(buf.wrapping_add(i), AccessKind::Read)).collect();
+    // 読めなければ書かない
+    if cpu.fault.is_none() {
+        console.write_line(&words_to_string(&words));
+    }
+}
+
+impl<C: Console> Supervisor for C {
+    fn svc(&mut self, code: u16, cpu: &mut CPU) {
+        match code {
+            svc::IN => svc_in(self, cpu),
+            svc::OUT => svc_out(self, cpu),
+            _ => {
+                // PRはSVCの2語目を指している
+                let addr = cpu.state.pr.wrapping_sub(1);
+                cpu.raise_fault(addr, AccessKind::Fetch, &format!("unknown SVC {}", code));
+            }
+        }
+    }
+}
+
+/// 標準入出力
+pub struct StdioConsole;
+
+impl Console for StdioConsole {
+    fn read_line(&mut self) -> Option<String> {
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end_matches(['\n', '\r']).to_string()),
+        }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        let mut out = io::stdout().lock();
+        let _ = writeln!(out, "{}", line);
+        let _ = out.flush();
+    }
+}
+
+/// 入力を先に用意しておき、出力をためておくコンソール
+/// cloneしたものは同じバッファを共有するので、CPUに渡した後も読み書きできる
+#[derive(Debug, Clone, Default)]
+pub struct BufferConsole {
+    input: Arc<Mutex<VecDeque<String>>>,
+    output: Arc<Mutex<Vec<String>>>,
+}
+
+impl BufferConsole {
+    pub fn new<S: AsRef<str>>(input: &[S]) -> Self {
+        let console = Self::default();
+        for line in input {
+            console.push_input(line.as_ref());
+        }
+        console
+    }
+
+    /// 入力を1行足す
+    pub fn push_input(&self, line: &str) {
+        self.input.lock().unwrap().push_back(line.to_string());
+    }
+
+    /// まだ読まれていない入力の行数
+    pub fn pending_input(&self) -> usize {
+        self.input.lock().unwrap().len()
+    }
+
+    /// これまでの出力
+    pub fn output(&self) -> Vec<String> {
+        self.output.lock().unwrap().clone()
+    }
+
+    /// これまでの出力を取り出して空にする
+    pub fn take_output(&self) -> Vec<String> {
+        std::mem::take(&mut *self.output.lock().unwrap())
+    }
+}
+
+impl Console for BufferConsole {
+    fn read_line(&mut self) -> Option<String> {
+        self.input.lock().unwrap().pop_front()
+    }
+
+    fn write_line(&mut self, line: &str) {
+        self.output.lock().unwrap().push(line.to_string());
+    }
+}
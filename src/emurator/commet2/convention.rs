@@ -0,0 +1,58 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::emurator::commet2::{callstack::Frame, state::CPUState};
+
+/// 呼ばれたルーチンが値を変えて戻ったGR
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clobbered {
+    /// ルーチンの先頭番地
+    pub routine: u16,
+    /// 呼んだCALLの番地
+    pub call_site: u16,
+    /// 戻ったRETの番地
+    pub ret: u16,
+    pub register: u8,
+    /// CALLの時の値
+    pub before: u16,
+    /// RETの時の値
+    pub after: u16,
+}
+
+/// 呼び出し規約の検査 CPUの`convention`に入れておくと、CALLで入ったルーチンが対応するRETで戻る時に
+/// GR1からGR7が入った時と同じか比べる GR0は戻り値に使うので見ない
+/// 戻り値などで変えてよいGRはルーチンの先頭番地ごとに`allow`で足す
+/// 同じ呼び出し元、同じRET、同じGRの違反は1回だけ残す
+#[derive(Debug, Clone, Default)]
+pub struct ConventionChecker {
+    /// ルーチンの先頭番地ごとの変えてよいGR
+    pub allowed: BTreeMap<u16, Vec<u8>>,
+    pub violations: Vec<Clobbered>,
+    reported: HashSet<(u16, u16, u8)>,
+}
+
+impl ConventionChecker {
+    /// GR1からGR7をすべて守らせる
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `routine`では`registers`を変えてもよい
+    pub fn allow(mut self, routine: u16, registers: &[u8]) -> Self {
+        self.allowed.entry(routine).or_default().extend_from_slice(registers);
+        self
+    }
+
+    /// `ret`のRETで`frame`の呼び出しから戻った時に呼ぶ
+    pub fn check(&mut self, frame: &Frame, ret: u16, state: &CPUState) {
+        let allowed = self.allowed.get(&frame.target);
+        for register in 1..8u8 {
+            let (before, after) = (frame.gr[register as usize], state.gr.get(register));
+            if before == after || allowed.is_some_and(|a| a.contains(&register)) {
+                continue;
+            }
+            if self.reported.insert((frame.call_site, ret, register)) {
+                self.violations.push(Clobbered { routine: frame.target, call_site: frame.call_site, ret, register, before, after });
+            }
+        }
+    }
+}
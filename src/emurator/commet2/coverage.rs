@@ -0,0 +1,47 @@
+use std::collections::BTreeMap;
+
+/// 実行のカバレッジ
+/// CPUの`coverage`に入れておくと、命令を取り出した番地と条件分岐の結果を数える
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    /// 番地ごとの命令を取り出した回数
+    pub fetched: BTreeMap<u16, u64>,
+    /// 条件分岐の番地ごとの (分岐した回数, しなかった回数)
+    pub branches: BTreeMap<u16, (u64, u64)>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_fetch(&mut self, addr: u16) {
+        *self.fetched.entry(addr).or_default() += 1;
+    }
+
+    pub fn record_branch(&mut self, addr: u16, taken: bool) {
+        let counts = self.branches.entry(addr).or_default();
+        if taken {
+            counts.0 += 1;
+        } else {
+            counts.1 += 1;
+        }
+    }
+
+    /// 番地の命令を取り出した回数
+    pub fn hits(&self, addr: u16) -> u64 {
+        self.fetched.get(&addr).copied().unwrap_or(0)
+    }
+
+    /// 別の実行の結果を足す テスト入力ごとに実行してまとめるとき
+    pub fn merge(&mut self, other: &Coverage) {
+        for (&addr, &n) in &other.fetched {
+            *self.fetched.entry(addr).or_default() += n;
+        }
+        for (&addr, &(taken, not_taken)) in &other.branches {
+            let counts = self.branches.entry(addr).or_default();
+            counts.0 += taken;
+            counts.1 += not_taken;
+        }
+    }
+}
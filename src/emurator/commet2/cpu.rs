@@ -1,8 +1,10 @@
-use std::{fmt::Debug, thread::LocalKey};
+use std::fmt::{self, Debug};
 
-use crate::emurator::commet2::{alu::{ALUExecution, ALU}, decoder::{DecResult, Decoder, DecoderExecution}, prefix::{decoder_cycle, fetch_cycle, instruction, machine_cycle, opecode_to_4char}};
+use crate::emurator::commet2::{alu::{ALUExecution, ALU}, decoder::{Decoder, DecoderExecution}, prefix::{decoder_cycle, fetch_cycle, instruction, machine_cycle, opecode_to_4char}};
 
-use super::state::CPUState;
+use super::{bus::{Access, AccessKind, Bus}, callstack::{CallStack, Return}, convention::ConventionChecker, coverage::Coverage, profile::Profile, protection::MemoryProtection, shadow::{Location, ShadowMemory}, stack::StackGuard, state::CPUState};
+#[cfg(feature = "interrupts")]
+use super::interrupt::InterruptController;
 
 pub struct CPU {
     /// CPUの状態を保持するやつ
@@ -11,6 +13,77 @@ pub struct CPU {
     pub alu: ALU,
     /// decoder: 命令デコーダ
     pub decoder: Decoder,
+    /// SVCを受け取るホスト なければSVCは何もしない
+    pub supervisor: Option<Box<dyn Supervisor>>,
+    /// 入れておくと実行した番地と条件分岐の結果を数える
+    pub coverage: Option<Coverage>,
+    /// 入れておくとルーチンごとのサイクル数を数える
+    pub profile: Option<Profile>,
+    /// 入れておくと命令の取り出し、CALL、RET、SVCを知らせる
+    pub tracer: Option<Box<dyn Tracer>>,
+    /// 入れておくとメモリの読み書きをすべてここに通す なければ`state.memory`を直に読み書きする
+    pub bus: Option<Box<dyn Bus>>,
+    /// 入れておくと命令の取り出しの前に割り込みを受け付ける
+    #[cfg(feature = "interrupts")]
+    pub interrupts: Option<InterruptController>,
+    /// 入れておくとメモリの読み書きを検査する
+    pub protection: Option<MemoryProtection>,
+    /// 入れておくと未初期化の値を使ったのを見つける
+    pub shadow: Option<ShadowMemory>,
+    /// 入れておくとスタックのあふれと底抜けで止め、深さを数える
+    pub stack: Option<StackGuard>,
+    /// CALLとRETで組み立てた呼び出しの並び
+    pub calls: CallStack,
+    /// 入れておくとRETでGR1からGR7を守ったか確かめる
+    pub convention: Option<ConventionChecker>,
+    /// CPUを止めた原因 止まると`machine_cycle::END`になる
+    pub fault: Option<Fault>,
+    /// 実行中の命令の先頭番地
+    inst_addr: u16,
+}
+
+/// CPUを止めた原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fault {
+    /// 止めた命令の先頭番地
+    pub pr: u16,
+    /// アクセスした番地
+    pub addr: u16,
+    pub kind: AccessKind,
+    pub reason: String,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} #{:04X} at PR #{:04X}", self.reason, self.kind, self.addr, self.pr)
+    }
+}
+
+/// SVC命令を処理するホスト側
+pub trait Supervisor {
+    /// `code`は実効アドレス レジスタを読み書きしてよい
+    /// メモリはCPUの`read_word`/`write_word`で読み書きする 保護やバスを通る
+    fn svc(&mut self, code: u16, cpu: &mut CPU);
+}
+
+/// 実行中に起きたこと 番地は命令の先頭
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecEvent {
+    /// 命令の1語目を取り出した
+    Fetch { addr: u16, opcode: u8 },
+    /// CALLで`to`に移った
+    Call { from: u16, to: u16 },
+    /// RETで`to`に戻った `depth`は戻った後のCPUの`calls`の段数
+    /// `outermost`ならいちばん外のルーチンから出た
+    Ret { from: u16, to: u16, depth: usize, outermost: bool },
+    /// SVCをホストが処理した
+    Svc { addr: u16, code: u16 },
+}
+
+/// 実行中の出来事を受け取る
+pub trait Tracer {
+    /// `state.cycle`がその出来事のサイクル
+    fn event(&mut self, event: ExecEvent, state: &CPUState);
 }
 
 pub trait CPUExecution {
@@ -44,6 +117,7 @@ pub enum UpdateNotify {
     GENADDR(u16),
     ACCSGR(u8, u16),
     EXEALU(u8, u16, [bool; 3]),
+    SVC(u16),
     NONE,
     END,
 }
@@ -66,6 +140,7 @@ impl Debug for UpdateNotify {
                 "EXE ALU(r{}, 0x{:04X}, [OF: {}, CF: {}, ZF: {}])",
                 r, val, flags[0], flags[1], flags[2]
             ),
+            UpdateNotify::SVC(code) => write!(f, "SVC({})", code),
             UpdateNotify::NONE => write!(f, "NONE"),
             UpdateNotify::END => write!(f, "END"),
         }
@@ -83,22 +158,151 @@ impl CPU {
             state: CPUState::new(),
             alu: ALU,
             decoder: Decoder,
+            supervisor: None,
+            coverage: None,
+            profile: None,
+            tracer: None,
+            bus: None,
+            #[cfg(feature = "interrupts")]
+            interrupts: None,
+            protection: None,
+            shadow: None,
+            stack: None,
+            calls: CallStack::new(),
+            convention: None,
+            fault: None,
+            inst_addr: 0,
         }
     }
+
+    /// 止める 最初の原因だけ残す
+    pub fn raise_fault(&mut self, addr: u16, kind: AccessKind, reason: &str) {
+        if self.fault.is_none() {
+            self.fault = Some(Fault { pr: self.inst_addr, addr, kind, reason: reason.to_string() });
+        }
+    }
+
+    /// 保護に反していれば止めてfalse
+    fn permitted(&mut self, addr: u16, kind: AccessKind) -> bool {
+        let reason = self.protection.as_ref().and_then(|p| p.check(addr, kind));
+        if let Some(reason) = reason {
+            self.raise_fault(addr, kind, reason);
+        }
+        reason.is_none()
+    }
+
+    /// MARの番地を読む
+    fn read_memory(&mut self, kind: AccessKind) -> u16 {
+        self.read_word(self.state.mar, kind)
+    }
+
+    /// 番地を読む 保護に反していれば止めて0
+    pub fn read_word(&mut self, addr: u16, kind: AccessKind) -> u16 {
+        let access = Access { addr, kind, cycle: self.state.cycle };
+        if !self.permitted(access.addr, kind) {
+            return 0;
+        }
+        match &mut self.bus {
+            Some(bus) => bus.read(&mut self.state.memory, access),
+            None => self.state.memory.0[access.addr as usize],
+        }
+    }
+
+    /// MARの番地にMDRを書く
+    fn write_memory(&mut self) {
+        self.write_word(self.state.mar, self.state.mdr);
+    }
+
+    /// 番地に書く 保護に反していれば止めて書かない
+    pub fn write_word(&mut self, addr: u16, value: u16) {
+        let access = Access { addr, kind: AccessKind::Write, cycle: self.state.cycle };
+        if !self.permitted(addr, AccessKind::Write) {
+            return;
+        }
+        match &mut self.bus {
+            Some(bus) => bus.write(&mut self.state.memory, access, value),
+            None => self.state.memory.0[addr as usize] = value,
+        }
+    }
+
+    /// CPUが自分でスタックに積む
+    fn push_word(&mut self, value: u16) {
+        self.state.sp = self.state.sp.wrapping_sub(1);
+        self.write_word(self.state.sp, value);
+        if let Some(shadow) = &mut self.shadow {
+            shadow.define(Location::Memory(self.state.sp));
+        }
+    }
+
+    /// 割り込みを受け付ける PR、FRの順に積んでベクタの番地へ 1サイクルで済ませる
+    #[cfg(feature = "interrupts")]
+    fn enter_interrupt(&mut self, vector: u16) -> UpdateNotify {
+        let fr = (self.state.fr[0] as u16) << 2 | (self.state.fr[1] as u16) << 1 | self.state.fr[2] as u16;
+        self.push_word(self.state.pr);
+        self.push_word(fr);
+        self.state.mar = vector;
+        self.state.pr = self.read_memory(AccessKind::Read);
+        UpdateNotify::PR(self.state.pr)
+    }
+
+    /// トレーサーに知らせる
+    fn trace(&mut self, event: ExecEvent) {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.event(event, &self.state);
+        }
+    }
+
+    /// 条件分岐 `taken`なら実効アドレスへ
+    fn conditional_jump(&mut self, taken: bool, gen_addr: u16) -> UpdateNotify {
+        if let Some(coverage) = &mut self.coverage {
+            // PRは命令の2語目を指している
+            coverage.record_branch(self.state.pr.wrapping_sub(1), taken);
+        }
+        if taken {
+            // MAR から PR へ
+            self.state.pr = gen_addr;
+            self.state.machine_cycle = machine_cycle::FETCH;
+            UpdateNotify::PR(self.state.pr)
+        } else {
+            self.state.next_cycle();
+            UpdateNotify::NONE
+        }
+    }
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CPUExecution for CPU {
     type UpdateNotify = UpdateNotify;
     
-    fn init(&mut self, mode: InitMode) {
+    fn init(&mut self, _mode: InitMode) {
         todo!()
     }
     
     fn execute_fetch(&mut self) -> Self::UpdateNotify {
         let now_fetch_cycle = self.state.step_cycle;
         match now_fetch_cycle {
+            fetch_cycle::READ_PR2MAR if self.state.exit_addr == Some(self.state.pr) => {
+                // 終了番地に戻ってきた
+                self.state.machine_cycle = machine_cycle::END;
+                UpdateNotify::END
+            },
+            #[cfg(feature = "interrupts")]
+            fetch_cycle::READ_PR2MAR if let Some((_, vector)) = self.interrupts.as_ref().and_then(|c| c.accept()) => {
+                self.enter_interrupt(vector)
+            },
             fetch_cycle::READ_PR2MAR
             | fetch_cycle::READ_PR2MAR_FOR2W => {
+                if now_fetch_cycle == fetch_cycle::READ_PR2MAR {
+                    self.inst_addr = self.state.pr;
+                    if let Some(coverage) = &mut self.coverage {
+                        coverage.record_fetch(self.state.pr);
+                    }
+                }
                 // プログラムレジスタからメモリアドレスレジスタへアドレスを転送
                 self.state.mar = self.state.pr;
                 self.state.step_cycle += 1;
@@ -107,7 +311,11 @@ impl CPUExecution for CPU {
             fetch_cycle::READ_MEM2MDR
             | fetch_cycle::READ_MEM2MDR_FOR2W => {
                 // メモリからメモリデータレジスタへデータを転送
-                self.state.mdr = self.state.memory.0[self.state.mar as usize];
+                self.state.mdr = self.read_memory(AccessKind::Fetch);
+                if now_fetch_cycle == fetch_cycle::READ_MEM2MDR {
+                    let opcode = (self.state.mdr >> 8) as u8;
+                    self.trace(ExecEvent::Fetch { addr: self.inst_addr, opcode });
+                }
                 self.state.step_cycle += 1;
                 UpdateNotify::MDR(self.state.mdr)
             },
@@ -141,8 +349,16 @@ impl CPUExecution for CPU {
         let now_decode_cycle = self.state.step_cycle;
         match now_decode_cycle {
             decoder_cycle::DECODE => {
-                self.state.decoder_state = Decoder::dec(&self.state.ir);
-                self.state.step_cycle += 1;
+                match Decoder::dec(&self.state.ir) {
+                    Some(decoded) => {
+                        self.state.decoder_state = decoded;
+                        self.state.step_cycle += 1;
+                    }
+                    None => {
+                        // 定義されていない命令 データを実行しているなど
+                        self.raise_fault(self.inst_addr, AccessKind::Fetch, "invalid instruction");
+                    }
+                }
                 UpdateNotify::DECODER(self.state.ir)
             },
             decoder_cycle::SYNC_CONTROLLER => {
@@ -189,7 +405,9 @@ impl CPUExecution for CPU {
             | instruction::w2::JUMP
             | instruction::w2::JPL
             | instruction::w2::JOV
-            | instruction::w2::CALL => {
+            | instruction::w2::PUSH
+            | instruction::w2::CALL
+            | instruction::w2::SVC => {
                 if self.state.decoder_state.r2 == 0 {
                     let gen_addr = self.state.decoder_state.addr;
                     self.state.gen_addr = gen_addr;
@@ -200,9 +418,8 @@ impl CPUExecution for CPU {
                         self.state.decoder_state.r2
                     );
                     let addr = self.state.decoder_state.addr;
-                    let gen_addr = unsafe {
-                        x.unchecked_add(addr)
-                    }; 
+                    // 番地は16ビットで一周する
+                    let gen_addr = x.wrapping_add(addr);
                     self.state.gen_addr = gen_addr;
                     self.state.next_step_cycle();
                     UpdateNotify::GENADDR(self.state.gen_addr)
@@ -239,7 +456,7 @@ impl CPUExecution for CPU {
                     },
                     1 => {
                         // MDRにデータをセット
-                        self.state.mdr = self.state.memory.0[self.state.mar as usize];
+                        self.state.mdr = self.read_memory(AccessKind::Read);
                         self.state.step_cycle += 1;
                         UpdateNotify::MDR(self.state.mdr)
                     }
@@ -272,7 +489,7 @@ impl CPUExecution for CPU {
                     }
                     2 => {
                         // メモリにデータを書き込む
-                        self.state.memory.0[self.state.mar as usize] = self.state.mdr;
+                        self.write_memory();
                         self.state.next_cycle();
                         UpdateNotify::NONE
                     }
@@ -299,7 +516,7 @@ impl CPUExecution for CPU {
                     },
                     1 => {
                         // MDRにデータをセット
-                        self.state.mdr = self.state.memory.0[self.state.mar as usize];
+                        self.state.mdr = self.read_memory(AccessKind::Read);
                         self.state.step_cycle += 1;
                         UpdateNotify::MDR(self.state.mdr)
                     },
@@ -329,7 +546,7 @@ impl CPUExecution for CPU {
                     },
                     1 => {
                         // MDRにデータをセット
-                        self.state.mdr = self.state.memory.0[self.state.mar as usize];
+                        self.state.mdr = self.read_memory(AccessKind::Read);
                         self.state.step_cycle += 1;
                         UpdateNotify::MDR(self.state.mdr)
                     },
@@ -359,7 +576,7 @@ impl CPUExecution for CPU {
                     },
                     1 => {
                         // MDRにデータをセット
-                        self.state.mdr = self.state.memory.0[self.state.mar as usize];
+                        self.state.mdr = self.read_memory(AccessKind::Read);
                         self.state.step_cycle += 1;
                         UpdateNotify::MDR(self.state.mdr)
                     },
@@ -389,7 +606,7 @@ impl CPUExecution for CPU {
                     },
                     1 => {
                         // MDRにデータをセット
-                        self.state.mdr = self.state.memory.0[self.state.mar as usize];
+                        self.state.mdr = self.read_memory(AccessKind::Read);
                         self.state.step_cycle += 1;
                         UpdateNotify::MDR(self.state.mdr)
                     },
@@ -419,7 +636,7 @@ impl CPUExecution for CPU {
                     },
                     1 => {
                         // MDRにデータをセット
-                        self.state.mdr = self.state.memory.0[self.state.mar as usize];
+                        self.state.mdr = self.read_memory(AccessKind::Read);
                         self.state.step_cycle += 1;
                         UpdateNotify::MDR(self.state.mdr)
                     },
@@ -449,7 +666,7 @@ impl CPUExecution for CPU {
                     },
                     1 => {
                         // MDRにデータをセット
-                        self.state.mdr = self.state.memory.0[self.state.mar as usize];
+                        self.state.mdr = self.read_memory(AccessKind::Read);
                         self.state.step_cycle += 1;
                         UpdateNotify::MDR(self.state.mdr)
                     },
@@ -479,7 +696,7 @@ impl CPUExecution for CPU {
                     },
                     1 => {
                         // MDRにデータをセット
-                        self.state.mdr = self.state.memory.0[self.state.mar as usize];
+                        self.state.mdr = self.read_memory(AccessKind::Read);
                         self.state.step_cycle += 1;
                         UpdateNotify::MDR(self.state.mdr)
                     },
@@ -509,20 +726,19 @@ impl CPUExecution for CPU {
                     },
                     1 => {
                         // MDRにデータをセット
-                        self.state.mdr = self.state.memory.0[self.state.mar as usize];
+                        self.state.mdr = self.read_memory(AccessKind::Read);
                         self.state.step_cycle += 1;
                         UpdateNotify::MDR(self.state.mdr)
                     },
                     2 => {
-                        // ALUを通してフラグセット&汎用レジスタにデータをセット
+                        // ALUを通してフラグだけセット 比較なので汎用レジスタは変えない
                         let exers = self.alu.cpa(
                             self.state.gr.get(r1),
                             self.state.mdr
                         );
                         self.state.fr = exers.flags;
-                        *self.state.gr.get_mut(r1) = exers.result;
                         self.state.next_cycle();
-                        UpdateNotify::EXEALU(r1, exers.result, exers.flags)
+                        UpdateNotify::EXEALU(r1, self.state.gr.get(r1), exers.flags)
                     }
                     _ => {
                         panic!("Unknown step cycle for CPA: {}", step_cycle);
@@ -539,20 +755,19 @@ impl CPUExecution for CPU {
                     },
                     1 => {
                         // MDRにデータをセット
-                        self.state.mdr = self.state.memory.0[self.state.mar as usize];
+                        self.state.mdr = self.read_memory(AccessKind::Read);
                         self.state.step_cycle += 1;
                         UpdateNotify::MDR(self.state.mdr)
                     },
                     2 => {
-                        // ALUを通してフラグセット&汎用レジスタにデータをセット
+                        // ALUを通してフラグだけセット 比較なので汎用レジスタは変えない
                         let exers = self.alu.cpl(
                             self.state.gr.get(r1),
                             self.state.mdr
                         );
                         self.state.fr = exers.flags;
-                        *self.state.gr.get_mut(r1) = exers.result;
                         self.state.next_cycle();
-                        UpdateNotify::EXEALU(r1, exers.result, exers.flags)
+                        UpdateNotify::EXEALU(r1, self.state.gr.get(r1), exers.flags)
                     }
                     _ => {
                         panic!("Unknown step cycle for CPL: {}", step_cycle);
@@ -569,7 +784,7 @@ impl CPUExecution for CPU {
                     },
                     1 => {
                         // MDRにデータをセット
-                        self.state.mdr = self.state.memory.0[self.state.mar as usize];
+                        self.state.mdr = self.read_memory(AccessKind::Read);
                         self.state.step_cycle += 1;
                         UpdateNotify::MDR(self.state.mdr)
                     },
@@ -599,7 +814,7 @@ impl CPUExecution for CPU {
                     },
                     1 => {
                         // MDRにデータをセット
-                        self.state.mdr = self.state.memory.0[self.state.mar as usize];
+                        self.state.mdr = self.read_memory(AccessKind::Read);
                         self.state.step_cycle += 1;
                         UpdateNotify::MDR(self.state.mdr)
                     },
@@ -629,7 +844,7 @@ impl CPUExecution for CPU {
                     },
                     1 => {
                         // MDRにデータをセット
-                        self.state.mdr = self.state.memory.0[self.state.mar as usize];
+                        self.state.mdr = self.read_memory(AccessKind::Read);
                         self.state.step_cycle += 1;
                         UpdateNotify::MDR(self.state.mdr)
                     },
@@ -659,7 +874,7 @@ impl CPUExecution for CPU {
                     },
                     1 => {
                         // MDRにデータをセット
-                        self.state.mdr = self.state.memory.0[self.state.mar as usize];
+                        self.state.mdr = self.read_memory(AccessKind::Read);
                         self.state.step_cycle += 1;
                         UpdateNotify::MDR(self.state.mdr)
                     },
@@ -751,60 +966,36 @@ impl CPUExecution for CPU {
                 UpdateNotify::EXEALU(r1, exers.result, exers.flags)
             },
             instruction::w1::CPA => {
+                // 比較なのでフラグだけ変える
                 let exers = self.alu.cpa(
                     self.state.gr.get(r1),
                     self.state.gr.get(r2)
                 );
                 self.state.fr = exers.flags;
-                *self.state.gr.get_mut(r1) = exers.result;
                 self.state.next_cycle();
-                UpdateNotify::EXEALU(r1, exers.result, exers.flags)
+                UpdateNotify::EXEALU(r1, self.state.gr.get(r1), exers.flags)
             },
             instruction::w1::CPL => {
+                // 比較なのでフラグだけ変える
                 let exers = self.alu.cpl(
                     self.state.gr.get(r1),
                     self.state.gr.get(r2)
                 );
                 self.state.fr = exers.flags;
-                *self.state.gr.get_mut(r1) = exers.result;
                 self.state.next_cycle();
-                UpdateNotify::EXEALU(r1, exers.result, exers.flags)
+                UpdateNotify::EXEALU(r1, self.state.gr.get(r1), exers.flags)
             },
             instruction::w2::JMI => {
-                // MAR から PR へ
                 let fr = self.state.fr;
-                if fr[1] == true{
-                    self.state.pr = gen_addr;
-                    self.state.machine_cycle = machine_cycle::FETCH;
-                    UpdateNotify::PR(self.state.pr)
-                } else {
-                    self.state.next_cycle();
-                    UpdateNotify::NONE
-                }
+                self.conditional_jump(fr[1], gen_addr)
             },
             instruction::w2::JNZ => {
-                // MAR から PR へ
                 let fr = self.state.fr;
-                if fr[2] == false {
-                    self.state.pr = gen_addr;
-                    self.state.machine_cycle = machine_cycle::FETCH;
-                    UpdateNotify::PR(self.state.pr)
-                } else {
-                    self.state.next_cycle();
-                    UpdateNotify::NONE
-                }
+                self.conditional_jump(!fr[2], gen_addr)
             },
             instruction::w2::JZE => {
-                // MAR から PR へ
                 let fr = self.state.fr;
-                if fr[2] == true {
-                    self.state.pr = gen_addr;
-                    self.state.machine_cycle = machine_cycle::FETCH;
-                    UpdateNotify::PR(self.state.pr)
-                } else {
-                    self.state.next_cycle();
-                    UpdateNotify::NONE
-                }
+                self.conditional_jump(fr[2], gen_addr)
             },
             instruction::w2::JUMP => {
                 // MAR から PR へ
@@ -813,39 +1004,250 @@ impl CPUExecution for CPU {
                 UpdateNotify::PR(self.state.pr)
             },
             instruction::w2::JPL => {
-                // MAR から PR へ
                 let fr = self.state.fr;
-                if fr[1] == false && fr[2] == false {
-                    self.state.pr = gen_addr;
-                    self.state.machine_cycle = machine_cycle::FETCH;
-                    UpdateNotify::PR(self.state.pr)
-                } else {
-                    self.state.next_cycle();
-                    UpdateNotify::NONE
-                }
+                self.conditional_jump(!fr[1] && !fr[2], gen_addr)
             },
             instruction::w2::JOV => {
-                // MAR から PR へ
                 let fr = self.state.fr;
-                if fr[0] == true {
-                    self.state.pr = gen_addr;
+                self.conditional_jump(fr[0], gen_addr)
+            },
+            instruction::w1::LD => {
+                // アドレス生成でGR[r2]がgen_addrに入っている
+                let exers = self.alu.or(gen_addr, 0);
+                self.state.fr = exers.flags;
+                *self.state.gr.get_mut(r1) = exers.result;
+                self.state.next_cycle();
+                UpdateNotify::EXEALU(r1, exers.result, exers.flags)
+            },
+            instruction::w2::PUSH => {
+                match step_cycle {
+                    0 => {
+                        // SPを減らす
+                        self.state.sp = self.state.sp.wrapping_sub(1);
+                        self.state.step_cycle += 1;
+                        UpdateNotify::SP(self.state.sp)
+                    },
+                    1 => {
+                        // MARにSPをセット
+                        self.state.mar = self.state.sp;
+                        self.state.step_cycle += 1;
+                        UpdateNotify::MAR(self.state.mar)
+                    },
+                    2 => {
+                        // MDRに実効アドレスをセット
+                        self.state.mdr = gen_addr;
+                        self.state.step_cycle += 1;
+                        UpdateNotify::MDR(self.state.mdr)
+                    },
+                    3 => {
+                        // メモリにデータを書き込む
+                        self.write_memory();
+                        self.state.next_cycle();
+                        UpdateNotify::NONE
+                    },
+                    _ => {
+                        panic!("Unknown step cycle for PUSH: {}", step_cycle);
+                    }
+                }
+            },
+            instruction::w1::POP => {
+                match step_cycle {
+                    0 => {
+                        // MARにSPをセット
+                        self.state.mar = self.state.sp;
+                        self.state.step_cycle += 1;
+                        UpdateNotify::MAR(self.state.mar)
+                    },
+                    1 => {
+                        // MDRにデータをセット
+                        self.state.mdr = self.read_memory(AccessKind::Read);
+                        self.state.step_cycle += 1;
+                        UpdateNotify::MDR(self.state.mdr)
+                    },
+                    2 => {
+                        // 汎用レジスタにデータをセット フラグは変化しない
+                        *self.state.gr.get_mut(r1) = self.state.mdr;
+                        self.state.step_cycle += 1;
+                        UpdateNotify::ACCSGR(r1, self.state.mdr)
+                    },
+                    3 => {
+                        // SPを増やす
+                        self.state.sp = self.state.sp.wrapping_add(1);
+                        self.state.next_cycle();
+                        UpdateNotify::SP(self.state.sp)
+                    },
+                    _ => {
+                        panic!("Unknown step cycle for POP: {}", step_cycle);
+                    }
+                }
+            },
+            instruction::w2::CALL => {
+                match step_cycle {
+                    0 => {
+                        // SPを減らす
+                        self.state.sp = self.state.sp.wrapping_sub(1);
+                        self.state.step_cycle += 1;
+                        UpdateNotify::SP(self.state.sp)
+                    },
+                    1 => {
+                        // MARにSPをセット
+                        self.state.mar = self.state.sp;
+                        self.state.step_cycle += 1;
+                        UpdateNotify::MAR(self.state.mar)
+                    },
+                    2 => {
+                        // MDRに戻り番地をセット PRは2語目を指している
+                        self.state.mdr = self.state.pr.wrapping_add(1);
+                        self.state.step_cycle += 1;
+                        UpdateNotify::MDR(self.state.mdr)
+                    },
+                    3 => {
+                        // メモリに戻り番地を書き込む
+                        self.write_memory();
+                        self.state.step_cycle += 1;
+                        UpdateNotify::NONE
+                    },
+                    4 => {
+                        // 実効アドレスから PR へ
+                        let from = self.state.pr.wrapping_sub(1);
+                        self.calls.call(from, gen_addr, self.state.mdr, &self.state);
+                        self.state.pr = gen_addr;
+                        self.state.machine_cycle = machine_cycle::FETCH;
+                        self.state.step_cycle = 0;
+                        self.trace(ExecEvent::Call { from, to: gen_addr });
+                        UpdateNotify::PR(self.state.pr)
+                    },
+                    _ => {
+                        panic!("Unknown step cycle for CALL: {}", step_cycle);
+                    }
+                }
+            },
+            instruction::w1::RET => {
+                match step_cycle {
+                    0 => {
+                        // MARにSPをセット
+                        self.state.mar = self.state.sp;
+                        self.state.step_cycle += 1;
+                        UpdateNotify::MAR(self.state.mar)
+                    },
+                    1 => {
+                        // MDRに戻り番地をセット
+                        self.state.mdr = self.read_memory(AccessKind::Read);
+                        self.state.step_cycle += 1;
+                        UpdateNotify::MDR(self.state.mdr)
+                    },
+                    2 => {
+                        // SPを増やす
+                        self.state.sp = self.state.sp.wrapping_add(1);
+                        self.state.step_cycle += 1;
+                        UpdateNotify::SP(self.state.sp)
+                    },
+                    3 => {
+                        // MDR から PR へ
+                        let from = self.state.pr;
+                        let ret = self.calls.ret(from, self.state.mdr, &self.state);
+                        let outermost = ret == Return::Outermost;
+                        if let (Some(convention), Return::Frame(frame)) = (&mut self.convention, ret)
+                            && frame.return_addr == self.state.mdr
+                        {
+                            convention.check(&frame, from, &self.state);
+                        }
+                        self.state.pr = self.state.mdr;
+                        self.state.machine_cycle = machine_cycle::FETCH;
+                        self.state.step_cycle = 0;
+                        if let Some(protection) = &mut self.protection
+                            && protection.supervisor
+                            && !protection.is_supervisor(self.state.pr)
+                        {
+                            // スーパーバイザ専用でない番地に戻ればユーザモード
+                            protection.supervisor = false;
+                        }
+                        self.trace(ExecEvent::Ret { from, to: self.state.pr, depth: self.calls.frames.len(), outermost });
+                        UpdateNotify::PR(self.state.pr)
+                    },
+                    _ => {
+                        panic!("Unknown step cycle for RET: {}", step_cycle);
+                    }
+                }
+            },
+            #[cfg(feature = "interrupts")]
+            instruction::w1::RETI => {
+                match step_cycle {
+                    0 | 3 => {
+                        // MARにSPをセット
+                        self.state.mar = self.state.sp;
+                        self.state.step_cycle += 1;
+                        UpdateNotify::MAR(self.state.mar)
+                    },
+                    1 | 4 => {
+                        // MDRにFR、次にPRをセット
+                        self.state.mdr = self.read_memory(AccessKind::Read);
+                        self.state.step_cycle += 1;
+                        UpdateNotify::MDR(self.state.mdr)
+                    },
+                    2 => {
+                        // FRを戻してSPを増やす
+                        let fr = self.state.mdr;
+                        self.state.fr = [fr & 4 != 0, fr & 2 != 0, fr & 1 != 0];
+                        self.state.sp = self.state.sp.wrapping_add(1);
+                        self.state.step_cycle += 1;
+                        UpdateNotify::SP(self.state.sp)
+                    },
+                    5 => {
+                        // SPを増やす
+                        self.state.sp = self.state.sp.wrapping_add(1);
+                        self.state.step_cycle += 1;
+                        UpdateNotify::SP(self.state.sp)
+                    },
+                    6 => {
+                        // MDR から PR へ 割り込みを許可に戻す
+                        self.state.pr = self.state.mdr;
+                        if let Some(interrupts) = &self.interrupts {
+                            interrupts.set_enabled(true);
+                        }
+                        self.state.machine_cycle = machine_cycle::FETCH;
+                        self.state.step_cycle = 0;
+                        UpdateNotify::PR(self.state.pr)
+                    },
+                    _ => {
+                        panic!("Unknown step cycle for RETI: {}", step_cycle);
+                    }
+                }
+            },
+            instruction::w2::SVC => {
+                let gate = self.protection.as_ref().and_then(|p| p.gate);
+                if let Some(gate) = gate {
+                    // 戻り番地とSVCの番号を積んでスーパーバイザモードで入口へ
+                    let from = self.state.pr.wrapping_sub(1);
+                    let ret = self.state.pr.wrapping_add(1);
+                    if let Some(protection) = &mut self.protection {
+                        protection.supervisor = true;
+                    }
+                    self.push_word(ret);
+                    self.calls.call(from, gate, ret, &self.state);
+                    self.push_word(gen_addr);
+                    self.state.pr = gate;
                     self.state.machine_cycle = machine_cycle::FETCH;
-                    UpdateNotify::PR(self.state.pr)
-                } else {
-                    self.state.next_cycle();
-                    UpdateNotify::NONE
+                    self.state.step_cycle = 0;
+                    self.trace(ExecEvent::Svc { addr: from, code: gen_addr });
+                    self.trace(ExecEvent::Call { from, to: gate });
+                    return UpdateNotify::PR(self.state.pr);
                 }
+                // ホストに任せる
+                if let Some(mut supervisor) = self.supervisor.take() {
+                    supervisor.svc(gen_addr, self);
+                    self.supervisor = Some(supervisor);
+                    if let Some(shadow) = &mut self.shadow {
+                        shadow.after_svc(gen_addr, &self.state);
+                    }
+                }
+                self.trace(ExecEvent::Svc { addr: self.state.pr.wrapping_sub(1), code: gen_addr });
+                self.state.next_cycle();
+                UpdateNotify::SVC(gen_addr)
             },
-            // instruction::w2::CALL => {
-            //     // MAR から PR へ
-            // スタックにアドレス積んでない！！！！
-            //     self.state.pr = gen_addr;
-            //     self.state.machine_cycle = machine_cycle::FETCH;
-            //     UpdateNotify::PR(self.state.pr)
-            // },
             _ => {
-                println!("Unknown opcode: {}", opcode);
-                self.state.next_cycle();
+                // デコードできても実行できない命令 (割り込みを入れていない時のRETIなど)
+                self.raise_fault(self.inst_addr, AccessKind::Fetch, "invalid instruction");
                 UpdateNotify::NONE
             }
         }
@@ -854,7 +1256,7 @@ impl CPUExecution for CPU {
     
     fn commet2_step(&mut self) -> Self::UpdateNotify {
         let now_machine_cycle = self.state.machine_cycle;
-        match now_machine_cycle {
+        let notify = match now_machine_cycle {
             machine_cycle::FETCH => {
                 self.execute_fetch()
             }
@@ -865,6 +1267,11 @@ impl CPUExecution for CPU {
                 self.execute_addr_gen()
             }
             machine_cycle::EXECUTE => {
+                if self.state.step_cycle == 0
+                    && let Some(shadow) = &mut self.shadow
+                {
+                    shadow.execute(&self.state);
+                }
                 self.execute_execute()
             }
             machine_cycle::END => {
@@ -873,13 +1280,34 @@ impl CPUExecution for CPU {
             _ => {
                 panic!("Unknown machine cycle: {}", now_machine_cycle);
             }
+        };
+        if let Some(stack) = &mut self.stack
+            && let Some((reason, kind)) = stack.record(&self.state, &notify, &self.calls)
+        {
+            self.raise_fault(self.state.sp, kind, reason);
+        }
+        if self.fault.is_some() {
+            self.state.machine_cycle = machine_cycle::END;
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.record(&self.state, &notify, &self.calls);
+        }
+        if !matches!(notify, UpdateNotify::END) {
+            self.state.cycle += 1;
+            if let Some(bus) = &mut self.bus {
+                bus.tick(self.state.cycle);
+            }
         }
+        notify
     }
     
     fn casl_step(&mut self) {
         loop {
             self.commet2_step();
-            if self.state.machine_cycle == machine_cycle::FETCH {
+            // フェッチの最初に戻ったら1命令終わり
+            if (self.state.machine_cycle == machine_cycle::FETCH && self.state.step_cycle == 0)
+                || self.state.machine_cycle == machine_cycle::END
+            {
                 break;
             }
         }
@@ -5,7 +5,8 @@ pub struct Decoder;
 pub trait DecoderExecution {
     type DecResult;
     fn is_2w(val: &[u16; 2]) -> bool;
-    fn dec(val: &[u16; 2]) -> Self::DecResult;
+    /// 定義されていない命令ならNone
+    fn dec(val: &[u16; 2]) -> Option<Self::DecResult>;
 }
 
 pub struct DecResult {
@@ -21,31 +22,28 @@ impl DecoderExecution for Decoder {
 
     fn is_2w(val: &[u16; 2]) -> bool {
         let opcode = (val[0] >> 8) as u8; // 上位8ビットをオペコードとして取得
-        match opcode {
-            instruction::w1::NOP 
-            | instruction::w1::LD 
-            | instruction::w1::ADDA 
-            | instruction::w1::SUBA 
-            | instruction::w1::ADDL 
-            | instruction::w1::SUBL 
-            | instruction::w1::AND 
-            | instruction::w1::OR 
-            | instruction::w1::XOR 
-            | instruction::w1::CPA 
-            | instruction::w1::CPL 
-            | instruction::w1::POP 
-            | instruction::w1::RET => {
-                false
-            },
-            _ => {
-                true
-            }
-        }
+        let reti = cfg!(feature = "interrupts") && opcode == instruction::w1::RETI;
+        !reti && !matches!(
+            opcode,
+            instruction::w1::NOP
+            | instruction::w1::LD
+            | instruction::w1::ADDA
+            | instruction::w1::SUBA
+            | instruction::w1::ADDL
+            | instruction::w1::SUBL
+            | instruction::w1::AND
+            | instruction::w1::OR
+            | instruction::w1::XOR
+            | instruction::w1::CPA
+            | instruction::w1::CPL
+            | instruction::w1::POP
+            | instruction::w1::RET
+        )
     }
 
-    fn dec(val: &[u16; 2]) -> Self::DecResult {
+    fn dec(val: &[u16; 2]) -> Option<Self::DecResult> {
         let opcode = (val[0] >> 8) as u8; // 上位8ビットをオペコードとして取得
-        match opcode {
+        let result = match opcode {
             instruction::w1::NOP
             | instruction::w1::RET => {
                 DecResult {
@@ -56,6 +54,16 @@ impl DecoderExecution for Decoder {
                     addr: 0,
                 }
             }
+            #[cfg(feature = "interrupts")]
+            instruction::w1::RETI => {
+                DecResult {
+                    w2: false,
+                    opcode,
+                    r1: 0,
+                    r2: 0,
+                    addr: 0,
+                }
+            }
             instruction::w1::LD 
             | instruction::w1::ADDA
             | instruction::w1::SUBA
@@ -124,16 +132,8 @@ impl DecoderExecution for Decoder {
                     addr: val[1],
                 }
             }
-            _ => {
-                println!("Unknown instruction: {:#04X}", opcode);
-                DecResult {
-                    w2: false,
-                    opcode,
-                    r1: 0,
-                    r2: 0,
-                    addr: 0,
-                }
-            }
-        }
+            _ => return None,
+        };
+        Some(result)
     }
 }
\ No newline at end of file
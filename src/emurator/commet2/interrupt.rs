@@ -0,0 +1,154 @@
+use std::sync::{Arc, Mutex};
+
+use crate::emurator::commet2::bus::{Access, Device};
+
+/// 割り込み線の数
+pub const LINES: u16 = 16;
+/// ベクタ表の先頭番地の既定値 線nの処理の番地を`base + n`に置く
+pub const DEFAULT_VECTOR_BASE: u16 = 0xFF00;
+
+#[derive(Debug)]
+struct ControllerState {
+    enabled: bool,
+    pending: u16,
+    vector_base: u16,
+}
+
+/// 割り込みコントローラ
+/// CPUは命令の取り出しの前に見て、許可されていて保留中の線があれば番号の小さいものから受け付ける
+/// 受け付けるとPR、FRの順にスタックに積んで割り込みを禁止し、ベクタ表の番地へ飛ぶ RETIで戻ると許可に戻る
+///
+/// バスに割り当てると3語のレジスタになる
+/// - +0 許可 0以外で許可
+/// - +1 保留中の線 1ビット1本 書くとそのまま置き換わる
+/// - +2 ベクタ表の先頭番地
+///
+/// CPUやタイマーに渡した後も`clone`したもので操作できる
+#[derive(Debug, Clone)]
+pub struct InterruptController {
+    state: Arc<Mutex<ControllerState>>,
+}
+
+impl Default for InterruptController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InterruptController {
+    /// 禁止の状態で作る
+    pub fn new() -> Self {
+        InterruptController {
+            state: Arc::new(Mutex::new(ControllerState { enabled: false, pending: 0, vector_base: DEFAULT_VECTOR_BASE })),
+        }
+    }
+
+    /// 線`line`に割り込みを上げる
+    pub fn raise(&self, line: u16) {
+        if line < LINES {
+            self.state.lock().unwrap().pending |= 1 << line;
+        }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.state.lock().unwrap().enabled = enabled;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.state.lock().unwrap().enabled
+    }
+
+    /// 保留中の線 1ビット1本
+    pub fn pending(&self) -> u16 {
+        self.state.lock().unwrap().pending
+    }
+
+    pub fn vector_base(&self) -> u16 {
+        self.state.lock().unwrap().vector_base
+    }
+
+    /// 受け付ける線とベクタの番地 受け付けたら保留を消して禁止にする
+    pub fn accept(&self) -> Option<(u16, u16)> {
+        let mut state = self.state.lock().unwrap();
+        if !state.enabled || state.pending == 0 {
+            return None;
+        }
+        let line = state.pending.trailing_zeros() as u16;
+        state.pending &= !(1 << line);
+        state.enabled = false;
+        Some((line, state.vector_base.wrapping_add(line)))
+    }
+}
+
+impl Device for InterruptController {
+    fn size(&self) -> u16 {
+        3
+    }
+
+    fn read(&mut self, access: Access) -> u16 {
+        let state = self.state.lock().unwrap();
+        match access.addr {
+            0 => state.enabled as u16,
+            1 => state.pending,
+            _ => state.vector_base,
+        }
+    }
+
+    fn write(&mut self, access: Access, value: u16) {
+        let mut state = self.state.lock().unwrap();
+        match access.addr {
+            0 => state.enabled = value != 0,
+            1 => state.pending = value,
+            _ => state.vector_base = value,
+        }
+    }
+}
+
+/// 一定のサイクルごとに割り込みを上げるタイマー
+///
+/// バスに割り当てると2語のレジスタになる
+/// - +0 間隔のサイクル数 書いた時から数え始める 0で止まる
+/// - +1 上げる線の番号
+pub struct IntervalTimer {
+    controller: InterruptController,
+    pub interval: u16,
+    pub line: u16,
+    next: u64,
+}
+
+impl IntervalTimer {
+    /// 止まった状態で作る
+    pub fn new(controller: InterruptController, line: u16) -> Self {
+        IntervalTimer { controller, interval: 0, line, next: 0 }
+    }
+}
+
+impl Device for IntervalTimer {
+    fn size(&self) -> u16 {
+        2
+    }
+
+    fn read(&mut self, access: Access) -> u16 {
+        match access.addr {
+            0 => self.interval,
+            _ => self.line,
+        }
+    }
+
+    fn write(&mut self, access: Access, value: u16) {
+        match access.addr {
+            0 => {
+                self.interval = value;
+                self.next = access.cycle + value as u64;
+            }
+            _ => self.line = value,
+        }
+    }
+
+    fn tick(&mut self, cycle: u64) {
+        if self.interval != 0 && cycle >= self.next {
+            self.controller.raise(self.line);
+            self.next = cycle + self.interval as u64;
+        }
+    }
+}
@@ -2,4 +2,17 @@ pub mod state;
 pub mod alu;
 pub mod cpu;
 pub mod decoder;
-pub mod prefix;
\ No newline at end of file
+pub mod prefix;
+pub mod console;
+pub mod coverage;
+pub mod profile;
+pub mod vcd;
+pub mod trace;
+pub mod bus;
+#[cfg(feature = "interrupts")]
+pub mod interrupt;
+pub mod protection;
+pub mod shadow;
+pub mod stack;
+pub mod callstack;
+pub mod convention;
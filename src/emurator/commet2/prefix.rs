@@ -20,6 +20,14 @@ pub mod decoder_cycle {
     pub const SYNC_CONTROLLER: u8 = 0x01;
 }
 
+/// SVCの機能番号
+pub mod svc {
+    /// 1行入力 GR1: 入力領域, GR2: 文字長を入れる番地
+    pub const IN: u16 = 1;
+    /// 1行出力 GR1: 出力領域, GR2: 文字長の番地
+    pub const OUT: u16 = 2;
+}
+
 pub mod instruction {
     pub mod w1 {
         pub const NOP: u8 = 0x00;
@@ -40,6 +48,8 @@ pub mod instruction {
         pub const POP: u8 = 0x71;
 
         pub const RET: u8 = 0x81;
+        /// 割り込みから戻る `interrupts`のときだけ
+        pub const RETI: u8 = 0x82;
     }
     pub mod w2 {
         pub const LD: u8 = 0x10;
@@ -79,7 +89,7 @@ pub mod instruction {
 }
 
 pub fn opecode_to_4char(opcode: u8) -> [char; 4] {
-    let chars = match opcode {
+    match opcode {
         instruction::w1::NOP => ['N', 'O', 'P', ' '],
         instruction::w1::LD => ['L', 'D', ' ', ' '],
         instruction::w1::ADDA => ['A', 'D', 'D', 'A'],
@@ -93,6 +103,8 @@ pub fn opecode_to_4char(opcode: u8) -> [char; 4] {
         instruction::w1::CPL => ['C', 'P', 'L', ' '],
         instruction::w1::POP => ['P', 'O', 'P', ' '],
         instruction::w1::RET => ['R', 'E', 'T', ' '],
+        #[cfg(feature = "interrupts")]
+        instruction::w1::RETI => ['R', 'E', 'T', 'I'],
         instruction::w2::LD => ['L', 'D', ' ', ' '],
         instruction::w2::ST => ['S', 'T', ' ', ' '],
         instruction::w2::LAD => ['L', 'D', 'A', ' '],
@@ -119,8 +131,7 @@ pub fn opecode_to_4char(opcode: u8) -> [char; 4] {
         instruction::w2::CALL => ['C', 'A', 'L', 'L'],
         instruction::w2::SVC => ['S', 'V', 'C', ' '],
         _ => ['I', 'D', 'K', '?'], // Unknown opcode
-    };
-    chars
+    }
 }
 
 pub fn opecode_to_binary(opcode: &str, is_2w: bool) -> u8 {
@@ -169,6 +180,8 @@ pub fn opecode_to_binary(opcode: &str, is_2w: bool) -> u8 {
             "CPL" => instruction::w1::CPL,
             "POP" => instruction::w1::POP,
             "RET" => instruction::w1::RET,
+            #[cfg(feature = "interrupts")]
+            "RETI" => instruction::w1::RETI,
             _ => 0xFF, // Unknown opcode
             
         }
@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+
+use crate::emurator::commet2::{callstack::CallStack, cpu::UpdateNotify, state::CPUState};
+
+/// ルーチンごとのサイクル数
+/// CPUの`profile`に入れておくと、`commet2_step`の1サイクルずつを実行中のルーチンに数える
+/// ルーチンの並びはCPUの`calls`に合わせる CALLのサイクルは呼んだ側、RETのサイクルは呼ばれた側に入る
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// 呼び出し中のルーチンの先頭番地 最後が実行中のルーチン
+    pub stack: Vec<u16>,
+    /// 呼び出しの並びごとの、いちばん上のルーチン自身のサイクル数
+    pub stacks: BTreeMap<Vec<u16>, u64>,
+    /// ルーチンの先頭番地ごとの呼ばれた回数
+    pub calls: BTreeMap<u16, u64>,
+    /// 全体のサイクル数
+    pub cycles: u64,
+    /// 最後に見たCPUの`calls`の`exits`
+    exits: u64,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 1サイクル実行した後に呼ぶ `calls`はそのサイクルの後の呼び出しの並び
+    pub fn record(&mut self, state: &CPUState, notify: &UpdateNotify, calls: &CallStack) {
+        if matches!(notify, UpdateNotify::END) {
+            return;
+        }
+        if self.stack.is_empty() {
+            // 最初のルーチン (またはいちばん外から戻った後)
+            self.stack.push(state.pr);
+            *self.calls.entry(state.pr).or_default() += 1;
+        }
+        self.cycles += 1;
+        // 毎サイクル呼ばれるので、並びを複製するのは初めて見たときだけ
+        match self.stacks.get_mut(&self.stack) {
+            Some(n) => *n += 1,
+            None => {
+                self.stacks.insert(self.stack.clone(), 1);
+            }
+        }
+
+        if calls.exits != self.exits {
+            self.exits = calls.exits;
+            self.stack.clear();
+            return;
+        }
+        // 段の数が変わっていなければそのまま
+        let depth = calls.frames.len();
+        if depth + 1 == self.stack.len() {
+            return;
+        }
+        self.stack.truncate(depth + 1);
+        for frame in &calls.frames[self.stack.len() - 1..] {
+            self.stack.push(frame.target);
+            *self.calls.entry(frame.target).or_default() += 1;
+        }
+    }
+
+    /// ルーチンの (自身のサイクル数, 呼んだ先も含めたサイクル数)
+    /// 再帰していても1つの並びでは1回だけ数える
+    pub fn cycles_of(&self, addr: u16) -> (u64, u64) {
+        let mut self_cycles = 0;
+        let mut total = 0;
+        for (stack, &n) in &self.stacks {
+            if stack.last() == Some(&addr) {
+                self_cycles += n;
+            }
+            if stack.contains(&addr) {
+                total += n;
+            }
+        }
+        (self_cycles, total)
+    }
+}
@@ -0,0 +1,86 @@
+use crate::emurator::commet2::bus::AccessKind;
+
+/// 保護する範囲 `start`から`end`まで(両端を含む)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub start: u16,
+    pub end: u16,
+    /// 書き込みを禁止する
+    pub read_only: bool,
+    /// 命令の取り出しを禁止する
+    pub no_execute: bool,
+    /// スーパーバイザモードでしか触れない
+    pub supervisor_only: bool,
+}
+
+impl Region {
+    /// 何も禁止しない範囲 ここから禁止するものを選ぶ
+    pub fn new(start: u16, end: u16) -> Self {
+        Region { start, end, read_only: false, no_execute: false, supervisor_only: false }
+    }
+
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    pub fn no_execute(mut self) -> Self {
+        self.no_execute = true;
+        self
+    }
+
+    pub fn supervisor_only(mut self) -> Self {
+        self.supervisor_only = true;
+        self
+    }
+
+    pub fn contains(&self, addr: u16) -> bool {
+        (self.start..=self.end).contains(&addr)
+    }
+}
+
+/// メモリ保護とスーパーバイザ/ユーザモード COMET2の仕様にはないのでCPUに入れたときだけ働く
+///
+/// ユーザモードから始まり、SVCでスーパーバイザモードに入る
+/// `gate`があればSVCは戻り番地、SVCの番号の順にスタックに積んで`gate`へ飛ぶ
+/// スーパーバイザモードでRETしてスーパーバイザ専用でない番地に戻るとユーザモードに戻る
+/// `gate`がなければSVCはホストが処理する ホストのIN/OUTの読み書きもモードはそのままで保護とバスを通る
+#[derive(Debug, Clone, Default)]
+pub struct MemoryProtection {
+    pub regions: Vec<Region>,
+    /// 今スーパーバイザモードか
+    pub supervisor: bool,
+    /// SVCの入口
+    pub gate: Option<u16>,
+}
+
+impl MemoryProtection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn region(mut self, region: Region) -> Self {
+        self.regions.push(region);
+        self
+    }
+
+    /// 許されないアクセスならその理由
+    pub fn check(&self, addr: u16, kind: AccessKind) -> Option<&'static str> {
+        for region in self.regions.iter().filter(|r| r.contains(addr)) {
+            if region.supervisor_only && !self.supervisor {
+                return Some("supervisor memory accessed from user mode");
+            }
+            match kind {
+                AccessKind::Write if region.read_only => return Some("write to read-only memory"),
+                AccessKind::Fetch if region.no_execute => return Some("execute from no-execute memory"),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// `addr`がスーパーバイザ専用か
+    pub fn is_supervisor(&self, addr: u16) -> bool {
+        self.regions.iter().any(|r| r.supervisor_only && r.contains(addr))
+    }
+}
@@ -0,0 +1,224 @@
+use std::{collections::HashSet, fmt};
+
+use crate::emurator::commet2::{prefix::{instruction, svc}, state::CPUState};
+
+/// 値を運んだ命令を覚えておく数
+pub const HISTORY_LEN: usize = 8;
+
+/// 値の置き場所
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Location {
+    Memory(u16),
+    Register(u8),
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Memory(addr) => write!(f, "#{:04X}", addr),
+            Location::Register(r) => write!(f, "GR{}", r),
+        }
+    }
+}
+
+/// 未初期化の値の来歴
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Undefined {
+    /// 最初に未初期化だった場所
+    pub origin: Location,
+    /// 値を運んだ命令の番地 古い順で最後の`HISTORY_LEN`個
+    pub moves: Vec<u16>,
+}
+
+impl Undefined {
+    fn moved(mut self, pr: u16) -> Self {
+        if self.moves.len() == HISTORY_LEN {
+            self.moves.remove(0);
+        }
+        self.moves.push(pr);
+        self
+    }
+}
+
+/// 未初期化の値を使った
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UninitRead {
+    /// 使った命令の番地
+    pub pr: u16,
+    pub opcode: u8,
+    /// 読んだ場所
+    pub location: Location,
+    pub value: Undefined,
+}
+
+/// メモリの語とGRごとに初期化されたかを持つ影
+/// 命令の実行の前に呼ばれ、読み込み、演算、比較、RET、OUTで未初期化の値を使うと`warnings`に残す
+/// ST、PUSH、POPは未初期化のまま運ぶだけで警告しない (RPUSH/RPOPで騒がないように)
+pub struct ShadowMemory {
+    memory: Vec<Option<Undefined>>,
+    gr: [Option<Undefined>; 8],
+    pub warnings: Vec<UninitRead>,
+    reported: HashSet<(u16, Location)>,
+}
+
+impl Default for ShadowMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShadowMemory {
+    /// メモリもGRもすべて未初期化
+    pub fn new() -> Self {
+        let undefined = |origin| Some(Undefined { origin, moves: Vec::new() });
+        ShadowMemory {
+            memory: (0..=0xFFFF).map(|a| undefined(Location::Memory(a))).collect(),
+            gr: std::array::from_fn(|r| undefined(Location::Register(r as u8))),
+            warnings: Vec::new(),
+            reported: HashSet::new(),
+        }
+    }
+
+    /// 初期化済みにする
+    pub fn define(&mut self, location: Location) {
+        self.set(location, None);
+    }
+
+    pub fn define_range(&mut self, start: u16, len: u16) {
+        for i in 0..len {
+            self.define(Location::Memory(start.wrapping_add(i)));
+        }
+    }
+
+    pub fn is_defined(&self, location: Location) -> bool {
+        self.get(location).is_none()
+    }
+
+    fn get(&self, location: Location) -> Option<Undefined> {
+        match location {
+            Location::Memory(addr) => self.memory[addr as usize].clone(),
+            Location::Register(r) => self.gr[r as usize].clone(),
+        }
+    }
+
+    fn set(&mut self, location: Location, value: Option<Undefined>) {
+        match location {
+            Location::Memory(addr) => self.memory[addr as usize] = value,
+            Location::Register(r) => self.gr[r as usize] = value,
+        }
+    }
+
+    /// 値を使う 未初期化なら警告して来歴を伸ばす
+    fn read(&mut self, pr: u16, opcode: u8, location: Location) -> Option<Undefined> {
+        let value = self.get(location)?;
+        if self.reported.insert((pr, location)) {
+            self.warnings.push(UninitRead { pr, opcode, location, value: value.clone() });
+        }
+        Some(value.moved(pr))
+    }
+
+    /// 値を運ぶだけ
+    fn carry(&self, pr: u16, location: Location) -> Option<Undefined> {
+        self.get(location).map(|v| v.moved(pr))
+    }
+
+    /// EXECUTEの最初に呼ぶ 命令が読み書きする場所の影を更新する
+    pub fn execute(&mut self, state: &CPUState) {
+        let d = &state.decoder_state;
+        let op = d.opcode;
+        // 2語命令ならPRは2語目を指している
+        let pr = if d.w2 { state.pr.wrapping_sub(1) } else { state.pr };
+        let (r1, r2) = (Location::Register(d.r1), Location::Register(d.r2));
+        let ea = Location::Memory(state.gen_addr);
+        let sp = state.sp;
+        // 指標レジスタ PUSHは運ぶだけ
+        let index = if d.w2 && d.r2 != 0 && op != instruction::w2::PUSH { self.read(pr, op, r2) } else { None };
+
+        match op {
+            instruction::w2::LD => {
+                let v = self.read(pr, op, ea);
+                self.set(r1, v);
+            }
+            instruction::w2::ST => {
+                let v = self.carry(pr, r1);
+                self.set(ea, v);
+            }
+            instruction::w2::LAD => self.set(r1, index),
+            instruction::w2::ADDA
+            | instruction::w2::SUBA
+            | instruction::w2::ADDL
+            | instruction::w2::SUBL
+            | instruction::w2::AND
+            | instruction::w2::OR
+            | instruction::w2::XOR => {
+                let a = self.read(pr, op, r1);
+                let b = self.read(pr, op, ea);
+                self.set(r1, a.or(b));
+            }
+            instruction::w2::CPA | instruction::w2::CPL => {
+                self.read(pr, op, r1);
+                self.read(pr, op, ea);
+            }
+            instruction::w2::SLA | instruction::w2::SRA | instruction::w2::SLL | instruction::w2::SRL => {
+                let a = self.read(pr, op, r1);
+                self.set(r1, a.or(index));
+            }
+            instruction::w2::PUSH => {
+                let v = if d.r2 != 0 { self.carry(pr, r2) } else { None };
+                self.set(Location::Memory(sp.wrapping_sub(1)), v);
+            }
+            instruction::w2::CALL => self.define(Location::Memory(sp.wrapping_sub(1))),
+            instruction::w2::SVC if state.gen_addr == svc::OUT => {
+                let len_addr = state.gr.get(2);
+                if self.read(pr, op, Location::Memory(len_addr)).is_none() {
+                    let buf = state.gr.get(1);
+                    let len = state.memory.0[len_addr as usize];
+                    // 最初の1語だけ知らせる
+                    let first = (0..len).map(|i| Location::Memory(buf.wrapping_add(i))).find(|&l| !self.is_defined(l));
+                    if let Some(location) = first {
+                        self.read(pr, op, location);
+                    }
+                }
+            }
+            instruction::w1::LD => {
+                let v = self.read(pr, op, r2);
+                self.set(r1, v);
+            }
+            instruction::w1::ADDA
+            | instruction::w1::SUBA
+            | instruction::w1::ADDL
+            | instruction::w1::SUBL
+            | instruction::w1::AND
+            | instruction::w1::OR
+            | instruction::w1::XOR => {
+                let a = self.read(pr, op, r1);
+                let b = self.read(pr, op, r2);
+                self.set(r1, a.or(b));
+            }
+            instruction::w1::CPA | instruction::w1::CPL => {
+                self.read(pr, op, r1);
+                self.read(pr, op, r2);
+            }
+            instruction::w1::POP => {
+                let v = self.carry(pr, Location::Memory(sp));
+                self.set(r1, v);
+            }
+            instruction::w1::RET => {
+                self.read(pr, op, Location::Memory(sp));
+            }
+            _ => {}
+        }
+    }
+
+    /// ホストがSVCを処理した後に呼ぶ INが書いた所を初期化済みにする
+    pub fn after_svc(&mut self, code: u16, state: &CPUState) {
+        if code == svc::IN {
+            let len_addr = state.gr.get(2);
+            self.define(Location::Memory(len_addr));
+            let len = state.memory.0[len_addr as usize];
+            if len != 0xFFFF {
+                self.define_range(state.gr.get(1), len);
+            }
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::BTreeMap;
+
+use crate::emurator::commet2::{bus::AccessKind, callstack::CallStack, cpu::UpdateNotify, state::CPUState};
+
+/// スタックの範囲を見張り、使った深さを数える
+/// CPUの`stack`に入れておくと、SPが`limit`より下に来たらあふれ、戻り番地を降ろした後よりさらに上に来たら底抜けで止める
+/// 深さはローダーが積んだ戻り番地を含めた語数
+/// ルーチンごとの深さはそのルーチン自身が積んだ分だけ CALLで積んだ戻り番地は呼んだ側に数える
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackGuard {
+    /// スタックに使ってよい最も低い番地 ふつうはプログラムの直後
+    pub limit: u16,
+    /// SPの初期値 ローダーが積んだ戻り番地を指す
+    pub top: u16,
+    /// SPのいちばん低かった値
+    pub lowest: u16,
+    /// いちばん外のルーチンの先頭番地 中のルーチンはCPUの`calls`から見る
+    pub entry: Option<u16>,
+    /// ルーチンの先頭番地ごとの、自身の最大の深さ
+    pub usage: BTreeMap<u16, u16>,
+    /// 前のサイクルのSPの位置 #FFFFから0に回っても続けて数えるので`top`+1を超えられる
+    pos: u32,
+    /// 最後に見たCPUの`calls`の`exits`
+    exits: u64,
+}
+
+impl StackGuard {
+    pub fn new(limit: u16, top: u16) -> Self {
+        StackGuard { limit, top, lowest: top, entry: None, usage: BTreeMap::new(), pos: top as u32, exits: 0 }
+    }
+
+    /// いちばん深かった時の語数
+    pub fn max_depth(&self) -> u16 {
+        (self.top as u32 + 1 - self.lowest as u32) as u16
+    }
+
+    /// 1サイクル実行した後に呼ぶ 範囲を出たら理由とアクセスの種類
+    /// `calls`はそのサイクルの後の呼び出しの並び
+    pub fn record(&mut self, state: &CPUState, notify: &UpdateNotify, calls: &CallStack) -> Option<(&'static str, AccessKind)> {
+        if matches!(notify, UpdateNotify::END) {
+            return None;
+        }
+        // 最初のルーチン (またはいちばん外から戻った後)
+        let entry = *self.entry.get_or_insert(state.pr);
+        // SPは1サイクルに少ししか動かないので前の位置からの差で進める
+        let delta = state.sp.wrapping_sub(self.pos as u16) as i16;
+        let Some(pos) = self.pos.checked_add_signed(delta as i32) else {
+            return Some(("stack overflow", AccessKind::Write));
+        };
+        self.pos = pos;
+        if pos > self.top as u32 + 1 {
+            return Some(("stack underflow", AccessKind::Read));
+        }
+        if pos < self.limit as u32 {
+            return Some(("stack overflow", AccessKind::Write));
+        }
+        if pos < self.lowest as u32 {
+            self.lowest = pos as u16;
+        }
+        let (routine, base) = calls.frames.last().map_or((entry, self.top), |f| (f.target, f.sp));
+        let used = self.usage.entry(routine).or_default();
+        *used = (*used).max((base as u32).saturating_sub(pos) as u16);
+
+        if calls.exits != self.exits {
+            self.exits = calls.exits;
+            self.entry = None;
+        }
+        None
+    }
+}
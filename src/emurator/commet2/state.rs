@@ -25,20 +25,21 @@ pub struct CPUState {
     pub fr: [bool; 3],
     /// デコーダーのデコード結果
     pub decoder_state: DecResult,
+    /// PRがこの番地になったらフェッチせずに実行を終える
+    /// ローダーが最後のRETの戻り先にする
+    pub exit_addr: Option<u16>,
+    /// `commet2_step`で進めたサイクル数 止まった後は数えない
+    pub cycle: u64,
 }
 
 impl CPUState {
     pub fn next_line(&mut self) {
         // プログラムレジスタを次のアドレスに進める
-        self.pr = unsafe {
-            self.pr.unchecked_add(1)
-        };
+        self.pr = self.pr.wrapping_add(1);
     }
 
     pub fn next_cycle(&mut self) {
-        self.pr = unsafe {
-            self.pr.unchecked_add(1)
-        };
+        self.pr = self.pr.wrapping_add(1);
         self.machine_cycle = machine_cycle::FETCH; // マシンサイクルはフェッチにリセット
         self.step_cycle = 0; // 各ステップのサイクルはリセット
     }
@@ -157,8 +158,16 @@ impl CPUState {
                 r1: 0,
                 r2: 0,
                 addr: 0,
-            }
+            },
+            exit_addr: None,
+            cycle: 0,
         }
     }
     
-}
\ No newline at end of file
+}
+
+impl Default for CPUState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
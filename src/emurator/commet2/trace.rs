@@ -0,0 +1,32 @@
+use std::sync::{Arc, Mutex};
+
+use crate::emurator::commet2::{cpu::{ExecEvent, Tracer}, state::CPUState};
+
+/// 起きたことをサイクル数と一緒にためる
+/// CPUに渡した後も`clone`したもので読める
+#[derive(Debug, Clone, Default)]
+pub struct TraceRecorder {
+    events: Arc<Mutex<Vec<(u64, ExecEvent)>>>,
+    /// 命令の取り出しも記録する
+    pub instructions: bool,
+}
+
+impl TraceRecorder {
+    pub fn new(instructions: bool) -> Self {
+        TraceRecorder { events: Arc::default(), instructions }
+    }
+
+    /// これまでの記録
+    pub fn events(&self) -> Vec<(u64, ExecEvent)> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl Tracer for TraceRecorder {
+    fn event(&mut self, event: ExecEvent, state: &CPUState) {
+        if matches!(event, ExecEvent::Fetch { .. }) && !self.instructions {
+            return;
+        }
+        self.events.lock().unwrap().push((state.cycle, event));
+    }
+}
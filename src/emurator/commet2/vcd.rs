@@ -0,0 +1,139 @@
+use std::io::{self, Write};
+
+use crate::emurator::commet2::{cpu::UpdateNotify, state::CPUState};
+
+/// 書き出す信号の名前とビット幅
+pub const SIGNALS: [(&str, u8); 21] = [
+    ("machine_cycle", 8),
+    ("step_cycle", 8),
+    ("PR", 16),
+    ("SP", 16),
+    ("MAR", 16),
+    ("MDR", 16),
+    ("IR0", 16),
+    ("IR1", 16),
+    ("GENADDR", 16),
+    ("GR0", 16),
+    ("GR1", 16),
+    ("GR2", 16),
+    ("GR3", 16),
+    ("GR4", 16),
+    ("GR5", 16),
+    ("GR6", 16),
+    ("GR7", 16),
+    ("OF", 1),
+    ("SF", 1),
+    ("ZF", 1),
+    ("opcode", 8),
+];
+
+/// 信号の値 `SIGNALS`の順
+pub fn signal_values(state: &CPUState) -> [u16; 21] {
+    let gr = |i| state.gr.get(i);
+    [
+        state.machine_cycle as u16,
+        state.step_cycle as u16,
+        state.pr,
+        state.sp,
+        state.mar,
+        state.mdr,
+        state.ir[0],
+        state.ir[1],
+        state.gen_addr,
+        gr(0),
+        gr(1),
+        gr(2),
+        gr(3),
+        gr(4),
+        gr(5),
+        gr(6),
+        gr(7),
+        state.fr[0] as u16,
+        state.fr[1] as u16,
+        state.fr[2] as u16,
+        state.decoder_state.opcode as u16,
+    ]
+}
+
+/// マイクロサイクルごとのレジスタの変化をValue Change Dumpで書き出す
+/// 1サイクルを時刻1つにする GTKWaveなどで波形として見られる
+///
+/// ```ignore
+/// let mut vcd = VcdWriter::new(file, &cpu.state)?;
+/// while cpu.state.machine_cycle != machine_cycle::END {
+///     let notify = cpu.commet2_step();
+///     vcd.record(&cpu.state, &notify)?;
+/// }
+/// vcd.finish()?;
+/// ```
+pub struct VcdWriter<W: Write> {
+    out: W,
+    /// 今の時刻 = 記録したサイクル数
+    pub time: u64,
+    last: [u16; 21],
+}
+
+impl<W: Write> VcdWriter<W> {
+    /// ヘッダと初期値を書く
+    pub fn new(mut out: W, state: &CPUState) -> io::Result<Self> {
+        writeln!(out, "$version x-casl2 COMET2 $end")?;
+        writeln!(out, "$timescale 1ns $end")?;
+        writeln!(out, "$scope module comet2 $end")?;
+        for (i, (name, width)) in SIGNALS.iter().enumerate() {
+            writeln!(out, "$var wire {} {} {} $end", width, id(i), name)?;
+        }
+        writeln!(out, "$upscope $end")?;
+        writeln!(out, "$enddefinitions $end")?;
+        let last = signal_values(state);
+        writeln!(out, "#0")?;
+        writeln!(out, "$dumpvars")?;
+        for (i, &value) in last.iter().enumerate() {
+            write_value(&mut out, i, value)?;
+        }
+        writeln!(out, "$end")?;
+        Ok(VcdWriter { out, time: 0, last })
+    }
+
+    /// 1サイクル実行した後に呼ぶ 変わった信号だけ書く
+    pub fn record(&mut self, state: &CPUState, notify: &UpdateNotify) -> io::Result<()> {
+        let values = signal_values(state);
+        if matches!(notify, UpdateNotify::END) && values == self.last {
+            // 止まった後は時間を進めない
+            return Ok(());
+        }
+        self.time += 1;
+        let mut stamped = false;
+        for (i, (&value, last)) in values.iter().zip(self.last.iter_mut()).enumerate() {
+            if value != *last {
+                if !stamped {
+                    writeln!(self.out, "#{}", self.time)?;
+                    stamped = true;
+                }
+                write_value(&mut self.out, i, value)?;
+                *last = value;
+            }
+        }
+        Ok(())
+    }
+
+    /// 最後の時刻を書いて出力先を返す
+    pub fn finish(mut self) -> io::Result<W> {
+        writeln!(self.out, "#{}", self.time + 1)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// 信号の識別子 `!`から順に1文字
+fn id(i: usize) -> char {
+    (b'!' + i as u8) as char
+}
+
+fn write_value<W: Write>(out: &mut W, i: usize, value: u16) -> io::Result<()> {
+    let width = SIGNALS[i].1 as usize;
+    if width == 1 {
+        writeln!(out, "{}{}", value & 1, id(i))
+    } else {
+        writeln!(out, "b{:0width$b} {}", value, id(i), width = width)
+    }
+}
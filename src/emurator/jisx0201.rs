@@ -0,0 +1,57 @@
+// JIS X 0201 (ローマ字 + 半角カタカナ) とUnicodeの対応
+//
+// CASL2の文字は1語に1文字のJIS X 0201コードで持つ
+// - 0x20..=0x7E: ASCIIと同じ ただし0x5Cは円記号、0x7Eはオーバーライン
+// - 0xA1..=0xDF: 半角カタカナ (U+FF61..=U+FF9F)
+
+/// 対応のない文字を変換するときの代わり `?`
+pub const SUBSTITUTE: u8 = 0x3F;
+
+/// コードから文字にする 定義されていないコードならNone
+pub fn decode_byte(code: u8) -> Option<char> {
+    match code {
+        0x5C => Some('¥'),
+        0x7E => Some('‾'),
+        0x00..=0x7F => Some(code as char),
+        0xA1..=0xDF => char::from_u32(0xFF61 + (code - 0xA1) as u32),
+        _ => None,
+    }
+}
+
+/// 文字をコードにする 対応のない文字ならNone
+/// `\`と`~`はそれぞれ円記号とオーバーラインのコードにする
+pub fn encode_char(c: char) -> Option<u8> {
+    match c {
+        '¥' | '\\' => Some(0x5C),
+        '‾' | '~' => Some(0x7E),
+        '\u{00}'..='\u{7F}' => Some(c as u8),
+        '\u{FF61}'..='\u{FF9F}' => Some((c as u32 - 0xFF61) as u8 + 0xA1),
+        _ => None,
+    }
+}
+
+/// 文字列を1語1文字にする 対応のない文字があればその文字を返す
+pub fn encode(text: &str) -> Result<Vec<u16>, char> {
+    text.chars().map(|c| encode_char(c).map(u16::from).ok_or(c)).collect()
+}
+
+/// 文字列を1語1文字にする 対応のない文字は`?`にする
+pub fn encode_lossy(text: &str) -> Vec<u16> {
+    text.chars().map(|c| encode_char(c).unwrap_or(SUBSTITUTE) as u16).collect()
+}
+
+/// 1語1文字の並びを文字列にする 範囲外の語は置換文字にする
+pub fn decode_lossy(words: &[u16]) -> String {
+    words
+        .iter()
+        .map(|&w| u8::try_from(w).ok().and_then(decode_byte).unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// メモリの表示用 表示できる文字なら返す
+pub fn printable(word: u16) -> Option<char> {
+    match word {
+        0x20..=0x7E | 0xA1..=0xDF => decode_byte(word as u8),
+        _ => None,
+    }
+}
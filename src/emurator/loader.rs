@@ -0,0 +1,96 @@
+use crate::emurator::{casl2::{code_gen::CodeGenerator, err::Casl2AssemblerError, linker::{link, LinkedImage}, object::ObjectFile}, commet2::cpu::CPU};
+
+/// 最後のRETの戻り先の既定値
+pub const DEFAULT_SENTINEL: u16 = 0xFFFF;
+/// SPの初期値の既定値
+pub const DEFAULT_STACK_TOP: u16 = 0xFFFF;
+
+/// プログラムをメモリに置いてOSから呼ばれたのと同じ状態のCPUを作る
+///
+/// 1. イメージを`base`から置いてPRを実行開始番地にする
+/// 2. SPを`stack_top`にして戻り番地`sentinel`を積む
+/// 3. GR1からGR7に引数を入れる
+///
+/// 最後のRETでPRが`sentinel`になるとCPUは`machine_cycle::END`で止まる
+#[derive(Debug, Clone)]
+pub struct Loader {
+    /// 配置する先頭番地
+    pub base: u16,
+    /// SPの初期値 スタックはここから下に伸びる
+    pub stack_top: u16,
+    /// 最後のRETの戻り先
+    pub sentinel: u16,
+    /// GR1からGR7の初期値
+    pub args: [u16; 7],
+}
+
+impl Default for Loader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Loader {
+    pub fn new() -> Self {
+        Loader {
+            base: 0,
+            stack_top: DEFAULT_STACK_TOP,
+            sentinel: DEFAULT_SENTINEL,
+            args: [0; 7],
+        }
+    }
+
+    /// GRn (1から7) の初期値を決める
+    pub fn arg(mut self, gr: u8, value: u16) -> Self {
+        if (1..=7).contains(&gr) {
+            self.args[gr as usize - 1] = value;
+        }
+        self
+    }
+
+    /// 語の並びを`base`から置く `entry`は`base`からの位置
+    pub fn load_words(&self, code: &[u16], entry: u16) -> Result<Box<CPU>, Casl2AssemblerError> {
+        let base = self.base as usize;
+        if base + code.len() > 0x10000 {
+            return Err(Casl2AssemblerError::OutOfMemory);
+        }
+        // 戻り番地を積む場所がプログラムに重ならないか
+        let slot = self.stack_top.wrapping_sub(1) as usize;
+        if (base..base + code.len()).contains(&slot) {
+            return Err(Casl2AssemblerError::RuntimeError(format!("Stack top #{:04X} overlaps the program", self.stack_top)));
+        }
+        // 戻り番地がプログラムの中だと最後のRETで止まらずに続きを実行してしまう
+        if (base..base + code.len()).contains(&(self.sentinel as usize)) {
+            return Err(Casl2AssemblerError::RuntimeError(format!("Sentinel #{:04X} is inside the program", self.sentinel)));
+        }
+
+        let mut cpu = Box::new(CPU::new());
+        let state = &mut cpu.state;
+        state.memory.0[base..base + code.len()].copy_from_slice(code);
+        state.pr = self.base.wrapping_add(entry);
+        state.sp = self.stack_top.wrapping_sub(1);
+        state.memory.0[state.sp as usize] = self.sentinel;
+        state.exit_addr = Some(self.sentinel);
+        for (i, &value) in self.args.iter().enumerate() {
+            *state.gr.get_mut(i as u8 + 1) = value;
+        }
+        Ok(cpu)
+    }
+
+    /// リンクしたイメージを置く `base`はイメージのものを使う
+    pub fn load_image(&self, image: &LinkedImage) -> Result<Box<CPU>, Casl2AssemblerError> {
+        let loader = Loader { base: image.base, ..self.clone() };
+        loader.load_words(&image.code, image.entry.wrapping_sub(image.base))
+    }
+
+    /// オブジェクトを`base`にリンクして置く
+    pub fn load_objects(&self, objects: &[ObjectFile]) -> Result<Box<CPU>, Casl2AssemblerError> {
+        self.load_image(&link(objects, self.base)?)
+    }
+
+    /// ソースをアセンブルして`base`に置く
+    pub fn load_source(&self, src: &str) -> Result<Box<CPU>, Casl2AssemblerError> {
+        let objects = CodeGenerator::from_source("", src)?.generate_standalone()?;
+        self.load_objects(&objects)
+    }
+}
@@ -1,2 +1,4 @@
 pub mod commet2;
-pub mod casl2;
\ No newline at end of file
+pub mod casl2;
+pub mod loader;
+pub mod jisx0201;
@@ -0,0 +1,252 @@
+// Rustのテストからサブルーチンを呼んで確かめるためのもの
+//
+// ```ignore
+// let mut h = Harness::new(src).unwrap();
+// h.set("GR1", 3).set("DAT", 4).call("ADD");
+// h.assert_reg("GR0", 7);
+// ```
+//
+// assert_*が失敗すると、呼ぶ前からのCPUの状態の変化と最近実行した命令を出してpanicする
+
+use std::collections::VecDeque;
+
+use crate::{
+    emurator::{casl2::{code_gen::CodeGenerator, err::Casl2AssemblerError}, commet2::{callstack::CallStack, console::BufferConsole, cpu::CPU, prefix::machine_cycle}, loader::Loader},
+    grader::{parse_register, resolve_location, step, unit::FLAGS, Outcome, DEFAULT_MAX_STEPS},
+};
+
+/// 覚えておく命令の数
+pub const TRACE_LEN: usize = 16;
+
+/// レジスタとフラグの値
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    pub gr: [u16; 8],
+    pub sp: u16,
+    pub pr: u16,
+    pub fr: [bool; 3],
+}
+
+impl Snapshot {
+    pub fn of(cpu: &CPU) -> Self {
+        let mut gr = [0; 8];
+        for (i, r) in gr.iter_mut().enumerate() {
+            *r = cpu.state.gr.get(i as u8);
+        }
+        Snapshot { gr, sp: cpu.state.sp, pr: cpu.state.pr, fr: cpu.state.fr }
+    }
+}
+
+pub struct Harness {
+    pub code_gen: CodeGenerator,
+    pub cpu: Box<CPU>,
+    pub console: BufferConsole,
+    /// 呼ぶときのSPと戻り番地
+    pub loader: Loader,
+    /// 置いたプログラムの直後の番地
+    pub image_end: u16,
+    pub max_steps: usize,
+    /// 最後に呼んだときに実行した命令の数
+    pub steps: usize,
+    /// 最後に呼ぶ直前の状態
+    pub before: Snapshot,
+    /// 最近実行した命令の番地
+    pub trace: VecDeque<u16>,
+}
+
+impl Harness {
+    /// アセンブルしてアドレス0から置く
+    pub fn new(src: &str) -> Result<Self, Casl2AssemblerError> {
+        Self::load(src, Loader::new())
+    }
+
+    /// アセンブルしてローダーの`base`から置く
+    pub fn load(src: &str, loader: Loader) -> Result<Self, Casl2AssemblerError> {
+        let mut code_gen = CodeGenerator::from_source("", src)?;
+        code_gen.base = loader.base;
+        let objects = code_gen.generate_standalone()?;
+        let image_end = loader.base.wrapping_add(objects.iter().map(|o| o.code.len()).sum::<usize>() as u16);
+        let mut cpu = loader.load_objects(&objects)?;
+        let console = BufferConsole::default();
+        cpu.supervisor = Some(Box::new(console.clone()));
+        let before = Snapshot::of(&cpu);
+        Ok(Harness { code_gen, cpu, console, loader, image_end, max_steps: DEFAULT_MAX_STEPS, steps: 0, before, trace: VecDeque::new() })
+    }
+
+    /// ラベル (`PROG.LABEL`, `LABEL+n`, `#0100`も可) の番地
+    pub fn addr(&self, location: &str) -> u16 {
+        resolve_location(&self.code_gen, location).unwrap_or_else(|| panic!("Unknown label: {}", location))
+    }
+
+    /// `GR0`..`GR7`ならレジスタに、それ以外ならラベルの番地に入れる 負の数も書ける
+    /// 1語に入らない値 (-32768..=65535の外) ならpanicする
+    pub fn set(&mut self, name: &str, value: i32) -> &mut Self {
+        assert!((-32768..=65535).contains(&value), "{}: value out of range: {}", name, value);
+        let value = value as u16;
+        match parse_register(name) {
+            Some(gr) => *self.cpu.state.gr.get_mut(gr) = value,
+            None => self.cpu.state.memory.0[self.addr(name) as usize] = value,
+        }
+        self
+    }
+
+    /// ラベルの番地から続けて書く
+    pub fn set_words(&mut self, location: &str, words: &[u16]) -> &mut Self {
+        let addr = self.addr(location);
+        for (i, &w) in words.iter().enumerate() {
+            self.cpu.state.memory.0[addr.wrapping_add(i as u16) as usize] = w;
+        }
+        self
+    }
+
+    /// INで読む行を足す
+    pub fn input(&mut self, line: &str) -> &mut Self {
+        self.console.push_input(line);
+        self
+    }
+
+    /// `GR0`..`GR7`, `SP`, `PR`
+    pub fn reg(&self, name: &str) -> u16 {
+        match name.to_ascii_uppercase().as_str() {
+            "SP" => self.cpu.state.sp,
+            "PR" => self.cpu.state.pr,
+            _ => self.cpu.state.gr.get(parse_register(name).unwrap_or_else(|| panic!("Unknown register: {}", name))),
+        }
+    }
+
+    /// `OF`, `SF`, `ZF`
+    pub fn flag(&self, name: &str) -> bool {
+        let i = FLAGS.iter().position(|f| f.eq_ignore_ascii_case(name)).unwrap_or_else(|| panic!("Unknown flag: {}", name));
+        self.cpu.state.fr[i]
+    }
+
+    pub fn mem(&self, location: &str) -> u16 {
+        self.cpu.state.memory.0[self.addr(location) as usize]
+    }
+
+    pub fn words(&self, location: &str, len: usize) -> Vec<u16> {
+        let addr = self.addr(location);
+        (0..len).map(|i| self.cpu.state.memory.0[addr.wrapping_add(i as u16) as usize]).collect()
+    }
+
+    /// これまでOUTで書かれた行
+    pub fn output(&self) -> Vec<String> {
+        self.console.output()
+    }
+
+    /// ラベルを呼んで戻ってくるまで実行する 戻ってこなければpanic
+    #[track_caller]
+    pub fn call(&mut self, label: &str) -> &mut Self {
+        if let Err(outcome) = self.try_call(label) {
+            self.fail(&outcome.messages().join("\n"));
+        }
+        self
+    }
+
+    /// ラベルを呼んで戻ってくるまで実行する 時間切れや実行エラーならErr
+    /// 戻り番地はローダーの`sentinel`、SPはローダーの`stack_top`からやり直す
+    pub fn try_call(&mut self, label: &str) -> Result<(), Outcome> {
+        let entry = self.addr(label);
+        let state = &mut self.cpu.state;
+        state.sp = self.loader.stack_top.wrapping_sub(1);
+        state.memory.0[state.sp as usize] = self.loader.sentinel;
+        state.pr = entry;
+        state.machine_cycle = machine_cycle::FETCH;
+        state.step_cycle = 0;
+        self.cpu.fault = None;
+        self.cpu.calls = CallStack::new();
+        self.before = Snapshot::of(&self.cpu);
+        self.trace.clear();
+        self.steps = 0;
+
+        while self.cpu.state.machine_cycle != machine_cycle::END {
+            if self.steps >= self.max_steps {
+                return Err(Outcome::Timeout);
+            }
+            let pr = self.cpu.state.pr;
+            step(&mut self.cpu)?;
+            // 戻り番地に着いて止まったのは数えない
+            if self.cpu.state.machine_cycle != machine_cycle::END {
+                if self.trace.len() == TRACE_LEN {
+                    self.trace.pop_front();
+                }
+                self.trace.push_back(pr);
+                self.steps += 1;
+            }
+        }
+        Ok(())
+    }
+
+    #[track_caller]
+    pub fn assert_reg(&self, name: &str, expected: i32) {
+        let (expected, actual) = (expected as u16, self.reg(name));
+        if actual != expected {
+            self.fail(&format!("{}: expected #{:04X}, got #{:04X}", name, expected, actual));
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_mem(&self, location: &str, expected: &[u16]) {
+        let actual = self.words(location, expected.len());
+        if actual != expected {
+            let show = |words: &[u16]| words.iter().map(|w| format!("#{:04X}", w)).collect::<Vec<_>>().join(",");
+            self.fail(&format!("{}: expected {}, got {}", location, show(expected), show(&actual)));
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_flag(&self, name: &str, expected: bool) {
+        if self.flag(name) != expected {
+            self.fail(&format!("{}: expected {}, got {}", name, expected as u8, !expected as u8));
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_output(&self, expected: &[&str]) {
+        let actual = self.output();
+        if actual != expected {
+            self.fail(&format!("output: expected {:?}, got {:?}", expected, actual));
+        }
+    }
+
+    /// 呼ぶ前と同じ値か
+    #[track_caller]
+    pub fn assert_preserved(&self, names: &[&str]) {
+        for name in names {
+            let gr = parse_register(name).unwrap_or_else(|| panic!("Unknown register: {}", name));
+            let (before, actual) = (self.before.gr[gr as usize], self.reg(name));
+            if actual != before {
+                self.fail(&format!("{} not preserved: was #{:04X}, got #{:04X}", name, before, actual));
+            }
+        }
+    }
+
+    /// 呼ぶ前から変わったレジスタと最近実行した命令
+    pub fn report(&self) -> String {
+        let now = Snapshot::of(&self.cpu);
+        let mut out = String::from("CPU state (before call -> now):\n");
+        let mut row = |name: String, before: u16, now: u16| {
+            let mark = if before != now { "  *" } else { "" };
+            out += &format!("  {:<4} #{:04X} -> #{:04X}{}\n", name, before, now, mark);
+        };
+        for i in 0..8 {
+            row(format!("GR{}", i), self.before.gr[i], now.gr[i]);
+        }
+        row("SP".to_string(), self.before.sp, now.sp);
+        row("PR".to_string(), self.before.pr, now.pr);
+        let flags = |fr: [bool; 3]| FLAGS.iter().zip(fr).map(|(f, v)| format!("{}={}", f, v as u8)).collect::<Vec<_>>().join(" ");
+        out += &format!("  FR   {} -> {}\n", flags(self.before.fr), flags(now.fr));
+
+        out += &format!("recent trace ({} steps):\n", self.steps);
+        for &addr in &self.trace {
+            let code = self.code_gen.source_map.describe(addr).unwrap_or_default();
+            out += &format!("  #{:04X} {:<12} {}\n", addr, self.code_gen.symbolize(addr), code);
+        }
+        out
+    }
+
+    #[track_caller]
+    fn fail(&self, message: &str) -> ! {
+        panic!("{}\n{}", message, self.report());
+    }
+}
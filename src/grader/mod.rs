@@ -0,0 +1,360 @@
+// 課題の採点
+//
+// 提出されたソースをテストケースごとに新しいCPUで実行して、出力、レジスタ、メモリを比べる
+// 結果はJSONとJUnit XMLで出せる (report.rs)
+
+pub mod report;
+pub mod unit;
+pub mod harness;
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use serde_json::Value;
+
+use crate::emurator::{casl2::{backtrace::describe_fault, code_gen::CodeGenerator, err::Casl2AssemblerError, object::ObjectFile}, commet2::{console::BufferConsole, cpu::{CPUExecution, CPU}, prefix::machine_cycle, stack::StackGuard}, loader::Loader};
+
+/// ステップ数の上限の既定値
+pub const DEFAULT_MAX_STEPS: usize = 100_000;
+
+/// テストケース
+///
+/// JSONでは次のように書く 期待値は書いたものだけ比べる
+/// ```json
+/// {
+///   "name": "add",
+///   "input": ["12"],
+///   "registers": { "GR1": 3 },
+///   "memory": { "DATA": [1, 2], "#0100": 5 },
+///   "expected": { "output": ["15"], "registers": { "GR0": -1 }, "memory": { "ANS": 7 } },
+///   "max_steps": 10000,
+///   "max_stack": 20
+/// }
+/// ```
+/// `max_stack`はスタックの最大の深さ (語数) の上限
+/// スタックがプログラムまで伸びたらFault
+#[derive(Debug, Clone, Default)]
+pub struct TestCase {
+    pub name: String,
+    /// INで読む行
+    pub input: Vec<String>,
+    /// 実行前に入れる (レジスタ番号, 値)
+    pub registers: Vec<(u8, u16)>,
+    /// 実行前に書く (場所, 値) 場所は`resolve_location`の書き方
+    pub memory: Vec<(String, Vec<u16>)>,
+    /// OUTで書かれるはずの行
+    pub expected_output: Option<Vec<String>>,
+    pub expected_registers: Vec<(u8, u16)>,
+    pub expected_memory: Vec<(String, Vec<u16>)>,
+    pub max_steps: usize,
+    pub max_stack: Option<u16>,
+}
+
+impl TestCase {
+    pub fn from_json(value: &Value) -> Result<Self, Casl2AssemblerError> {
+        let err = |msg: String| Casl2AssemblerError::ParseError(msg);
+        let name = value["name"].as_str().ok_or_else(|| err("Test case without name".to_string()))?.to_string();
+        let context = |msg: String| err(format!("{} in test case {}", msg, name));
+        let strings = |v: &Value| -> Result<Vec<String>, Casl2AssemblerError> {
+            v.as_array()
+                .ok_or_else(|| context(format!("Expected a list of strings: {}", v)))?
+                .iter()
+                .map(|s| s.as_str().map(|s| s.to_string()).ok_or_else(|| context(format!("Expected a string: {}", s))))
+                .collect()
+        };
+        let registers = |v: &Value| -> Result<Vec<(u8, u16)>, Casl2AssemblerError> {
+            let Some(map) = v.as_object() else { return Ok(vec![]) };
+            map.iter()
+                .map(|(k, v)| {
+                    let gr = parse_register(k).ok_or_else(|| context(format!("Unknown register: {}", k)))?;
+                    Ok((gr, json_word(v).ok_or_else(|| context(format!("Invalid value: {}", v)))?))
+                })
+                .collect()
+        };
+        let memory = |v: &Value| -> Result<Vec<(String, Vec<u16>)>, Casl2AssemblerError> {
+            let Some(map) = v.as_object() else { return Ok(vec![]) };
+            map.iter()
+                .map(|(k, v)| {
+                    let words = match v.as_array() {
+                        Some(list) => list.iter().map(json_word).collect(),
+                        None => json_word(v).map(|w| vec![w]),
+                    };
+                    Ok((k.clone(), words.ok_or_else(|| context(format!("Invalid value: {}", v)))?))
+                })
+                .collect()
+        };
+
+        let expected = &value["expected"];
+        Ok(TestCase {
+            input: if value["input"].is_null() { vec![] } else { strings(&value["input"])? },
+            registers: registers(&value["registers"])?,
+            memory: memory(&value["memory"])?,
+            expected_output: if expected["output"].is_null() { None } else { Some(strings(&expected["output"])?) },
+            expected_registers: registers(&expected["registers"])?,
+            expected_memory: memory(&expected["memory"])?,
+            max_steps: value["max_steps"].as_u64().map_or(DEFAULT_MAX_STEPS, |n| n as usize),
+            max_stack: value["max_stack"].as_u64().map(|n| n as u16),
+            name,
+        })
+    }
+
+    /// テストケースの配列のJSON
+    pub fn list_from_json(text: &str) -> Result<Vec<Self>, Casl2AssemblerError> {
+        let value: Value = serde_json::from_str(text).map_err(|e| Casl2AssemblerError::ParseError(e.to_string()))?;
+        value
+            .as_array()
+            .ok_or_else(|| Casl2AssemblerError::ParseError("Expected an array of test cases".to_string()))?
+            .iter()
+            .map(Self::from_json)
+            .collect()
+    }
+}
+
+/// `GR0`から`GR7`
+pub fn parse_register(name: &str) -> Option<u8> {
+    let n = name.to_ascii_uppercase().strip_prefix("GR")?.parse::<u8>().ok()?;
+    (n <= 7).then_some(n)
+}
+
+/// 10進数 (負でもよい)、`#FFFF`、1文字の文字列
+fn json_word(value: &Value) -> Option<u16> {
+    if let Some(n) = value.as_i64() {
+        return (-32768..=65535).contains(&n).then_some(n as u16);
+    }
+    let s = value.as_str()?;
+    if let Some(hex) = s.strip_prefix('#') {
+        return u16::from_str_radix(hex, 16).ok();
+    }
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => crate::emurator::jisx0201::encode_char(c).map(u16::from),
+        _ => None,
+    }
+}
+
+/// メモリの場所 ラベル、`PROG.LABEL`、`#0100`のような番地 後ろに`+n`をつけてもよい
+pub fn resolve_location(code_gen: &CodeGenerator, location: &str) -> Option<u16> {
+    let (base, offset) = match location.split_once('+') {
+        Some((base, offset)) => (base.trim(), offset.trim().parse::<u16>().ok()?),
+        None => (location.trim(), 0),
+    };
+    let addr = match base.strip_prefix('#') {
+        Some(hex) => u16::from_str_radix(hex, 16).ok()?,
+        None => code_gen.lookup(base)?,
+    };
+    Some(addr.wrapping_add(offset))
+}
+
+/// 1つのテストケースの結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    /// 期待値と違ったところ
+    Failed(Vec<String>),
+    AssembleError(String),
+    /// ステップ数の上限まで終わらなかった
+    Timeout,
+    /// 実行できない命令など
+    Fault(String),
+    /// テストケースの誤り 知らないラベルなど
+    CaseError(String),
+}
+
+impl Outcome {
+    /// JSONとJUnitでの名前
+    pub fn status(&self) -> &'static str {
+        match self {
+            Outcome::Passed => "passed",
+            Outcome::Failed(_) => "failed",
+            Outcome::AssembleError(_) => "assemble_error",
+            Outcome::Timeout => "timeout",
+            Outcome::Fault(_) => "fault",
+            Outcome::CaseError(_) => "case_error",
+        }
+    }
+
+    /// 理由の一覧
+    pub fn messages(&self) -> Vec<String> {
+        match self {
+            Outcome::Passed => vec![],
+            Outcome::Failed(diffs) => diffs.clone(),
+            Outcome::AssembleError(msg) | Outcome::Fault(msg) | Outcome::CaseError(msg) => vec![msg.clone()],
+            Outcome::Timeout => vec!["Step limit exceeded".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseResult {
+    pub name: String,
+    pub outcome: Outcome,
+    /// 実行した命令の数
+    pub steps: usize,
+    pub output: Vec<String>,
+}
+
+/// 1人の提出物
+#[derive(Debug, Clone)]
+pub struct Submission {
+    pub student: String,
+    pub source: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct StudentResult {
+    pub student: String,
+    pub cases: Vec<CaseResult>,
+}
+
+impl StudentResult {
+    pub fn passed(&self) -> usize {
+        self.cases.iter().filter(|c| c.outcome == Outcome::Passed).count()
+    }
+}
+
+/// 提出物をすべてのテストケースで実行する
+pub fn grade(submission: &Submission, cases: &[TestCase]) -> StudentResult {
+    let assembled = CodeGenerator::from_source(&submission.student, &submission.source)
+        .and_then(|mut code_gen| Ok((code_gen.generate_standalone()?, code_gen)));
+    match assembled {
+        Ok((objects, code_gen)) => StudentResult {
+            student: submission.student.clone(),
+            cases: cases.iter().map(|case| run_case(&code_gen, &objects, case)).collect(),
+        },
+        Err(e) => not_assembled(&submission.student, cases, &e.to_string()),
+    }
+}
+
+/// アセンブルできなかった (ファイルが読めなかったなど) ときの結果 全部のケースをアセンブルエラーにする
+pub fn not_assembled(student: &str, cases: &[TestCase], message: &str) -> StudentResult {
+    let cases = cases
+        .iter()
+        .map(|case| CaseResult { name: case.name.clone(), outcome: Outcome::AssembleError(message.to_string()), steps: 0, output: vec![] })
+        .collect();
+    StudentResult { student: student.to_string(), cases }
+}
+
+/// 全員を採点する
+pub fn grade_all(submissions: &[Submission], cases: &[TestCase]) -> Vec<StudentResult> {
+    submissions.iter().map(|s| grade(s, cases)).collect()
+}
+
+/// アセンブル済みのプログラムを新しいCPUに置いて1つのテストケースを実行する
+pub fn run_case(code_gen: &CodeGenerator, objects: &[ObjectFile], case: &TestCase) -> CaseResult {
+    let console = BufferConsole::new(&case.input);
+    let mut result = CaseResult { name: case.name.clone(), outcome: Outcome::Passed, steps: 0, output: vec![] };
+    let mut cpu = match prepare(code_gen, objects, case) {
+        Ok(cpu) => cpu,
+        // 書き込む場所がないのは提出物ではなくテストケースの誤り
+        Err(Casl2AssemblerError::UnknownLabel(label)) => {
+            result.outcome = Outcome::CaseError(format!("{}: unknown label", label));
+            return result;
+        }
+        Err(e) => {
+            result.outcome = Outcome::Fault(e.to_string());
+            return result;
+        }
+    };
+    cpu.supervisor = Some(Box::new(console.clone()));
+    cpu.stack = Some(StackGuard::new(objects.iter().map(|o| o.code.len()).sum::<usize>() as u16, cpu.state.sp));
+
+    let outcome = execute(&mut cpu, case.max_steps, &mut result.steps);
+    result.output = console.output();
+    result.outcome = match outcome {
+        // CPUが止めたなら呼び出しの並びをつける
+        Some(Outcome::Fault(msg)) => match &cpu.fault {
+            Some(fault) => Outcome::Fault(describe_fault(code_gen, fault, &cpu.calls).trim_end().to_string()),
+            None => Outcome::Fault(msg),
+        },
+        Some(outcome) => outcome,
+        None => compare(code_gen, &cpu, case, &result.output),
+    };
+    result
+}
+
+fn prepare(code_gen: &CodeGenerator, objects: &[ObjectFile], case: &TestCase) -> Result<Box<CPU>, Casl2AssemblerError> {
+    let mut cpu = Loader::new().load_objects(objects)?;
+    for &(gr, value) in &case.registers {
+        *cpu.state.gr.get_mut(gr) = value;
+    }
+    for (location, words) in &case.memory {
+        write_memory(code_gen, &mut cpu, location, words)?;
+    }
+    Ok(cpu)
+}
+
+/// 場所から続けて書く
+pub fn write_memory(code_gen: &CodeGenerator, cpu: &mut CPU, location: &str, words: &[u16]) -> Result<(), Casl2AssemblerError> {
+    let addr = resolve_location(code_gen, location).ok_or_else(|| Casl2AssemblerError::UnknownLabel(location.to_string()))?;
+    for (i, &w) in words.iter().enumerate() {
+        cpu.state.memory.0[addr.wrapping_add(i as u16) as usize] = w;
+    }
+    Ok(())
+}
+
+/// 場所から続く語を期待値と比べて、違うところを返す
+pub fn diff_memory(code_gen: &CodeGenerator, cpu: &CPU, location: &str, words: &[u16]) -> Vec<String> {
+    let Some(addr) = resolve_location(code_gen, location) else {
+        return vec![format!("{}: unknown label", location)];
+    };
+    let mut diffs = vec![];
+    for (i, &expected) in words.iter().enumerate() {
+        let actual = cpu.state.memory.0[addr.wrapping_add(i as u16) as usize];
+        if actual != expected {
+            let name = if i == 0 { location.to_string() } else { format!("{}+{}", location, i) };
+            diffs.push(format!("{}: expected #{:04X}, got #{:04X}", name, expected, actual));
+        }
+    }
+    diffs
+}
+
+/// 終わるまで実行する 正常に終わればNone
+/// 実行できない命令やCPUのpanicはFault、`max_steps`命令を超えたらTimeout
+pub fn execute(cpu: &mut CPU, max_steps: usize, steps: &mut usize) -> Option<Outcome> {
+    while cpu.state.machine_cycle != machine_cycle::END {
+        if *steps >= max_steps {
+            return Some(Outcome::Timeout);
+        }
+        if let Err(outcome) = step(cpu) {
+            return Some(outcome);
+        }
+        // 戻り番地に着いて止まったのは数えない
+        if cpu.state.machine_cycle != machine_cycle::END {
+            *steps += 1;
+        }
+    }
+    None
+}
+
+/// 1命令実行する 実行できなければFault
+pub fn step(cpu: &mut CPU) -> Result<(), Outcome> {
+    let pr = cpu.state.pr;
+    catch_unwind(AssertUnwindSafe(|| cpu.casl_step())).map_err(|_| Outcome::Fault(format!("CPU stopped at #{:04X}", pr)))?;
+    match &cpu.fault {
+        Some(fault) => Err(Outcome::Fault(fault.to_string())),
+        None => Ok(()),
+    }
+}
+
+fn compare(code_gen: &CodeGenerator, cpu: &CPU, case: &TestCase, output: &[String]) -> Outcome {
+    let mut diffs = vec![];
+    if let Some(expected) = &case.expected_output
+        && expected.as_slice() != output
+    {
+        diffs.push(format!("output: expected {:?}, got {:?}", expected, output));
+    }
+    for &(gr, expected) in &case.expected_registers {
+        let actual = cpu.state.gr.get(gr);
+        if actual != expected {
+            diffs.push(format!("GR{}: expected #{:04X}, got #{:04X}", gr, expected, actual));
+        }
+    }
+    for (location, words) in &case.expected_memory {
+        diffs.extend(diff_memory(code_gen, cpu, location, words));
+    }
+    if let (Some(limit), Some(stack)) = (case.max_stack, &cpu.stack)
+        && stack.max_depth() > limit
+    {
+        diffs.push(format!("stack: expected at most {} words, used {}", limit, stack.max_depth()));
+    }
+    if diffs.is_empty() { Outcome::Passed } else { Outcome::Failed(diffs) }
+}
@@ -0,0 +1,95 @@
+use serde_json::{json, Value};
+
+use crate::grader::{Outcome, StudentResult};
+
+/// 採点結果のJSON
+pub fn to_json(results: &[StudentResult]) -> Value {
+    let students: Vec<Value> = results
+        .iter()
+        .map(|r| {
+            let cases: Vec<Value> = r
+                .cases
+                .iter()
+                .map(|c| {
+                    json!({
+                        "name": c.name,
+                        "status": c.outcome.status(),
+                        "steps": c.steps,
+                        "messages": c.outcome.messages(),
+                        "output": c.output,
+                    })
+                })
+                .collect();
+            json!({
+                "student": r.student,
+                "passed": r.passed(),
+                "total": r.cases.len(),
+                "cases": cases,
+            })
+        })
+        .collect();
+    json!({ "students": students })
+}
+
+/// 採点結果のJUnit XML 1人を1つのtestsuiteにする
+/// 期待値と違うものはfailure、アセンブルエラー、時間切れ、実行エラー、テストケースの誤りはerrorにする
+pub fn to_junit(results: &[StudentResult]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    for r in results {
+        let failures = r.cases.iter().filter(|c| matches!(c.outcome, Outcome::Failed(_))).count();
+        let errors = r.cases.len() - r.passed() - failures;
+        xml += &format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\">\n",
+            escape(&r.student),
+            r.cases.len(),
+            failures,
+            errors
+        );
+        for c in &r.cases {
+            let open = format!("    <testcase name=\"{}\" classname=\"{}\"", escape(&c.name), escape(&r.student));
+            let tag = match c.outcome {
+                Outcome::Passed => {
+                    xml += &format!("{}/>\n", open);
+                    continue;
+                }
+                Outcome::Failed(_) => "failure",
+                _ => "error",
+            };
+            let messages = c.outcome.messages();
+            xml += &format!(
+                "{}>\n      <{} type=\"{}\" message=\"{}\">{}</{}>\n",
+                open,
+                tag,
+                c.outcome.status(),
+                escape(messages.first().map_or("", |m| m.as_str())),
+                escape(&messages.join("\n")),
+                tag
+            );
+            if !c.output.is_empty() {
+                xml += &format!("      <system-out>{}</system-out>\n", escape(&c.output.join("\n")));
+            }
+            xml += "    </testcase>\n";
+        }
+        xml += "  </testsuite>\n";
+    }
+    xml += "</testsuites>\n";
+    xml
+}
+
+/// XMLの特殊文字を参照にする XML 1.0で書けない制御文字はU+FFFDにする
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            '\u{0}'..='\u{1F}' | '\u{FFFE}' | '\u{FFFF}' => out.push(char::REPLACEMENT_CHARACTER),
+            _ => out.push(c),
+        }
+    }
+    out
+}
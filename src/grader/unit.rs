@@ -0,0 +1,300 @@
+// サブルーチンの単体テスト
+//
+// `foo.cas`の隣に`foo.test`を置いて、次のように書く `;`から後ろはコメント
+//
+// ```text
+// TEST add
+//     CALL    ADD             ; 呼ぶラベル
+//     SET     GR1 3           ; レジスタ
+//     SET     GR2 DAT         ; ラベルの番地をレジスタに
+//     SET     DAT 1,2,'A'     ; ラベルから続けて書く
+//     INPUT   HELLO           ; INで読む行
+//     EXPECT  GR0 7
+//     EXPECT  ANS #0007
+//     EXPECT  ZF 0            ; OF, SF, ZF
+//     EXPECT  OUTPUT HELLO    ; OUTで書かれる行 書いた順
+//     PRESERVE GR2,GR3        ; 呼ぶ前と同じ値のはずのレジスタ
+//     STEPS   1000            ; ステップ数の上限
+// END
+// ```
+//
+// ローダーが積んだ戻り番地に戻ってきたら終わり
+
+use std::{fs, path::{Path, PathBuf}};
+
+use crate::{
+    emurator::{casl2::{code_gen::CodeGenerator, err::Casl2AssemblerError, object::ObjectFile}, commet2::console::BufferConsole, jisx0201, loader::Loader},
+    grader::{diff_memory, execute, parse_register, resolve_location, write_memory, CaseResult, Outcome, DEFAULT_MAX_STEPS},
+};
+
+/// テストファイルの拡張子
+pub const TEST_EXTENSION: &str = "test";
+
+/// フラグの名前 `CPUState::fr`の順
+pub const FLAGS: [&str; 3] = ["OF", "SF", "ZF"];
+
+/// SETでレジスタに入れる値 ラベルならその番地
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegisterValue {
+    Word(u16),
+    Address(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UnitTest {
+    pub name: String,
+    /// TESTの行 (0始まり)
+    pub line: usize,
+    /// 呼ぶラベル
+    pub entry: String,
+    pub registers: Vec<(u8, RegisterValue)>,
+    pub memory: Vec<(String, Vec<u16>)>,
+    pub input: Vec<String>,
+    pub expected_registers: Vec<(u8, u16)>,
+    pub expected_memory: Vec<(String, Vec<u16>)>,
+    /// (`FLAGS`の位置, 値)
+    pub expected_flags: Vec<(usize, bool)>,
+    pub expected_output: Option<Vec<String>>,
+    pub preserved: Vec<u8>,
+    pub max_steps: usize,
+}
+
+/// テストファイルを読む
+pub fn parse(src: &str) -> Result<Vec<UnitTest>, Casl2AssemblerError> {
+    let mut tests = vec![];
+    let mut current: Option<UnitTest> = None;
+    for (n, raw) in src.lines().enumerate() {
+        let err = |msg: String| Casl2AssemblerError::ParseError(format!("{}, line: {}", msg, n + 1));
+        let line = strip_comment(raw).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (keyword, rest) = line.split_once(char::is_whitespace).map_or((line, ""), |(k, r)| (k, r.trim()));
+        let keyword = keyword.to_ascii_uppercase();
+
+        if keyword == "TEST" {
+            if current.is_some() {
+                return Err(err("TEST before END".to_string()));
+            }
+            if rest.is_empty() {
+                return Err(err("TEST without name".to_string()));
+            }
+            current = Some(UnitTest { name: rest.to_string(), line: n, max_steps: DEFAULT_MAX_STEPS, ..Default::default() });
+            continue;
+        }
+        let Some(test) = current.as_mut() else {
+            return Err(err(format!("{} outside TEST", keyword)));
+        };
+        let (target, value) = rest.split_once(char::is_whitespace).map_or((rest, ""), |(t, v)| (t, v.trim()));
+        match keyword.as_str() {
+            "END" => {
+                let test = current.take().unwrap();
+                if test.entry.is_empty() {
+                    return Err(err(format!("No CALL in test {}", test.name)));
+                }
+                tests.push(test);
+            }
+            "CALL" => test.entry = rest.to_string(),
+            "INPUT" => test.input.push(rest.to_string()),
+            "STEPS" => test.max_steps = rest.parse().map_err(|_| err(format!("Invalid step count: {}", rest)))?,
+            "PRESERVE" => {
+                for name in rest.split(',') {
+                    test.preserved.push(parse_register(name.trim()).ok_or_else(|| err(format!("Unknown register: {}", name)))?);
+                }
+            }
+            "SET" | "EXPECT" => {
+                let expect = keyword == "EXPECT";
+                if expect && target.eq_ignore_ascii_case("OUTPUT") {
+                    test.expected_output.get_or_insert_with(Vec::new).push(value.to_string());
+                    continue;
+                }
+                let words = parse_values(value);
+                if !expect
+                    && let Some(gr) = parse_register(target)
+                {
+                    let value = match words.as_deref() {
+                        Some(&[word]) => RegisterValue::Word(word),
+                        None if !value.is_empty() => RegisterValue::Address(value.to_string()),
+                        _ => return Err(err(format!("GR{} takes one value", gr))),
+                    };
+                    test.registers.push((gr, value));
+                    continue;
+                }
+                let words = words.ok_or_else(|| err(format!("Invalid value: {}", value)))?;
+                if let Some(flag) = FLAGS.iter().position(|f| f.eq_ignore_ascii_case(target)).filter(|_| expect) {
+                    test.expected_flags.push((flag, words != [0]));
+                } else if let Some(gr) = parse_register(target) {
+                    let [word] = words[..] else {
+                        return Err(err(format!("GR{} takes one value", gr)));
+                    };
+                    test.expected_registers.push((gr, word));
+                } else {
+                    let list = if expect { &mut test.expected_memory } else { &mut test.memory };
+                    list.push((target.to_string(), words));
+                }
+            }
+            _ => return Err(err(format!("Unknown keyword: {}", keyword))),
+        }
+    }
+    if let Some(test) = current {
+        return Err(Casl2AssemblerError::ParseError(format!("TEST {} without END, line: {}", test.name, test.line + 1)));
+    }
+    Ok(tests)
+}
+
+/// 文字定数の中の`;`はコメントにしない
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\'' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// `,`で区切った値 10進数 (負でもよい)、`#FFFF`、`'ABC'` (1文字1語)
+pub fn parse_values(text: &str) -> Option<Vec<u16>> {
+    let mut items = vec![];
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '\'' => quoted = !quoted,
+            ',' if !quoted => {
+                items.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&text[start..]);
+
+    let mut words = vec![];
+    for item in items {
+        let item = item.trim();
+        if let Some(inner) = CodeGenerator::string_constant(item) {
+            words.extend(jisx0201::encode(&inner).ok()?);
+        } else if let Some(hex) = item.strip_prefix('#') {
+            words.push(u16::from_str_radix(hex, 16).ok()?);
+        } else {
+            let n = item.parse::<i32>().ok()?;
+            if !(-32768..=65535).contains(&n) {
+                return None;
+            }
+            words.push(n as u16);
+        }
+    }
+    Some(words)
+}
+
+/// アセンブル済みのプログラムで1つのテストを実行する
+pub fn run_test(code_gen: &CodeGenerator, objects: &[ObjectFile], test: &UnitTest) -> CaseResult {
+    let mut result = CaseResult { name: test.name.clone(), outcome: Outcome::Passed, steps: 0, output: vec![] };
+    let Some(entry) = code_gen.lookup(&test.entry) else {
+        result.outcome = Outcome::CaseError(format!("{}: unknown label", test.entry));
+        return result;
+    };
+    let mut cpu = match Loader::new().load_objects(objects) {
+        Ok(cpu) => cpu,
+        Err(e) => {
+            result.outcome = Outcome::Fault(e.to_string());
+            return result;
+        }
+    };
+    // ローダーが積んだ戻り番地はそのままで、呼ぶラベルから始める
+    cpu.state.pr = entry;
+    for (gr, value) in &test.registers {
+        let value = match value {
+            RegisterValue::Word(word) => *word,
+            RegisterValue::Address(location) => match resolve_location(code_gen, location) {
+                Some(addr) => addr,
+                None => {
+                    result.outcome = Outcome::CaseError(format!("{}: unknown label", location));
+                    return result;
+                }
+            },
+        };
+        *cpu.state.gr.get_mut(*gr) = value;
+    }
+    for (location, words) in &test.memory {
+        if write_memory(code_gen, &mut cpu, location, words).is_err() {
+            result.outcome = Outcome::CaseError(format!("{}: unknown label", location));
+            return result;
+        }
+    }
+    let before: Vec<u16> = (0..8).map(|gr| cpu.state.gr.get(gr)).collect();
+    let console = BufferConsole::new(&test.input);
+    cpu.supervisor = Some(Box::new(console.clone()));
+
+    let outcome = execute(&mut cpu, test.max_steps, &mut result.steps);
+    result.output = console.output();
+    if let Some(outcome) = outcome {
+        result.outcome = outcome;
+        return result;
+    }
+
+    let mut diffs = vec![];
+    for &(gr, expected) in &test.expected_registers {
+        let actual = cpu.state.gr.get(gr);
+        if actual != expected {
+            diffs.push(format!("GR{}: expected #{:04X}, got #{:04X}", gr, expected, actual));
+        }
+    }
+    for (location, words) in &test.expected_memory {
+        diffs.extend(diff_memory(code_gen, &cpu, location, words));
+    }
+    for &(flag, expected) in &test.expected_flags {
+        if cpu.state.fr[flag] != expected {
+            diffs.push(format!("{}: expected {}, got {}", FLAGS[flag], expected as u8, cpu.state.fr[flag] as u8));
+        }
+    }
+    if let Some(expected) = &test.expected_output
+        && *expected != result.output
+    {
+        diffs.push(format!("output: expected {:?}, got {:?}", expected, result.output));
+    }
+    for &gr in &test.preserved {
+        let actual = cpu.state.gr.get(gr);
+        if actual != before[gr as usize] {
+            diffs.push(format!("GR{} not preserved: was #{:04X}, got #{:04X}", gr, before[gr as usize], actual));
+        }
+    }
+    if !diffs.is_empty() {
+        result.outcome = Outcome::Failed(diffs);
+    }
+    result
+}
+
+/// ソースをアセンブルしてすべてのテストを実行する
+pub fn run_tests(file: &str, src: &str, tests: &[UnitTest]) -> Vec<CaseResult> {
+    let assembled = CodeGenerator::from_source(file, src).and_then(|mut code_gen| Ok((code_gen.generate_standalone()?, code_gen)));
+    match assembled {
+        Ok((objects, code_gen)) => tests.iter().map(|t| run_test(&code_gen, &objects, t)).collect(),
+        Err(e) => tests
+            .iter()
+            .map(|t| CaseResult { name: t.name.clone(), outcome: Outcome::AssembleError(e.to_string()), steps: 0, output: vec![] })
+            .collect(),
+    }
+}
+
+/// `.test`ファイルと隣の`.cas`ファイルの組を探す ディレクトリならその下をすべて見る
+pub fn discover(path: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let mut found = vec![];
+    if path.is_dir() {
+        let Ok(entries) = fs::read_dir(path) else { return found };
+        let mut entries: Vec<PathBuf> = entries.filter_map(|e| e.ok().map(|e| e.path())).collect();
+        entries.sort();
+        for entry in entries {
+            found.extend(discover(&entry));
+        }
+    } else if path.extension().is_some_and(|e| e == TEST_EXTENSION) {
+        let source = path.with_extension("cas");
+        if source.exists() {
+            found.push((path.to_path_buf(), source));
+        }
+    }
+    found
+}
@@ -1 +1,5 @@
-pub mod emurator;
\ No newline at end of file
+pub mod emurator;
+pub mod rpc;
+pub mod dap;
+pub mod lsp;
+pub mod grader;
@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+
+use crate::emurator::{casl2::{code_gen::CodeGenerator, lexer::{is_label_operand, split_fields, utf16_len, LineFields, Token}, linter::{lint, Lint, LintConfig, LintLevel}, parser::ASTNode}, commet2::prefix::opecode_to_binary};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error = 1,
+    Warning = 2,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub start: usize,
+    pub end: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// START から END までのプログラム
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub name: Token,
+    pub end_line: usize,
+    /// ブロック内で定義されたラベル
+    pub labels: Vec<Token>,
+}
+
+/// 開いているソースを解析した結果
+pub struct Document {
+    pub lines: Vec<String>,
+    pub fields: Vec<LineFields>,
+    pub nodes: Vec<Option<ASTNode>>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// ラベルの定義位置
+    /// STARTのラベルはそのまま、プログラム内のラベルは`MAIN.LOOP`の形をキーにする
+    pub definitions: HashMap<String, Token>,
+    /// オペランドで使われているラベル
+    pub references: Vec<Token>,
+    pub blocks: Vec<Block>,
+    /// 各行を含むブロックの添字
+    pub scopes: Vec<Option<usize>>,
+    /// アセンブルできた場合のラベルのアドレス キーはdefinitionsと同じ
+    pub addresses: HashMap<String, u16>,
+}
+
+impl Document {
+    pub fn new(text: &str) -> Self {
+        let lines: Vec<String> = text.lines().map(|l| l.to_string()).collect();
+        let fields: Vec<LineFields> = lines.iter().enumerate().map(|(i, l)| split_fields(i, l)).collect();
+        let mut doc = Document {
+            lines,
+            fields,
+            nodes: Vec::new(),
+            diagnostics: Vec::new(),
+            definitions: HashMap::new(),
+            references: Vec::new(),
+            blocks: Vec::new(),
+            scopes: Vec::new(),
+            addresses: HashMap::new(),
+        };
+        doc.analyze();
+        doc
+    }
+
+    fn line_diagnostic(&self, line: usize, severity: Severity, message: String) -> Diagnostic {
+        Diagnostic {
+            line,
+            start: 0,
+            end: utf16_len(&self.lines[line]),
+            severity,
+            message,
+        }
+    }
+
+    fn token_diagnostic(token: &Token, severity: Severity, message: String) -> Diagnostic {
+        Diagnostic {
+            line: token.line,
+            start: token.start,
+            end: token.end,
+            severity,
+            message,
+        }
+    }
+
+    fn analyze(&mut self) {
+        // 構文
+        for (i, line) in self.lines.iter().enumerate() {
+            match ASTNode::analyze(i, line) {
+                Ok(node) => self.nodes.push(Some(node)),
+                Err(e) => {
+                    let message = e.to_string().lines().next().unwrap_or_default().to_string();
+                    self.nodes.push(None);
+                    self.diagnostics.push(self.line_diagnostic(i, Severity::Error, message));
+                }
+            }
+        }
+
+        // プログラムの範囲
+        let mut open_block: Option<Block> = None;
+        for i in 0..self.lines.len() {
+            let Some(node) = &self.nodes[i] else { continue };
+            match node {
+                ASTNode::START { .. } => {
+                    if let Some(block) = open_block.take() {
+                        self.diagnostics.push(self.line_diagnostic(i, Severity::Error, format!("START before END of {}", block.name.text)));
+                        self.blocks.push(Block { end_line: i.saturating_sub(1), ..block });
+                    }
+                    match &self.fields[i].label {
+                        Some(name) => {
+                            open_block = Some(Block { name: name.clone(), end_line: i, labels: Vec::new() });
+                        }
+                        None => self.diagnostics.push(self.line_diagnostic(i, Severity::Error, "START needs a label".to_string())),
+                    }
+                }
+                ASTNode::END => match open_block.take() {
+                    Some(block) => self.blocks.push(Block { end_line: i, ..block }),
+                    None => self.diagnostics.push(self.line_diagnostic(i, Severity::Error, "END without START".to_string())),
+                },
+                ASTNode::EMPTY => {}
+                _ => match (&mut open_block, &self.fields[i].label) {
+                    (Some(block), Some(label)) => block.labels.push(label.clone()),
+                    (None, _) => self.diagnostics.push(self.line_diagnostic(i, Severity::Warning, "Instruction outside of START/END".to_string())),
+                    _ => {}
+                },
+            }
+        }
+        if let Some(block) = open_block.take() {
+            let last = self.lines.len().saturating_sub(1);
+            self.diagnostics.push(Self::token_diagnostic(&block.name, Severity::Error, format!("Missing END for {}", block.name.text)));
+            self.blocks.push(Block { end_line: last, ..block });
+        }
+        self.scopes = (0..self.lines.len())
+            .map(|i| self.blocks.iter().position(|b| b.name.line <= i && i <= b.end_line))
+            .collect();
+
+        // ラベルの定義 STARTのラベル以外はプログラムの中だけで有効
+        for i in 0..self.lines.len() {
+            let (Some(_), Some(label)) = (&self.nodes[i], &self.fields[i].label) else { continue };
+            let key = self.definition_key(label);
+            if let Some(prev) = self.definitions.get(&key) {
+                let message = format!("Duplicate label: {} (first defined on line {})", label.text, prev.line + 1);
+                self.diagnostics.push(Self::token_diagnostic(label, Severity::Error, message));
+            } else {
+                self.definitions.insert(key, label.clone());
+            }
+        }
+
+        // ラベルの参照と命令の形
+        for i in 0..self.lines.len() {
+            let Some(node) = &self.nodes[i] else { continue };
+            let operands = &self.fields[i].operands;
+            let refs: Vec<&Token> = match node {
+                ASTNode::Machine1wInstruction { opcode, .. } | ASTNode::Machine2wInstruction { opcode, .. } => {
+                    let is_2w = matches!(node, ASTNode::Machine2wInstruction { .. });
+                    if opecode_to_binary(opcode, is_2w) == 0xFF
+                        && let Some(op) = &self.fields[i].opcode
+                    {
+                        let message = format!("Invalid operands for {}", opcode);
+                        self.diagnostics.push(Self::token_diagnostic(op, Severity::Error, message));
+                    }
+                    operands.iter().filter(|t| is_label_operand(&t.text)).collect()
+                }
+                ASTNode::AssemblerInstruction { .. } | ASTNode::MacroInstruction { .. } => operands.iter().filter(|t| is_label_operand(&t.text)).collect(),
+                ASTNode::START { .. } => operands.iter().filter(|t| is_label_operand(&t.text)).collect(),
+                _ => Vec::new(),
+            };
+            for token in refs {
+                if self.resolve(token).is_none() {
+                    self.diagnostics.push(Self::token_diagnostic(token, Severity::Error, format!("Unknown label: {}", token.text)));
+                }
+                self.references.push(token.clone());
+            }
+        }
+
+        // アセンブルできればラベルのアドレスがわかる
+        if self.diagnostics.iter().all(|d| d.severity != Severity::Error) {
+            let nodes = self.nodes.iter().flatten().cloned().collect();
+            let mut code_gen = CodeGenerator::new(nodes);
+            match code_gen.generate() {
+                Ok(_) => {
+                    self.addresses = code_gen.label_map.clone();
+                    for program in &code_gen.programs {
+                        for (label, &addr) in &program.labels {
+                            self.addresses.insert(Self::scoped(&program.name, label), addr);
+                        }
+                    }
+                }
+                Err(e) => {
+                    let message = e.to_string().lines().next().unwrap_or_default().to_string();
+                    self.diagnostics.push(self.line_diagnostic(0, Severity::Error, message));
+                }
+            }
+        }
+
+        // エラーがなければリントの警告も出す 未定義ラベルは上で報告済み
+        if self.diagnostics.iter().all(|d| d.severity != Severity::Error) {
+            let mut config = LintConfig::new();
+            config.set(Lint::UndefinedLabel, LintLevel::Allow);
+            let warnings = lint(&self.lines.join("\n"), &config).unwrap_or_default();
+            for w in warnings {
+                let message = format!("{} [{}]", w.message, w.lint.name());
+                self.diagnostics.push(self.line_diagnostic(w.line, Severity::Warning, message));
+            }
+        }
+    }
+
+    /// 位置にある語
+    pub fn token_at(&self, line: usize, character: usize) -> Option<&Token> {
+        let fields = self.fields.get(line)?;
+        fields
+            .label
+            .iter()
+            .chain(fields.opcode.iter())
+            .chain(fields.operands.iter())
+            .find(|t| t.contains(line, character))
+    }
+
+    /// プログラム内のラベルのキー
+    fn scoped(program: &str, label: &str) -> String {
+        if program.is_empty() {
+            label.to_string()
+        } else {
+            format!("{}.{}", program, label)
+        }
+    }
+
+    /// 行を含むプログラムの名前 STARTの外なら空
+    fn scope_name(&self, line: usize) -> &str {
+        match self.scopes.get(line).copied().flatten() {
+            Some(b) => &self.blocks[b].name.text,
+            None => "",
+        }
+    }
+
+    /// ラベルを定義している語のキー
+    fn definition_key(&self, label: &Token) -> String {
+        let is_start = matches!(self.nodes[label.line], Some(ASTNode::START { .. }));
+        if is_start {
+            label.text.clone()
+        } else {
+            Self::scoped(self.scope_name(label.line), &label.text)
+        }
+    }
+
+    /// 参照している語が指す定義のキー プログラム内のラベルが優先
+    pub fn resolve(&self, token: &Token) -> Option<String> {
+        let local = Self::scoped(self.scope_name(token.line), &token.text);
+        if self.definitions.contains_key(&local) {
+            return Some(local);
+        }
+        self.definitions.contains_key(&token.text).then(|| token.text.clone())
+    }
+
+    /// 行から見えるラベル名
+    pub fn visible_labels(&self, line: usize) -> Vec<&str> {
+        let prefix = format!("{}.", self.scope_name(line));
+        let mut labels: Vec<&str> = self
+            .definitions
+            .keys()
+            .filter_map(|k| match k.strip_prefix(&prefix) {
+                Some(local) => Some(local),
+                None => (!k.contains('.')).then_some(k.as_str()),
+            })
+            .collect();
+        labels.sort();
+        labels.dedup();
+        labels
+    }
+
+    /// 位置にあるラベルのキー (定義でも参照でもよい)
+    pub fn label_at(&self, line: usize, character: usize) -> Option<String> {
+        let token = self.token_at(line, character)?;
+        if self.fields[line].label.as_ref() == Some(token) {
+            let key = self.definition_key(token);
+            return self.definitions.contains_key(&key).then_some(key);
+        }
+        self.resolve(token)
+    }
+
+    /// ラベルの参照位置
+    pub fn references_of(&self, key: &str) -> Vec<&Token> {
+        self.references.iter().filter(|t| self.resolve(t).as_deref() == Some(key)).collect()
+    }
+}
@@ -0,0 +1,67 @@
+/// 命令のホバー用の説明
+pub struct InstructionDoc {
+    pub name: &'static str,
+    /// 命令の説明
+    pub summary: &'static str,
+    /// 書式とオペコード
+    pub encoding: &'static [&'static str],
+    /// 変化するフラグ
+    pub flags: &'static str,
+}
+
+pub const INSTRUCTION_DOCS: &[InstructionDoc] = &[
+    InstructionDoc { name: "START", summary: "プログラムの先頭を定義する。ラベルはプログラムの入口名になり、オペランドがあればそのラベルから実行を始める", encoding: &["[実行開始番地]"], flags: "-" },
+    InstructionDoc { name: "END", summary: "プログラムの終わりを定義する", encoding: &[""], flags: "-" },
+    InstructionDoc { name: "DS", summary: "指定した語数の領域を確保する", encoding: &["語数"], flags: "-" },
+    InstructionDoc { name: "DC", summary: "定数を定義する。10進定数、16進定数(#hhhh)、文字定数('...')、アドレス定数(ラベル)を書ける", encoding: &["定数[,定数]..."], flags: "-" },
+    InstructionDoc { name: "IN", summary: "入力装置から1レコードを読み、文字を入力領域に、文字数を入力文字長に格納する (マクロ命令)", encoding: &["入力領域,入力文字長 → PUSH, LAD, SVC 1, POP に展開"], flags: "不定" },
+    InstructionDoc { name: "OUT", summary: "出力領域から出力文字長の文字を出力装置に書き出す (マクロ命令)", encoding: &["出力領域,出力文字長 → PUSH, LAD, SVC 2, POP に展開"], flags: "不定" },
+    InstructionDoc { name: "RPUSH", summary: "GR1 から GR7 の順にスタックに積む (マクロ命令)", encoding: &["→ PUSH 0,GR1 ... PUSH 0,GR7 に展開"], flags: "-" },
+    InstructionDoc { name: "RPOP", summary: "GR7 から GR1 の順にスタックから取り出す (マクロ命令)", encoding: &["→ POP GR7 ... POP GR1 に展開"], flags: "-" },
+    InstructionDoc { name: "NOP", summary: "何もしない", encoding: &["00 00"], flags: "-" },
+    InstructionDoc { name: "LD", summary: "ロード r ← (実効アドレス) / r1 ← r2", encoding: &["r,adr[,x]: 10 rx adr", "r1,r2: 14 r1r2"], flags: "OF←0, SF, ZF" },
+    InstructionDoc { name: "ST", summary: "ストア (実効アドレス) ← r", encoding: &["r,adr[,x]: 11 rx adr"], flags: "-" },
+    InstructionDoc { name: "LAD", summary: "ロードアドレス r ← 実効アドレス", encoding: &["r,adr[,x]: 12 rx adr"], flags: "-" },
+    InstructionDoc { name: "ADDA", summary: "算術加算 r ← r + (実効アドレス) / r1 ← r1 + r2", encoding: &["r,adr[,x]: 20 rx adr", "r1,r2: 24 r1r2"], flags: "OF, SF, ZF" },
+    InstructionDoc { name: "SUBA", summary: "算術減算 r ← r - (実効アドレス) / r1 ← r1 - r2", encoding: &["r,adr[,x]: 21 rx adr", "r1,r2: 25 r1r2"], flags: "OF, SF, ZF" },
+    InstructionDoc { name: "ADDL", summary: "論理加算 r ← r +L (実効アドレス) / r1 ← r1 +L r2", encoding: &["r,adr[,x]: 22 rx adr", "r1,r2: 26 r1r2"], flags: "OF, SF, ZF" },
+    InstructionDoc { name: "SUBL", summary: "論理減算 r ← r -L (実効アドレス) / r1 ← r1 -L r2", encoding: &["r,adr[,x]: 23 rx adr", "r1,r2: 27 r1r2"], flags: "OF, SF, ZF" },
+    InstructionDoc { name: "AND", summary: "論理積 r ← r AND (実効アドレス) / r1 ← r1 AND r2", encoding: &["r,adr[,x]: 30 rx adr", "r1,r2: 34 r1r2"], flags: "OF←0, SF, ZF" },
+    InstructionDoc { name: "OR", summary: "論理和 r ← r OR (実効アドレス) / r1 ← r1 OR r2", encoding: &["r,adr[,x]: 31 rx adr", "r1,r2: 35 r1r2"], flags: "OF←0, SF, ZF" },
+    InstructionDoc { name: "XOR", summary: "排他的論理和 r ← r XOR (実効アドレス) / r1 ← r1 XOR r2", encoding: &["r,adr[,x]: 32 rx adr", "r1,r2: 36 r1r2"], flags: "OF←0, SF, ZF" },
+    InstructionDoc { name: "CPA", summary: "算術比較 r と (実効アドレス) / r1 と r2 を符号付きで比較する", encoding: &["r,adr[,x]: 40 rx adr", "r1,r2: 44 r1r2"], flags: "OF←0, SF, ZF" },
+    InstructionDoc { name: "CPL", summary: "論理比較 r と (実効アドレス) / r1 と r2 を符号なしで比較する", encoding: &["r,adr[,x]: 41 rx adr", "r1,r2: 45 r1r2"], flags: "OF←0, SF, ZF" },
+    InstructionDoc { name: "SLA", summary: "算術左シフト 符号を除いて実効アドレスのビット数だけ左へシフトする", encoding: &["r,adr[,x]: 50 rx adr"], flags: "OF←最後に送り出したビット, SF, ZF" },
+    InstructionDoc { name: "SRA", summary: "算術右シフト 符号を除いて実効アドレスのビット数だけ右へシフトする", encoding: &["r,adr[,x]: 51 rx adr"], flags: "OF←最後に送り出したビット, SF, ZF" },
+    InstructionDoc { name: "SLL", summary: "論理左シフト 実効アドレスのビット数だけ左へシフトする", encoding: &["r,adr[,x]: 52 rx adr"], flags: "OF←最後に送り出したビット, SF, ZF" },
+    InstructionDoc { name: "SRL", summary: "論理右シフト 実効アドレスのビット数だけ右へシフトする", encoding: &["r,adr[,x]: 53 rx adr"], flags: "OF←最後に送り出したビット, SF, ZF" },
+    InstructionDoc { name: "JMI", summary: "負分岐 SF=1 なら実効アドレスへ分岐する", encoding: &["adr[,x]: 61 0x adr"], flags: "-" },
+    InstructionDoc { name: "JNZ", summary: "非零分岐 ZF=0 なら実効アドレスへ分岐する", encoding: &["adr[,x]: 62 0x adr"], flags: "-" },
+    InstructionDoc { name: "JZE", summary: "零分岐 ZF=1 なら実効アドレスへ分岐する", encoding: &["adr[,x]: 63 0x adr"], flags: "-" },
+    InstructionDoc { name: "JUMP", summary: "無条件分岐 実効アドレスへ分岐する", encoding: &["adr[,x]: 64 0x adr"], flags: "-" },
+    InstructionDoc { name: "JPL", summary: "正分岐 SF=0 かつ ZF=0 なら実効アドレスへ分岐する", encoding: &["adr[,x]: 65 0x adr"], flags: "-" },
+    InstructionDoc { name: "JOV", summary: "オーバーフロー分岐 OF=1 なら実効アドレスへ分岐する", encoding: &["adr[,x]: 66 0x adr"], flags: "-" },
+    InstructionDoc { name: "PUSH", summary: "プッシュ SP ← SP - 1, (SP) ← 実効アドレス", encoding: &["adr[,x]: 70 0x adr"], flags: "-" },
+    InstructionDoc { name: "POP", summary: "ポップ r ← (SP), SP ← SP + 1", encoding: &["r: 71 r0"], flags: "-" },
+    InstructionDoc { name: "CALL", summary: "コール SP ← SP - 1, (SP) ← PR, PR ← 実効アドレス", encoding: &["adr[,x]: 80 0x adr"], flags: "-" },
+    InstructionDoc { name: "RET", summary: "リターン PR ← (SP), SP ← SP + 1", encoding: &["81 00"], flags: "-" },
+    InstructionDoc { name: "SVC", summary: "スーパーバイザコール 実効アドレスで指定した機能をOSに頼む", encoding: &["adr[,x]: F0 0x adr"], flags: "不定" },
+];
+
+/// 命令の説明を探す
+pub fn instruction_doc(opcode: &str) -> Option<&'static InstructionDoc> {
+    INSTRUCTION_DOCS.iter().find(|doc| doc.name == opcode)
+}
+
+impl InstructionDoc {
+    /// ホバーに出すMarkdown
+    pub fn markdown(&self) -> String {
+        let encoding = self
+            .encoding
+            .iter()
+            .map(|e| format!("    {} {}", self.name, e))
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!("**{}** {}\n\n{}\n\nフラグ: {}", self.name, self.summary, encoding, self.flags)
+    }
+}
@@ -0,0 +1,288 @@
+pub mod analysis;
+pub mod docs;
+
+use std::{collections::HashMap, io::{self, BufRead, Write}};
+
+use serde_json::{json, Value};
+
+use crate::{emurator::casl2::{lexer::Token, prefix::GR_LIST}, lsp::{analysis::Document, docs::INSTRUCTION_DOCS}, rpc::{read_message, write_message}};
+
+/// LSPのSymbolKind
+mod symbol_kind {
+    pub const MODULE: u8 = 2;
+    pub const FUNCTION: u8 = 12;
+    pub const VARIABLE: u8 = 13;
+}
+
+/// LSPのCompletionItemKind
+mod completion_kind {
+    pub const VARIABLE: u8 = 6;
+    pub const KEYWORD: u8 = 14;
+    pub const REFERENCE: u8 = 18;
+}
+
+/// Language Server Protocol のサーバー
+/// 開いているドキュメントを保持して要求に答える
+pub struct Server {
+    pub documents: HashMap<String, Document>,
+    /// shutdownを受け取った
+    shutdown: bool,
+    /// exitを受け取った
+    pub exit: bool,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn range(line: usize, start: usize, end: usize) -> Value {
+    json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": line, "character": end },
+    })
+}
+
+fn token_range(token: &Token) -> Value {
+    range(token.line, token.start, token.end)
+}
+
+fn location(uri: &str, token: &Token) -> Value {
+    json!({ "uri": uri, "range": token_range(token) })
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server {
+            documents: HashMap::new(),
+            shutdown: false,
+            exit: false,
+        }
+    }
+
+    /// メッセージを1つ処理して送り返すメッセージを返す
+    pub fn handle(&mut self, msg: &Value) -> Vec<Value> {
+        let method = msg["method"].as_str().unwrap_or_default();
+        let params = &msg["params"];
+        // idがなければ通知
+        let Some(id) = msg.get("id").cloned() else {
+            return self.notification(method, params);
+        };
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "completionProvider": {},
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "x-casl2" },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/hover" => Ok(self.hover(params)),
+            "textDocument/definition" => Ok(self.definition(params)),
+            "textDocument/references" => Ok(self.references(params)),
+            "textDocument/completion" => Ok(self.completion(params)),
+            "textDocument/documentSymbol" => Ok(self.document_symbol(params)),
+            _ => Err(format!("unsupported method: {}", method)),
+        };
+        let res = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(message) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32601, "message": message } }),
+        };
+        vec![res]
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), Document::new(text));
+                vec![self.publish_diagnostics(&uri)]
+            }
+            "textDocument/didChange" => {
+                // 全文同期なので最後の変更が全文
+                let Some(text) = params["contentChanges"].as_array().and_then(|c| c.last()).and_then(|c| c["text"].as_str()) else {
+                    return Vec::new();
+                };
+                self.documents.insert(uri.clone(), Document::new(text));
+                vec![self.publish_diagnostics(&uri)]
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                vec![json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": [] },
+                })]
+            }
+            "exit" => {
+                self.exit = true;
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn publish_diagnostics(&self, uri: &str) -> Value {
+        let diagnostics: Vec<Value> = self.documents[uri]
+            .diagnostics
+            .iter()
+            .map(|d| json!({
+                "range": range(d.line, d.start, d.end),
+                "severity": d.severity as u8,
+                "source": "casl2",
+                "message": d.message,
+            }))
+            .collect();
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        })
+    }
+
+    /// 要求の対象ドキュメントと位置
+    fn position<'a>(&'a self, params: &Value) -> Option<(&'a str, &'a Document, usize, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let (uri, doc) = self.documents.get_key_value(uri)?;
+        let line = params["position"]["line"].as_u64()? as usize;
+        let character = params["position"]["character"].as_u64()? as usize;
+        Some((uri.as_str(), doc, line, character))
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        let Some((_, doc, line, character)) = self.position(params) else {
+            return Value::Null;
+        };
+        let Some(token) = doc.token_at(line, character) else {
+            return Value::Null;
+        };
+        let is_opcode = doc.fields[line].opcode.as_ref() == Some(token);
+        let contents = if is_opcode {
+            match docs::instruction_doc(&token.text.to_uppercase()) {
+                Some(doc) => doc.markdown(),
+                None => return Value::Null,
+            }
+        } else if let Some(key) = doc.label_at(line, character) {
+            let def = &doc.definitions[&key];
+            match doc.addresses.get(&key) {
+                Some(addr) => format!("**{}** #{:04X}\n\n{}", token.text, addr, doc.lines[def.line].trim()),
+                None => format!("**{}**\n\n{}", token.text, doc.lines[def.line].trim()),
+            }
+        } else {
+            return Value::Null;
+        };
+        json!({
+            "contents": { "kind": "markdown", "value": contents },
+            "range": token_range(token),
+        })
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let Some((uri, doc, line, character)) = self.position(params) else {
+            return Value::Null;
+        };
+        match doc.label_at(line, character) {
+            Some(key) => location(uri, &doc.definitions[&key]),
+            None => Value::Null,
+        }
+    }
+
+    fn references(&self, params: &Value) -> Value {
+        let Some((uri, doc, line, character)) = self.position(params) else {
+            return Value::Null;
+        };
+        let Some(key) = doc.label_at(line, character) else {
+            return json!([]);
+        };
+        let mut locations: Vec<Value> = Vec::new();
+        if params["context"]["includeDeclaration"].as_bool().unwrap_or(false) {
+            locations.push(location(uri, &doc.definitions[&key]));
+        }
+        locations.extend(doc.references_of(&key).into_iter().map(|t| location(uri, t)));
+        Value::Array(locations)
+    }
+
+    fn completion(&self, params: &Value) -> Value {
+        let Some((_, doc, line, character)) = self.position(params) else {
+            return json!([]);
+        };
+        // 命令欄より右ならオペランド
+        let in_operand = doc.fields.get(line)
+            .and_then(|f| f.opcode.as_ref())
+            .is_some_and(|op| character > op.end);
+        let items: Vec<Value> = if in_operand {
+            let registers = GR_LIST.iter().map(|gr| json!({ "label": gr, "kind": completion_kind::VARIABLE }));
+            let labels = doc.visible_labels(line).into_iter().map(|l| json!({ "label": l, "kind": completion_kind::REFERENCE }));
+            registers.chain(labels).collect()
+        } else {
+            INSTRUCTION_DOCS
+                .iter()
+                .map(|d| json!({ "label": d.name, "kind": completion_kind::KEYWORD, "detail": d.summary }))
+                .collect()
+        };
+        Value::Array(items)
+    }
+
+    fn document_symbol(&self, params: &Value) -> Value {
+        let Some(doc) = params["textDocument"]["uri"].as_str().and_then(|uri| self.documents.get(uri)) else {
+            return json!([]);
+        };
+        let symbols: Vec<Value> = doc
+            .blocks
+            .iter()
+            .map(|block| {
+                let children: Vec<Value> = block
+                    .labels
+                    .iter()
+                    .map(|label| {
+                        let kind = match &doc.nodes[label.line] {
+                            Some(node) if node.is_instruction() => symbol_kind::FUNCTION,
+                            _ => symbol_kind::VARIABLE,
+                        };
+                        json!({
+                            "name": label.text,
+                            "kind": kind,
+                            "range": range(label.line, 0, doc.lines[label.line].chars().map(char::len_utf16).sum()),
+                            "selectionRange": token_range(label),
+                        })
+                    })
+                    .collect();
+                let end_character: usize = doc.lines[block.end_line].chars().map(char::len_utf16).sum();
+                json!({
+                    "name": block.name.text,
+                    "kind": symbol_kind::MODULE,
+                    "range": {
+                        "start": { "line": block.name.line, "character": 0 },
+                        "end": { "line": block.end_line, "character": end_character },
+                    },
+                    "selectionRange": token_range(&block.name),
+                    "children": children,
+                })
+            })
+            .collect();
+        Value::Array(symbols)
+    }
+}
+
+/// 入力が終わるかexitを受け取るまでサーバーを回す
+pub fn serve<R: BufRead, W: Write>(input: &mut R, output: &mut W) -> io::Result<()> {
+    let mut server = Server::new();
+    while !server.exit {
+        let Some(msg) = read_message(input)? else {
+            break;
+        };
+        for out in server.handle(&msg) {
+            write_message(output, &out)?;
+        }
+    }
+    Ok(())
+}
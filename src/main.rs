@@ -2,25 +2,20 @@ use std::sync::OnceLock;
 use std::sync::RwLock;
 static LAST_DISPLAY: OnceLock<RwLock<std::time::Instant>> = OnceLock::new();
 use crate::emurator::commet2::cpu::CPUExecution;
-use std::io::{self, Write};
 use std::{thread, time};
 
 pub mod emurator;
 
 fn main() {
-    let mut commet2 = emurator::commet2::cpu::CPU::new();
-    let mut write_memory: [u16; 65536] = [0; 65536];
-    write_memory[0] = 0x1200;
-    write_memory[1] = 0x0001;
-    write_memory[2] = 0x2410;
-    write_memory[3] = 0x6400;
-    write_memory[4] = 0x0002;
-    commet2.state.memory.0.copy_from_slice(&write_memory);
-    commet2.state.pr = 0x0000; // プログラムレジスタを初期化
-    commet2.state.sp = 0x0000; // スタックポイン
+    // LAD GR0,1 / ADDA GR1,GR0 / JUMP 2
+    let program = [0x1200, 0x0001, 0x2410, 0x6400, 0x0002];
+    let mut commet2 = emurator::loader::Loader::new().load_words(&program, 0).unwrap();
 
     loop {
         let res = commet2.commet2_step();
+        if let emurator::commet2::cpu::UpdateNotify::END = res {
+            break;
+        }
         // 画面をクリアしてから状態を表示（1ms周期で表示のみ）
         let now = std::time::Instant::now();
         thread::sleep(std::time::Duration::from_millis(100)); // 1ms待機
@@ -0,0 +1,35 @@
+use std::io::{self, BufRead, Write};
+
+use serde_json::Value;
+
+/// Content-Length ヘッダ付きのメッセージを1つ読む
+/// 入力が終わっていれば`None`
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            if len.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some(val) = header.strip_prefix("Content-Length:") {
+            len = val.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; len.unwrap_or(0)];
+    reader.read_exact(&mut body)?;
+    serde_json::from_slice(&body).map(Some).map_err(io::Error::other)
+}
+
+/// Content-Length ヘッダ付きでメッセージを書く
+pub fn write_message<W: Write>(writer: &mut W, msg: &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
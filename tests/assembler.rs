@@ -1,12 +1,121 @@
-
 #[cfg(test)]
 mod tests {
-    use x_casl2::emurator::casl2::parser::ASTNode;
+    use x_casl2::emurator::{casl2::{code_gen::CodeGenerator, parser::ASTNode, source_map::Origin}, loader::Loader};
 
     #[test]
     fn test_ast_node_de() {
         let input = "KD1\tSTART\tADDR\nDAT\tDC\t3,3,3,3;this is comment\nADDR\tLD\tGR0,1000\n\tEND";
         let nodes = ASTNode::de(input).unwrap();
         println!("{:?}", nodes);
+
+        // 行番号は1から数える
+        let err = ASTNode::de("MAIN\tSTART\n\tFOO\tGR1\n\tEND").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid instruction: FOO, line: 2\n\t\tFOO\tGR1");
+        let err = ASTNode::de("MAIN\tSTART\n\tRET\nBUF\tDS\n\tEND").unwrap_err();
+        assert!(err.to_string().ends_with("line: 3\n\tBUF\tDS"));
+        let err = CodeGenerator::assemble("MAIN\tSTART\nA\tRET\nA\tRET\n\tEND").err().unwrap();
+        assert_eq!(err.to_string(), "Analyze error: Duplicate label: A, line: 3");
+    }
+
+    #[test]
+    fn test_code_gen() {
+        let input = "MAIN\tSTART\tBEGIN\nDAT\tDC\t3,#FFFF,'AB'\nBEGIN\tLD\tGR1,DAT,GR2\n\tADDA\tGR1,GR2\n\tJUMP\tBEGIN\n\tRET\n\tEND";
+        let (code_gen, bin) = CodeGenerator::assemble(input).unwrap();
+        assert_eq!(
+            bin,
+            vec![3, 0xFFFF, 'A' as u16, 'B' as u16, 0x1012, 0x0000, 0x2412, 0x6400, 0x0004, 0x8100]
+        );
+        assert_eq!(code_gen.entry, 4);
+        assert_eq!(code_gen.label_map["MAIN"], 4);
+        assert_eq!(code_gen.programs[0].labels["DAT"], 0);
+    }
+
+    #[test]
+    fn test_macro_and_literal() {
+        let input = "MAIN\tSTART\n\tRPUSH\n\tADDA\tGR1,=5\n\tOUT\tBUF,LEN\n\tRPOP\n\tRET\nBUF\tDC\t'HI'\nLEN\tDC\t2\n\tEND";
+        let (_, bin) = CodeGenerator::assemble(input).unwrap();
+        assert_eq!(bin.len(), 40);
+        // RPUSH は PUSH 0,GR1 から PUSH 0,GR7
+        assert_eq!((bin[0], bin[1], bin[12]), (0x7001, 0x0000, 0x7007));
+        // OUT は PUSH, PUSH, LAD, LAD, SVC 2, POP, POP
+        assert_eq!((bin[24], bin[25], bin[26], bin[27]), (0xF000, 2, 0x7120, 0x7110));
+        // RPOP は POP GR7 から POP GR1
+        assert_eq!((bin[28], bin[34]), (0x7170, 0x7110));
+        // リテラルはENDの位置に置かれる
+        assert_eq!((bin[15], bin[39]), (39, 5));
+    }
+
+    #[test]
+    fn test_source_map() {
+        let input = "MAIN\tSTART\n\tRPUSH\n\tADDA\tGR1,=5\n\tOUT\tBUF,LEN\n\tRET\nBUF\tDC\t'HI'\nLEN\tDC\t2\n\tEND";
+        let (code_gen, bin) = CodeGenerator::assemble(input).unwrap();
+        let map = &code_gen.source_map;
+        assert_eq!(map.entries.len(), bin.len());
+
+        // RPUSH は7命令14語
+        assert_eq!(map.addresses(0, 1).len(), 14);
+        assert_eq!(map.lookup(13).unwrap().origin, Origin::Macro("RPUSH".to_string()));
+        // ADDA の2語目はリテラルの列
+        assert_eq!(map.line_addr(0, 2), Some(14));
+        assert_eq!(map.lookup(15).unwrap().column, 10);
+        // リテラルはENDの位置に置かれ、使った行を指す
+        let literal = bin.len() as u16 - 1;
+        assert_eq!(bin[literal as usize], 5);
+        assert_eq!(map.lookup(literal).unwrap().line, 2);
+        assert_eq!(map.lookup(literal).unwrap().origin, Origin::Literal("=5".to_string()));
+        assert_eq!(map.describe(14).unwrap(), "line 3: ADDA GR1,=5");
+        assert_eq!(map.describe(16).unwrap(), "line 4: OUT BUF,LEN (expanded from OUT)");
+        // 文字定数は1文字ずつ列が進む
+        let buf = code_gen.programs[0].labels["BUF"];
+        assert_eq!(map.lookup(buf + 1).unwrap().column, 9);
+
+        // ローダーが置く番地からのソースマップとラベル
+        let mut code_gen = CodeGenerator::from_source("", input).unwrap();
+        code_gen.base = 0x100;
+        let relocated = code_gen.generate().unwrap();
+        let map = &code_gen.source_map;
+        assert_eq!(map.line_addr(0, 2), Some(0x10E));
+        assert_eq!(map.describe(0x10E).unwrap(), "line 3: ADDA GR1,=5");
+        assert!(map.lookup(14).is_none());
+        assert_eq!(relocated[15], 0x100 + literal);
+        assert_eq!(code_gen.lookup("BUF"), Some(0x100 + buf));
+        assert_eq!(code_gen.symbolize(0x100 + buf + 1), "BUF+1");
+    }
+
+    #[test]
+    fn test_multiple_programs() {
+        let input = "MAIN\tSTART\n\tLD\tGR1,=1\n\tCALL\tSUB\nA\tRET\n\tEND\nSUB\tSTART\tB\nA\tDC\t5\nB\tADDA\tGR1,=1\n\tJUMP\tA\n\tEND";
+        let (code_gen, bin) = CodeGenerator::assemble(input).unwrap();
+        // プログラムは続けて置かれ、リテラルはそれぞれのENDに置かれる
+        assert_eq!(
+            bin,
+            vec![0x1010, 5, 0x8000, 7, 0x8100, 1, 5, 0x2010, 11, 0x6400, 6, 1]
+        );
+        assert_eq!(code_gen.programs[1].addr, 6);
+        assert_eq!(code_gen.programs[1].size, 6);
+        // STARTのラベルだけが外から見える
+        assert_eq!(code_gen.label_map.len(), 2);
+        assert_eq!(code_gen.label_map["SUB"], 7);
+        assert_eq!(code_gen.programs[0].labels["A"], 4);
+        assert_eq!(code_gen.programs[1].labels["A"], 6);
+
+        let input = "MAIN\tSTART\n\tJUMP\tB\n\tEND\nSUB\tSTART\nB\tRET\n\tEND";
+        assert!(CodeGenerator::assemble(input).is_err());
+    }
+
+    #[test]
+    fn test_full_memory_program() {
+        // 65536語ちょうどのプログラムも語数を失わない
+        let input = "MAIN\tSTART\n\tRET\nBUF\tDS\t65535\n\tEND";
+        let mut code_gen = CodeGenerator::from_source("", input).unwrap();
+        let objects = code_gen.generate_object().unwrap();
+        assert_eq!(code_gen.programs[0].size, 0x10000);
+        assert_eq!(objects[0].code.len(), 0x10000);
+        // 戻り番地を積む場所がないので置けない
+        let err = Loader::new().load_source(input).err().unwrap();
+        assert_eq!(err.to_string(), "Runtime error: Stack top #FFFF overlaps the program");
+        // 1語でもあふれればアセンブルできない
+        assert!(CodeGenerator::assemble("MAIN\tSTART\n\tRET\nBUF\tDS\t65536\n\tEND").is_err());
+        assert!(CodeGenerator::assemble("MAIN\tSTART\n\tRET\n\tRET\nBUF\tDS\t65535\n\tEND").is_err());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,72 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::{emurator::{commet2::{bus::{AccessKind, ConsolePort, Framebuffer, MemoryMap, Rom, TimerRegister}, console::BufferConsole, cpu::CPUExecution, prefix::machine_cycle}, loader::Loader}, grader::{harness::Harness, Outcome}};
+
+    const SRC: &str = "MAIN\tSTART
+\tLD\tGR3,#F000
+\tLAD\tGR1,72
+\tST\tGR1,#F000
+\tLAD\tGR1,10
+\tST\tGR1,#F000
+\tLD\tGR2,#F001
+\tLAD\tGR1,65
+\tST\tGR1,#F100
+\tST\tGR1,#F105
+\tST\tGR1,#E000
+\tLD\tGR4,#E000
+\tRET
+\tEND";
+
+    #[test]
+    fn test_memory_map() {
+        let mut cpu = Loader::new().load_source(SRC).unwrap();
+        let console = BufferConsole::new(&["Z"]);
+        let screen = Framebuffer::new(4, 2);
+        let mut map = MemoryMap::new();
+        map.map(0xF000, Box::new(ConsolePort::new(Box::new(console.clone())))).unwrap();
+        map.map(0xF001, Box::new(TimerRegister)).unwrap();
+        map.map(0xF100, Box::new(screen.clone())).unwrap();
+        map.map(0xE000, Box::new(Rom::new(vec![7]))).unwrap();
+        assert!(map.map(0xF101, Box::new(TimerRegister)).is_err());
+        cpu.bus = Some(Box::new(map));
+        for _ in 0..1000 {
+            if cpu.state.machine_cycle == machine_cycle::END {
+                break;
+            }
+            cpu.commet2_step();
+        }
+        assert_eq!(cpu.state.machine_cycle, machine_cycle::END);
+        assert_eq!(cpu.state.gr.get(3), 'Z' as u16);
+        assert_eq!(console.output(), ["H"]);
+        // タイマーはLDのメモリ読み出しのサイクル
+        assert!(cpu.state.gr.get(2) > 0 && (cpu.state.gr.get(2) as u64) < cpu.state.cycle);
+        assert_eq!(screen.lines(), ["A   ", " A  "]);
+        // ROMには書けない
+        assert_eq!(cpu.state.gr.get(4), 7);
+        assert_eq!(cpu.state.memory.0[0xE000], 0);
+    }
+
+    /// IN/OUTの領域もバスと保護を通す
+    #[test]
+    fn test_console_through_bus() {
+        let mut h = Harness::new("MAIN\tSTART\n\tOUT\t#E000,#E002\n\tIN\t#F100,LEN\n\tRET\nLEN\tDS\t1\n\tEND").unwrap();
+        let screen = Framebuffer::new(4, 2);
+        let mut map = MemoryMap::new();
+        map.map(0xE000, Box::new(Rom::new(vec!['H' as u16, 'I' as u16, 2]))).unwrap();
+        map.map(0xF100, Box::new(screen.clone())).unwrap();
+        h.cpu.bus = Some(Box::new(map));
+        h.input("AB").call("MAIN");
+        assert_eq!(h.output(), ["HI"]);
+        assert_eq!(screen.lines(), ["AB  ", "    "]);
+        assert_eq!(h.mem("LEN"), 2);
+
+        // 命令の上には読み込めない
+        let mut h = Harness::new("MAIN\tSTART\n\tIN\tMAIN,LEN\n\tRET\nLEN\tDS\t1\n\tEND").unwrap();
+        h.cpu.protection = Some(h.code_gen.protection());
+        h.input("X");
+        assert!(matches!(h.try_call("MAIN"), Err(Outcome::Fault(_))));
+        let fault = h.cpu.fault.clone().unwrap();
+        assert_eq!((fault.addr, fault.kind), (0, AccessKind::Write));
+        assert_ne!(h.mem("MAIN"), 'X' as u16);
+    }
+}
@@ -0,0 +1,60 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::{emurator::{casl2::backtrace::{backtrace, describe_fault, describe_mismatch}, loader::Loader}, grader::{harness::Harness, Outcome}};
+
+    /// DIVIDEが自分の命令を書き換えて止まる
+    const SRC: &str = "MAIN\tSTART
+\tLAD\tGR1,1
+\tCALL\tSUB
+\tRET
+SUB\tCALL\tDIVIDE
+\tRET
+DIVIDE\tLAD\tGR2,0
+\tST\tGR2,MAIN
+\tRET
+\tEND";
+
+    #[test]
+    fn test_fault_backtrace() {
+        let mut h = Harness::new(SRC).unwrap();
+        h.cpu.protection = Some(h.code_gen.protection());
+        assert!(matches!(h.try_call("MAIN"), Err(Outcome::Fault(_))));
+        let (code_gen, cpu) = (&h.code_gen, &h.cpu);
+        let fault = cpu.fault.clone().unwrap();
+        let frames = &cpu.calls.frames;
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].call_site, frames[0].target, frames[0].gr[1]), (2, 5, 1));
+        assert_eq!(frames[1].sp, cpu.state.sp);
+        assert_eq!(cpu.calls.backtrace(fault.pr), vec![10, 5, 2]);
+        assert_eq!(backtrace(code_gen, fault.pr, &cpu.calls), vec!["in DIVIDE+2 (line 8: ST GR2,MAIN)", "called from SUB (line 5: CALL DIVIDE)", "called from MAIN+2 (line 3: CALL SUB)"]);
+        assert!(describe_fault(code_gen, &fault, &cpu.calls).starts_with("write to read-only memory (write MAIN) in DIVIDE+2 called from SUB called from MAIN+2\n"));
+        assert!(cpu.calls.mismatches.is_empty());
+    }
+
+    #[test]
+    fn test_fault_backtrace_at_base() {
+        let mut h = Harness::load(SRC, Loader { base: 0x200, ..Loader::new() }).unwrap();
+        h.cpu.protection = Some(h.code_gen.protection());
+        assert!(matches!(h.try_call("MAIN"), Err(Outcome::Fault(_))));
+        let (code_gen, cpu) = (&h.code_gen, &h.cpu);
+        let fault = cpu.fault.clone().unwrap();
+        assert_eq!(cpu.calls.backtrace(fault.pr), vec![0x20A, 0x205, 0x202]);
+        assert_eq!(backtrace(code_gen, fault.pr, &cpu.calls), vec!["in DIVIDE+2 (line 8: ST GR2,MAIN)", "called from SUB (line 5: CALL DIVIDE)", "called from MAIN+2 (line 3: CALL SUB)"]);
+        assert!(describe_fault(code_gen, &fault, &cpu.calls).starts_with("write to read-only memory (write MAIN) in DIVIDE+2 called from SUB called from MAIN+2\n"));
+    }
+
+    #[test]
+    fn test_mismatched_ret() {
+        // 積んだものを降ろし忘れて戻る
+        let mut h = Harness::new("MAIN\tSTART\n\tCALL\tSUB\n\tRET\nSUB\tPUSH\tOTHER\n\tRET\nOTHER\tPOP\tGR1\n\tRET\n\tEND").unwrap();
+        h.call("MAIN");
+        let (code_gen, cpu) = (&h.code_gen, &h.cpu);
+        assert_eq!(cpu.calls.mismatches.len(), 1);
+        let mismatch = &cpu.calls.mismatches[0];
+        assert_eq!((mismatch.pr, mismatch.to, mismatch.expected), (5, 6, Some(2)));
+        assert_eq!(describe_mismatch(code_gen, mismatch), "RET at SUB+2 returned to OTHER (expected MAIN+2): the stack is probably corrupted");
+        assert!(cpu.calls.frames.is_empty());
+        // 最後のRETでいちばん外から出た
+        assert_eq!(cpu.calls.exits, 1);
+    }
+}
@@ -0,0 +1,103 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::emurator::{casl2::{code_gen::CodeGenerator, parser::ASTNode}, commet2::{console::BufferConsole, cpu::CPUExecution, prefix::machine_cycle}, loader::Loader};
+
+    /// 1行読んでそのまま書き出す 入力がなくなったら終わる
+    const ECHO: &str = "MAIN\tSTART
+LOOP\tIN\tBUF,LEN
+\tLD\tGR1,LEN
+\tJMI\tFIN
+\tOUT\tBUF,LEN
+\tJUMP\tLOOP
+FIN\tRET
+BUF\tDS\t256
+LEN\tDS\t1
+\tEND";
+
+    #[test]
+    fn test_echo() {
+        let mut cpu = Loader::new().load_source(ECHO).unwrap();
+        let console = BufferConsole::new(&["HELLO", "", "CASL2"]);
+        cpu.supervisor = Some(Box::new(console.clone()));
+        for _ in 0..200 {
+            if cpu.state.machine_cycle == machine_cycle::END {
+                break;
+            }
+            cpu.casl_step();
+        }
+        assert_eq!(cpu.state.machine_cycle, machine_cycle::END);
+        assert_eq!(console.output(), vec!["HELLO", "", "CASL2"]);
+        // 入力の終わりは-1
+        assert_eq!(cpu.state.gr.get(1), 0xFFFF);
+    }
+
+    #[test]
+    fn test_unknown_svc() {
+        let mut cpu = Loader::new().load_source("MAIN\tSTART\n\tLAD\tGR1,1\n\tSVC\t9\n\tLAD\tGR1,2\n\tRET\n\tEND").unwrap();
+        cpu.supervisor = Some(Box::new(BufferConsole::default()));
+        for _ in 0..20 {
+            if cpu.state.machine_cycle == machine_cycle::END {
+                break;
+            }
+            cpu.casl_step();
+        }
+        assert_eq!(cpu.fault.unwrap().to_string(), "unknown SVC 9: fetch #0002 at PR #0002");
+        assert_eq!(cpu.state.gr.get(1), 1);
+    }
+
+    #[test]
+    fn test_indexed_wraps_around() {
+        // 指標レジスタで足した番地は#FFFFを越えると0に戻る
+        let src = "MAIN\tSTART\n\tLAD\tGR3,-1\n\tLAD\tGR1,MSG2,GR3\n\tLAD\tGR2,LEN\n\tSVC\t2\n\tRET\nMSG\tDC\t'O'\nMSG2\tDC\t'K'\nLEN\tDC\t2\n\tEND";
+        let mut cpu = Loader::new().load_source(src).unwrap();
+        let console = BufferConsole::default();
+        cpu.supervisor = Some(Box::new(console.clone()));
+        for _ in 0..20 {
+            if cpu.state.machine_cycle == machine_cycle::END {
+                break;
+            }
+            cpu.casl_step();
+        }
+        assert!(cpu.fault.is_none());
+        assert_eq!(console.output(), vec!["OK"]);
+    }
+
+    #[test]
+    fn test_katakana() {
+        let src = "MAIN\tSTART\n\tOUT\tMSG,LEN\n\tRET\nMSG\tDC\t'ｶﾅ¥1'\nLEN\tDC\t4\n\tEND";
+        let (code_gen, bin) = CodeGenerator::assemble(src).unwrap();
+        let msg = code_gen.programs[0].labels["MSG"] as usize;
+        assert_eq!(bin[msg..msg + 4], [0xB6, 0xC5, 0x5C, 0x31]);
+
+        let mut cpu = Loader::new().load_source(src).unwrap();
+        let console = BufferConsole::default();
+        cpu.supervisor = Some(Box::new(console.clone()));
+        for _ in 0..20 {
+            if cpu.state.machine_cycle == machine_cycle::END {
+                break;
+            }
+            cpu.casl_step();
+        }
+        assert_eq!(cpu.state.machine_cycle, machine_cycle::END);
+        assert_eq!(console.output(), vec!["ｶﾅ¥1"]);
+
+        // JIS X 0201 にない文字
+        assert!(CodeGenerator::assemble("MAIN\tSTART\nMSG\tDC\t'漢'\n\tEND").is_err());
+    }
+
+    #[test]
+    fn test_string_with_separators() {
+        // 文字定数の中の , と ; は区切りでもコメントでもない
+        let src = "MAIN\tSTART\n\tLD\tGR1,=';'\n\tRET\nMSG\tDC\t'A,B;C','IT''S',3 ; 'X,Y'\n\tEND";
+        let (code_gen, bin) = CodeGenerator::assemble(src).unwrap();
+        let msg = code_gen.programs[0].labels["MSG"] as usize;
+        let text: Vec<u16> = "A,B;CIT'S".chars().map(|c| c as u16).collect();
+        assert_eq!(bin[msg..msg + 9], text[..]);
+        assert_eq!(bin[msg + 9], 3);
+        // リテラルは最後
+        assert_eq!(bin[bin[1] as usize], ';' as u16);
+        let Some(ASTNode::AssemblerInstruction { operands, comment, .. }) = code_gen.nodes.get(3) else { panic!() };
+        assert_eq!(operands, &["'A,B;C'", "'IT''S'", "3"]);
+        assert_eq!(comment.as_deref(), Some("'X,Y'"));
+    }
+}
@@ -0,0 +1,65 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::{emurator::{casl2::convention::{allow, describe}, commet2::convention::ConventionChecker}, grader::harness::Harness};
+
+    /// ADDはGR1に結果を返す BADはGR3を壊す GOODはRPUSH/RPOPで守る
+    const SRC: &str = "MAIN\tSTART
+\tLAD\tGR1,3
+\tLAD\tGR2,4
+\tCALL\tADD
+\tCALL\tBAD
+\tCALL\tGOOD
+\tRET
+ADD\tADDA\tGR1,GR2
+\tRET
+BAD\tLAD\tGR3,1
+\tRET
+GOOD\tRPUSH
+\tLAD\tGR4,9
+\tRPOP
+\tRET
+\tEND";
+
+    #[test]
+    fn test_convention() {
+        let mut h = Harness::new(SRC).unwrap();
+        h.cpu.convention = Some(ConventionChecker::new());
+        h.call("MAIN");
+        let violations = &h.cpu.convention.as_ref().unwrap().violations;
+        let found: Vec<_> = violations.iter().map(|c| (h.code_gen.symbolize(c.routine), c.register, c.before, c.after)).collect();
+        assert_eq!(found, vec![("ADD".to_string(), 1, 3, 7), ("BAD".to_string(), 3, 0, 1)]);
+        assert_eq!(describe(&h.code_gen, &violations[1]), "line 11: RET: BAD clobbers GR3 (#0000 -> #0001)\n    called from line 5: CALL BAD\n");
+
+        // ADDの戻り値は許す
+        let mut h = Harness::new(SRC).unwrap();
+        h.cpu.convention = Some(allow(&h.code_gen, ConventionChecker::new(), "ADD=GR1").unwrap());
+        h.call("MAIN");
+        let violations = &h.cpu.convention.as_ref().unwrap().violations;
+        assert_eq!(violations.len(), 1);
+        assert_eq!((violations[0].routine, violations[0].call_site, violations[0].ret), (13, 6, 15));
+
+        assert!(allow(&h.code_gen, ConventionChecker::new(), "NOPE=GR1").is_err());
+        assert!(allow(&h.code_gen, ConventionChecker::new(), "ADD=GR8").is_err());
+    }
+
+    /// CPA/CPLはフラグだけ変えるのでGRを壊さない
+    #[test]
+    fn test_convention_compare_only() {
+        let src = "MAIN\tSTART
+\tLAD\tGR1,5
+\tLAD\tGR2,7
+\tCALL\tCMP
+\tRET
+CMP\tCPA\tGR1,FIVE
+\tCPL\tGR2,GR1
+\tRET
+FIVE\tDC\t5
+\tEND";
+        let mut h = Harness::new(src).unwrap();
+        h.cpu.convention = Some(ConventionChecker::new());
+        h.call("MAIN");
+        assert!(h.cpu.convention.as_ref().unwrap().violations.is_empty());
+        h.assert_reg("GR1", 5);
+        h.assert_reg("GR2", 7);
+    }
+}
@@ -0,0 +1,46 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::{emurator::{casl2::coverage::CoverageReport, commet2::coverage::Coverage, loader::Loader}, grader::harness::Harness};
+
+    /// GR1が負なら0にする 3回まわる
+    const SRC: &str = "MAIN\tSTART
+\tLAD\tGR2,3
+LOOP\tLD\tGR1,GR1
+\tJMI\tNEG
+\tSUBA\tGR2,=1
+\tJNZ\tLOOP
+\tRET
+NEG\tLAD\tGR1,0
+\tRET
+\tEND";
+
+    #[test]
+    fn test_coverage() {
+        let mut h = Harness::new(SRC).unwrap();
+        h.cpu.coverage = Some(Coverage::new());
+        h.set("GR1", 5).call("MAIN");
+        let report = CoverageReport::new(&h.code_gen, h.cpu.coverage.as_ref().unwrap());
+        assert_eq!(report.line_summary(), (6, 8));
+        assert_eq!(report.branch_summary(), (3, 4));
+
+        let listing = report.listing();
+        assert!(listing.contains("        3:    4:\tJMI\tNEG\n           branch taken 0, not taken 3\n"));
+        assert!(listing.contains("    #####:    8:NEG\tLAD\tGR1,0\n"));
+        assert!(listing.ends_with("lines: 6/8 (75.0%), branches: 3/4 (75.0%)\n"));
+
+        let lcov = report.lcov();
+        assert!(lcov.contains("BRDA:4,0,0,0\nBRDA:4,0,1,3\n"));
+        assert!(lcov.contains("DA:8,0\n"));
+        assert!(lcov.ends_with("LF:8\nLH:6\nend_of_record\n"));
+
+        assert!(report.html().contains("<tr class=\"partial\"><td class=\"line\">4</td><td class=\"count\">3</td>"));
+
+        // ローダーがずらして置いても同じ行に対応する
+        let mut h = Harness::load(SRC, Loader { base: 0x300, ..Loader::new() }).unwrap();
+        h.cpu.coverage = Some(Coverage::new());
+        h.set("GR1", 5).call("MAIN");
+        let relocated = CoverageReport::new(&h.code_gen, h.cpu.coverage.as_ref().unwrap());
+        assert_eq!(relocated.lines, report.lines);
+        assert_eq!(relocated.branches[0].addr, 0x300 + report.branches[0].addr);
+    }
+}
@@ -0,0 +1,143 @@
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use x_casl2::dap::{read_message, write_message, Session};
+
+    const SRC: &str = "MAIN\tSTART\n\tLAD\tGR1,2\n\tCALL\tSUB\n\tST\tGR1,ANS\n\tRET\nSUB\tADDA\tGR1,GR1\n\tRET\nANS\tDS\t1\n\tEND";
+
+    fn request(seq: i64, command: &str, arguments: Value) -> Value {
+        json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments })
+    }
+
+    fn run(session: &mut Session, out: &mut Vec<Value>) {
+        while session.is_running() {
+            out.extend(session.resume(100));
+        }
+    }
+
+    #[test]
+    fn test_breakpoint_and_stack_trace() {
+        let mut session = Session::new();
+        session.handle(&request(1, "initialize", json!({})));
+        let out = session.launch_source(&request(2, "launch", json!({})), SRC);
+        assert_eq!(out[1]["event"], "initialized");
+
+        // SUBの先頭 (6行目) に止める
+        let out = session.handle(&request(3, "setBreakpoints", json!({ "breakpoints": [{ "line": 6 }] })));
+        assert_eq!(out[0]["body"]["breakpoints"][0]["verified"], true);
+        let mut out = session.handle(&request(4, "configurationDone", json!({})));
+        run(&mut session, &mut out);
+        assert_eq!(out.last().unwrap()["body"]["reason"], "breakpoint");
+
+        let out = session.handle(&request(5, "stackTrace", json!({ "threadId": 1 })));
+        let frames = out[0]["body"]["stackFrames"].as_array().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0]["line"], 6);
+        assert_eq!(frames[1]["line"], 3);
+
+        let out = session.handle(&request(6, "variables", json!({ "variablesReference": 1 })));
+        assert_eq!(out[0]["body"]["variables"][1]["value"], "#0002 (2)");
+
+        // SUBから抜けてST命令で止まる
+        let mut out = session.handle(&request(7, "stepOut", json!({ "threadId": 1 })));
+        run(&mut session, &mut out);
+        assert_eq!(out.last().unwrap()["body"]["reason"], "step");
+        let out = session.handle(&request(8, "stackTrace", json!({ "threadId": 1 })));
+        assert_eq!(out[0]["body"]["stackFrames"][0]["line"], 4);
+
+        let mut out = session.handle(&request(9, "continue", json!({ "threadId": 1 })));
+        run(&mut session, &mut out);
+        assert_eq!(out.last().unwrap()["event"], "exited");
+        let out = session.handle(&request(10, "variables", json!({ "variablesReference": 2 })));
+        let ans = out[0]["body"]["variables"].as_array().unwrap().iter().find(|v| v["name"] == "ANS").unwrap().clone();
+        assert_eq!(ans["value"], "#0004 (4)");
+    }
+
+    #[test]
+    fn test_breakpoint_on_entry() {
+        let mut session = Session::new();
+        session.handle(&request(1, "initialize", json!({})));
+        session.launch_source(&request(2, "launch", json!({})), SRC);
+        session.handle(&request(3, "setBreakpoints", json!({ "breakpoints": [{ "line": 2 }] })));
+        let mut out = session.handle(&request(4, "configurationDone", json!({})));
+        run(&mut session, &mut out);
+        assert_eq!(out.last().unwrap()["body"]["reason"], "breakpoint");
+        let out = session.handle(&request(5, "stackTrace", json!({ "threadId": 1 })));
+        assert_eq!(out[0]["body"]["stackFrames"][0]["line"], 2);
+
+        // 止まった所からは進める
+        let mut out = session.handle(&request(6, "continue", json!({ "threadId": 1 })));
+        run(&mut session, &mut out);
+        assert_eq!(out.last().unwrap()["event"], "exited");
+
+        // stopOnEntryで止まった所のブレークポイントでもう一度止まらない
+        let mut session = Session::new();
+        session.handle(&request(1, "initialize", json!({})));
+        session.launch_source(&request(2, "launch", json!({ "stopOnEntry": true })), SRC);
+        session.handle(&request(3, "setBreakpoints", json!({ "breakpoints": [{ "line": 2 }] })));
+        let out = session.handle(&request(4, "configurationDone", json!({})));
+        assert_eq!(out.last().unwrap()["body"]["reason"], "entry");
+        let mut out = session.handle(&request(5, "continue", json!({ "threadId": 1 })));
+        run(&mut session, &mut out);
+        assert_eq!(out.last().unwrap()["event"], "exited");
+    }
+
+    #[test]
+    fn test_fault() {
+        // SUBが命令を書き換える
+        let src = "MAIN\tSTART\n\tCALL\tSUB\n\tRET\nSUB\tST\tGR1,MAIN\n\tRET\n\tEND";
+        let mut session = Session::new();
+        session.handle(&request(1, "initialize", json!({})));
+        session.launch_source(&request(2, "launch", json!({ "protect": true })), src);
+        let mut out = session.handle(&request(3, "configurationDone", json!({})));
+        run(&mut session, &mut out);
+        let stopped = out.last().unwrap();
+        assert_eq!(stopped["body"]["reason"], "exception");
+        assert_eq!(stopped["body"]["text"], "write to read-only memory: write #0000 at PR #0003");
+        let out = session.handle(&request(4, "stackTrace", json!({ "threadId": 1 })));
+        let frames = out[0]["body"]["stackFrames"].as_array().unwrap();
+        assert_eq!((frames.len(), &frames[0]["line"], &frames[1]["line"]), (2, &json!(4), &json!(2)));
+        assert_eq!(frames[0]["name"], "SUB @ SUB");
+
+        // 続けると失敗で終わる
+        let mut out = session.handle(&request(5, "continue", json!({ "threadId": 1 })));
+        run(&mut session, &mut out);
+        assert_eq!(out.last().unwrap()["body"]["exitCode"], 1);
+
+        // 実行できない命令
+        let mut session = Session::new();
+        session.handle(&request(1, "initialize", json!({})));
+        session.launch_source(&request(2, "launch", json!({})), "MAIN\tSTART\n\tDC\t#FF00\n\tEND");
+        let mut out = session.handle(&request(3, "configurationDone", json!({})));
+        run(&mut session, &mut out);
+        assert_eq!(out.last().unwrap()["body"]["text"], "invalid instruction: fetch #0000 at PR #0000");
+    }
+
+    #[test]
+    fn test_message_framing() {
+        let mut buf = Vec::new();
+        write_message(&mut buf, &json!({ "seq": 1 })).unwrap();
+        let mut reader = std::io::Cursor::new(buf);
+        assert_eq!(read_message(&mut reader).unwrap(), Some(json!({ "seq": 1 })));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_console_input() {
+        let src = "MAIN\tSTART\n\tIN\tBUF,LEN\n\tOUT\tBUF,LEN\n\tRET\nBUF\tDS\t256\nLEN\tDS\t1\n\tEND";
+        let mut session = Session::new();
+        session.handle(&request(1, "initialize", json!({})));
+        session.launch_source(&request(2, "launch", json!({})), src);
+        let mut out = session.handle(&request(3, "configurationDone", json!({})));
+        run(&mut session, &mut out);
+        // 入力がないのでINの手前で止まる
+        assert_eq!(out.last().unwrap()["body"]["description"], "Waiting for input");
+
+        session.handle(&request(4, "evaluate", json!({ "expression": "HI", "context": "repl" })));
+        let mut out = session.handle(&request(5, "continue", json!({ "threadId": 1 })));
+        run(&mut session, &mut out);
+        let output: Vec<&Value> = out.iter().filter(|m| m["event"] == "output").collect();
+        assert_eq!(output[0]["body"]["output"], "HI\n");
+        assert_eq!(out.last().unwrap()["event"], "exited");
+    }
+}
@@ -0,0 +1,57 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::emurator::casl2::{code_gen::CodeGenerator, formatter::{check, format_source}};
+
+    #[test]
+    fn test_format_source() {
+        let input = "; sample\nmain start\n  ld gr1 , data ;load\nLOOP  adda GR1,gr2\n   ; inner\n\tlad\tgr2,#00ff\n\tret\nDATA DC 'a,b' , 3\n\tEND";
+        let formatted = format_source(input).unwrap();
+        assert_eq!(
+            formatted,
+            "; sample\n\
+             main\tSTART\n\
+             \tLD\tGR1,data\t; load\n\
+             LOOP\tADDA\tGR1,GR2\n\
+             \t; inner\n\
+             \tLAD\tGR2,#00FF\n\
+             \tRET\n\
+             DATA\tDC\t'a,b',3\n\
+             \tEND\n"
+        );
+        // 整形済みなら変わらない
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+        assert!(check(&formatted).unwrap().is_empty());
+        assert_eq!(check(input).unwrap()[0].line, 1);
+        // 解析できない行はエラー
+        assert!(format_source("\tFOO\tGR1").is_err());
+        // ラベルだけの行を消さない
+        assert!(format_source("MAIN\tSTART\nLOOP\n\tRET\n\tEND\n").is_err());
+        assert!(check("MAIN\tSTART\nLOOP ; here\n\tEND\n").is_err());
+    }
+
+    #[test]
+    fn test_space_separated() {
+        // 空白で区切った行も整形した行も同じようにアセンブルできる
+        let input = "MAIN  START\n  LAD  GR1,1 ; one\nDAT DC 'A B',2\n  RET\n  END\n";
+        let formatted = format_source(input).unwrap();
+        assert_eq!(formatted, "MAIN\tSTART\n\tLAD\tGR1,1\t\t; one\nDAT\tDC\t'A B',2\n\tRET\n\tEND\n");
+        assert_eq!(CodeGenerator::assemble(input).unwrap().1, CodeGenerator::assemble(&formatted).unwrap().1);
+    }
+
+    #[test]
+    fn test_long_label() {
+        // 8文字のラベルはタブ1つで次の列 命令欄とオペランド欄はタブ1つ分ずれ、コメント欄は届かなければずれない
+        let input = "LONGNAME START ; entry\nLONGLOOP LAD GR1,1 ; one\nLONGDATA DC 'ABCDEFG' ; text\n\tRET\n\tEND\n";
+        let formatted = format_source(input).unwrap();
+        assert_eq!(
+            formatted,
+            "LONGNAME\tSTART\t\t; entry\n\
+             LONGLOOP\tLAD\tGR1,1\t; one\n\
+             LONGDATA\tDC\t'ABCDEFG'\t; text\n\
+             \tRET\n\
+             \tEND\n"
+        );
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+        assert_eq!(CodeGenerator::assemble(input).unwrap().1, CodeGenerator::assemble(&formatted).unwrap().1);
+    }
+}
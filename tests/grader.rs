@@ -0,0 +1,67 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::grader::{grade_all, report::{to_json, to_junit}, Outcome, Submission, TestCase};
+
+    /// GR1+DATを ANS に入れて、1行読んでそのまま書く
+    const CASES: &str = r#"[
+        {
+            "name": "add",
+            "input": ["HI"],
+            "registers": { "GR1": 3 },
+            "memory": { "DAT": 4 },
+            "expected": { "output": ["HI"], "registers": { "GR1": 7 }, "memory": { "ANS": 7 } },
+            "max_steps": 100,
+            "max_stack": 3
+        }
+    ]"#;
+
+    const GOOD: &str = "MAIN\tSTART\n\tADDA\tGR1,DAT\n\tST\tGR1,ANS\n\tIN\tBUF,LEN\n\tOUT\tBUF,LEN\n\tRET\nDAT\tDS\t1\nANS\tDS\t1\nBUF\tDS\t256\nLEN\tDS\t1\n\tEND";
+
+    #[test]
+    fn test_grade() {
+        let cases = TestCase::list_from_json(CASES).unwrap();
+        let submissions = [
+            ("good", GOOD.to_string()),
+            ("wrong", GOOD.replace("ADDA", "SUBA")),
+            ("syntax", "MAIN\tSTART\n\tFOO\tGR1\n\tEND".to_string()),
+            ("loop", "MAIN\tSTART\nL\tJUMP\tL\nDAT\tDS\t1\n\tEND".to_string()),
+            ("fault", "MAIN\tSTART\n\tDC\t#FF00\nDAT\tDS\t1\n\tEND".to_string()),
+            ("stack", GOOD.replace("\tRET\n", "\tRPUSH\n\tRPOP\n\tRET\n")),
+        ]
+        .map(|(student, source)| Submission { student: student.to_string(), source });
+        let results = grade_all(&submissions, &cases);
+        let status: Vec<&str> = results.iter().map(|r| r.cases[0].outcome.status()).collect();
+        assert_eq!(status, ["passed", "failed", "assemble_error", "timeout", "fault", "failed"]);
+        assert_eq!(results[0].cases[0].output, ["HI"]);
+        let Outcome::Failed(diffs) = &results[1].cases[0].outcome else { panic!() };
+        assert_eq!(diffs[0], "GR1: expected #0007, got #FFFF");
+        // 戻り番地とRPUSHの7語
+        let Outcome::Failed(diffs) = &results[5].cases[0].outcome else { panic!() };
+        assert_eq!(diffs, &["stack: expected at most 3 words, used 8"]);
+
+        let json = to_json(&results);
+        assert_eq!(json["students"][0]["passed"], 1);
+        // 戻り番地に着いたのは数えない
+        assert_eq!(json["students"][0]["cases"][0]["steps"], 17);
+        assert_eq!(json["students"][3]["cases"][0]["steps"], 100);
+        let junit = to_junit(&results);
+        assert!(junit.contains("<testsuite name=\"wrong\" tests=\"1\" failures=\"1\" errors=\"0\">"));
+        assert!(junit.contains("<error type=\"timeout\""));
+    }
+
+    #[test]
+    fn test_case_error() {
+        // 書き込む場所が知らないラベルなら提出物の失敗ではない
+        let cases = TestCase::list_from_json(&CASES.replace("\"DAT\"", "\"DTA\"")).unwrap();
+        let results = grade_all(&[Submission { student: "good".to_string(), source: GOOD.to_string() }], &cases);
+        assert_eq!(results[0].cases[0].outcome, Outcome::CaseError("DTA: unknown label".to_string()));
+        assert!(to_junit(&results).contains("<error type=\"case_error\" message=\"DTA: unknown label\">"));
+
+        // XML 1.0で書けない制御文字は残さない
+        let mut results = grade_all(&[Submission { student: "bell\u{7}".to_string(), source: GOOD.to_string() }], &cases);
+        results[0].cases[0].output = vec!["\u{1B}[0m\tOK".to_string()];
+        let junit = to_junit(&results);
+        assert!(junit.contains("<testsuite name=\"bell\u{FFFD}\""));
+        assert!(junit.contains("<system-out>\u{FFFD}[0m\tOK</system-out>"));
+    }
+}
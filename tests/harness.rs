@@ -0,0 +1,47 @@
+#[cfg(test)]
+mod tests {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use x_casl2::grader::harness::Harness;
+
+    /// GR1とDATの和をGR0とANSに入れる
+    const SRC: &str = "ADD\tSTART
+\tLD\tGR0,GR1
+\tADDA\tGR0,DAT
+\tST\tGR0,ANS
+\tRET
+DAT\tDS\t1
+ANS\tDS\t1
+\tEND";
+
+    #[test]
+    fn test_harness() {
+        let mut h = Harness::new(SRC).unwrap();
+        h.set("GR1", -3).set("DAT", 10).call("ADD");
+        h.assert_reg("GR0", 7);
+        h.assert_mem("ANS", &[7]);
+        h.assert_flag("SF", false);
+        h.assert_preserved(&["GR1", "GR2"]);
+        assert_eq!(h.steps, 4);
+
+        // 失敗したら状態の変化と実行した命令を出す
+        let err = catch_unwind(AssertUnwindSafe(|| h.assert_reg("GR0", 8))).unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.starts_with("GR0: expected #0008, got #0007"));
+        assert!(msg.contains("  GR0  #0000 -> #0007  *"));
+        assert!(msg.contains("  #0001 ADD+1        line 3: ADDA GR0,DAT"));
+
+        // 戻り番地が積まれた語の上でも止まる
+        let mut h = Harness::new(SRC).unwrap();
+        h.loader.stack_top = 0;
+        h.set("GR1", 1).set("DAT", 2).call("ADD");
+        h.assert_reg("GR0", 3);
+
+        // 1語に入らない値は書かない
+        h.set("GR1", -32768).set("DAT", 65535);
+        assert_eq!((h.reg("GR1"), h.mem("DAT")), (0x8000, 0xFFFF));
+        let err = catch_unwind(AssertUnwindSafe(|| { h.set("DAT", 65536); })).unwrap_err();
+        assert_eq!(err.downcast_ref::<String>().unwrap(), "DAT: value out of range: 65536");
+        assert!(catch_unwind(AssertUnwindSafe(|| { h.set("GR1", -32769); })).is_err());
+    }
+}
@@ -0,0 +1,50 @@
+#[cfg(all(test, feature = "interrupts"))]
+mod tests {
+    use x_casl2::emurator::{casl2::code_gen::CodeGenerator, commet2::{bus::MemoryMap, cpu::CPUExecution, interrupt::{InterruptController, IntervalTimer}, prefix::machine_cycle}, loader::Loader};
+
+    /// タイマー割り込みを3回数えるまで回る
+    const SRC: &str = "MAIN\tSTART
+\tLAD\tGR1,TICK
+\tST\tGR1,#FF00
+\tLAD\tGR1,200
+\tST\tGR1,#FE10
+\tLAD\tGR1,1
+\tST\tGR1,#FE00
+\tLAD\tGR4,3
+LOOP\tLAD\tGR3,1,GR3
+\tLD\tGR2,COUNT
+\tCPA\tGR2,GR4
+\tJMI\tLOOP
+\tLAD\tGR1,0
+\tST\tGR1,#FE10
+\tRET
+TICK\tLD\tGR5,COUNT
+\tLAD\tGR5,1,GR5
+\tST\tGR5,COUNT
+\tRETI
+COUNT\tDC\t0
+\tEND";
+
+    #[test]
+    fn test_timer_interrupt() {
+        let mut code_gen = CodeGenerator::from_source("", SRC).unwrap();
+        let objects = code_gen.generate_standalone().unwrap();
+        let mut cpu = Loader::new().load_objects(&objects).unwrap();
+        let controller = InterruptController::new();
+        let mut map = MemoryMap::new();
+        map.map(0xFE00, Box::new(controller.clone())).unwrap();
+        map.map(0xFE10, Box::new(IntervalTimer::new(controller.clone(), 0))).unwrap();
+        cpu.bus = Some(Box::new(map));
+        cpu.interrupts = Some(controller.clone());
+        let sp = cpu.state.sp;
+        while cpu.state.machine_cycle != machine_cycle::END {
+            cpu.commet2_step();
+            assert!(cpu.state.cycle < 10_000);
+        }
+        assert_eq!(cpu.state.memory.0[code_gen.lookup("COUNT").unwrap() as usize], 3);
+        assert!(cpu.state.gr.get(3) > 0);
+        // RETIで積んだ分は戻り、許可に戻っている
+        assert_eq!(cpu.state.sp, sp.wrapping_add(1));
+        assert!(controller.enabled());
+    }
+}
@@ -0,0 +1,54 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::emurator::casl2::{code_gen::CodeGenerator, err::Casl2AssemblerError, linker::link, object::{ObjectFile, Symbol}};
+
+    const MAIN: &str = "MAIN\tSTART\n\tLAD\tGR1,2\n\tCALL\tDOUBLE\n\tST\tGR1,ANS\n\tRET\nANS\tDS\t1\n\tEND";
+    const DOUBLE: &str = "DOUBLE\tSTART\n\tADDA\tGR1,GR1\n\tRET\n\tEND";
+
+    fn object(src: &str) -> ObjectFile {
+        CodeGenerator::from_source("", src).unwrap().generate_object().unwrap().remove(0)
+    }
+
+    #[test]
+    fn test_link() {
+        let main = object(MAIN);
+        assert_eq!(main.externals[0].name, "DOUBLE");
+        assert_eq!(main.externals[0].offset, 3);
+        assert_eq!(main.relocations, vec![5]);
+        // テキスト形式で往復できる
+        assert_eq!(ObjectFile::from_text(&main.to_text()).unwrap(), main);
+
+        let image = link(&[main, object(DOUBLE)], 0x100).unwrap();
+        assert_eq!(image.entry, 0x100);
+        assert_eq!(image.symbols["DOUBLE"], 0x108);
+        assert_eq!(image.code, vec![0x1210, 2, 0x8000, 0x108, 0x1110, 0x107, 0x8100, 0, 0x2411, 0x8100]);
+
+        // 同じ名前の単位と見つからない参照
+        let err = link(&[object(DOUBLE), object(DOUBLE)], 0).unwrap_err();
+        assert_eq!(err.to_string(), "Link error: Duplicate symbol: DOUBLE (defined in DOUBLE and DOUBLE)");
+        let err = link(&[object(MAIN)], 0).unwrap_err();
+        assert_eq!(err.to_string(), "Link error: Missing symbol: DOUBLE (referenced from MAIN)");
+    }
+
+    #[test]
+    fn test_object_per_program() {
+        // 1つのファイルの2つのプログラムはそれぞれのオブジェクトになる
+        let src = format!("{}\n{}", MAIN, DOUBLE);
+        let objects = CodeGenerator::from_source("", &src).unwrap().generate_object().unwrap();
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[0], object(MAIN));
+        assert_eq!(objects[1], object(DOUBLE));
+        assert_eq!(objects[1].exports, vec![Symbol { name: "DOUBLE".to_string(), offset: 0 }]);
+        let image = link(&objects, 0x100).unwrap();
+        assert_eq!(image.units, vec![("MAIN".to_string(), 0x100), ("DOUBLE".to_string(), 0x108)]);
+        assert_eq!(image.code[3], 0x108);
+
+        // 別のプログラムへの参照は外部参照のまま残り、どれもファイルの中で解決できればそのまま通す
+        assert_eq!(objects[0].externals, vec![Symbol { name: "DOUBLE".to_string(), offset: 3 }]);
+        assert_eq!(CodeGenerator::from_source("", &src).unwrap().generate_standalone().unwrap(), objects);
+        // どこにもないラベルはアセンブルエラー
+        let err = CodeGenerator::from_source("", MAIN).unwrap().generate_standalone().unwrap_err();
+        assert!(matches!(err, Casl2AssemblerError::At { line: 2, column: 6, .. }));
+        assert!(matches!(err.inner(), Casl2AssemblerError::UnknownLabel(label) if label == "DOUBLE"));
+    }
+}
@@ -0,0 +1,54 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::emurator::casl2::linter::{lint, Lint, LintConfig, LintLevel};
+
+    const SRC: &str = "MAIN\tSTART
+\tLD\tGR1,DATA,GR0
+\tST\tGR1,DATA
+\tCALL\tSUB
+\tRET
+\tNOP
+SUB\tPUSH\t0,GR1
+\tADDA\tGR1,ONE
+\tRET
+UNUSED\tLD\tGR2,GR1
+DATA\tDC\t3
+ONE\tDC\t1
+\tEND";
+
+    #[test]
+    fn test_lint() {
+        let warnings = lint(SRC, &LintConfig::new()).unwrap();
+        let found: Vec<(Lint, usize)> = warnings.iter().map(|w| (w.lint, w.line)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (Lint::Gr0Index, 1),
+                (Lint::WriteToConstant, 2),
+                (Lint::UnreachableCode, 5),
+                (Lint::UnbalancedStack, 8),
+                (Lint::UnusedLabel, 9),
+                (Lint::ReachableData, 10),
+            ]
+        );
+        assert_eq!(warnings[0].column, 13);
+
+        // 同じ行は桁の順
+        let same_line = "MAIN\tSTART\n\tRET\n\tST\tGR1,X\n\tEND";
+        let warnings = lint(same_line, &LintConfig::new()).unwrap();
+        let found: Vec<(Lint, usize, usize)> = warnings.iter().map(|w| (w.lint, w.line, w.column)).collect();
+        assert_eq!(found, vec![(Lint::UnreachableCode, 2, 0), (Lint::UndefinedLabel, 2, 8)]);
+
+        let ok = "MAIN\tSTART\n\tLD\tGR1,A\n\tRET\nA\tDC\t1\n\tEND";
+        let missing_ret = "MAIN\tSTART\n\tLD\tGR1,A\n\tEND";
+        assert!(lint(ok, &LintConfig::new()).unwrap().is_empty());
+        let warnings = lint(missing_ret, &LintConfig::new()).unwrap();
+        assert_eq!(warnings.iter().map(|w| w.lint).collect::<Vec<_>>(), vec![Lint::UndefinedLabel, Lint::MissingRet]);
+
+        // リントごとに設定できる
+        let mut config = LintConfig::new();
+        config.set(Lint::UnusedLabel, LintLevel::Allow).set(Lint::Gr0Index, LintLevel::Deny);
+        let err = lint(SRC, &config).unwrap_err().to_string();
+        assert_eq!(err, "Lint error: GR0 cannot be used as an index register (it means no index) [gr0-index], line: 2");
+    }
+}
@@ -0,0 +1,31 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::emurator::{commet2::{cpu::CPUExecution, prefix::machine_cycle}, loader::Loader};
+
+    #[test]
+    fn test_load_and_return() {
+        let src = "MAIN\tSTART\n\tCALL\tDOUBLE\n\tST\tGR1,ANS\n\tRET\nANS\tDS\t1\n\tEND\nDOUBLE\tSTART\n\tADDA\tGR1,GR2\n\tRET\n\tEND";
+        let loader = Loader { base: 0x200, stack_top: 0x1000, ..Loader::new() }.arg(1, 3).arg(2, 4);
+        let mut cpu = loader.load_source(src).unwrap();
+        assert_eq!(cpu.state.pr, 0x200);
+        assert_eq!(cpu.state.sp, 0x0FFF);
+
+        // 最後のRETで止まる
+        for _ in 0..10 {
+            cpu.casl_step();
+        }
+        assert_eq!(cpu.state.machine_cycle, machine_cycle::END);
+        assert_eq!(cpu.state.pr, loader.sentinel);
+        assert_eq!(cpu.state.sp, 0x1000);
+        assert_eq!(cpu.state.memory.0[0x205], 7);
+
+        // スタックがプログラムに重なる
+        let loader = Loader { stack_top: 3, ..Loader::new() };
+        assert!(loader.load_source(src).is_err());
+
+        // 戻り番地がプログラムの中
+        let loader = Loader { base: 0x200, sentinel: 0x203, ..Loader::new() };
+        let err = loader.load_source(src).err().unwrap().to_string();
+        assert!(err.contains("Sentinel #0203 is inside the program"), "{}", err);
+    }
+}
@@ -0,0 +1,87 @@
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use x_casl2::{lsp::{analysis::Document, serve}, rpc::{read_message, write_message}};
+
+    const URI: &str = "file:///sample.cas";
+    const SRC: &str = "MAIN\tSTART\n\tLD\tGR1,DATA\n\tCALL\tSUB\n\tRET\nSUB\tADDA\tGR1,DATA ; 2倍\n\tRET\nDATA\tDC\t3\n\tEND";
+
+    /// 要求を流してサーバーの出力を集める
+    fn session(messages: &[Value]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for msg in messages {
+            write_message(&mut input, msg).unwrap();
+        }
+        let mut output = Vec::new();
+        serve(&mut std::io::Cursor::new(input), &mut output).unwrap();
+        let mut reader = std::io::Cursor::new(output);
+        let mut out = Vec::new();
+        while let Some(msg) = read_message(&mut reader).unwrap() {
+            out.push(msg);
+        }
+        out
+    }
+
+    fn request(id: i64, method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    fn position(line: usize, character: usize) -> Value {
+        json!({ "textDocument": { "uri": URI }, "position": { "line": line, "character": character } })
+    }
+
+    #[test]
+    fn test_recorded_session() {
+        let out = session(&[
+            request(1, "initialize", json!({})),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": { "uri": URI, "languageId": "casl2", "version": 1, "text": SRC },
+            } }),
+            request(2, "textDocument/definition", position(2, 7)),
+            request(3, "textDocument/references", json!({
+                "textDocument": { "uri": URI }, "position": { "line": 6, "character": 1 }, "context": { "includeDeclaration": false },
+            })),
+            request(4, "textDocument/hover", position(4, 5)),
+            request(5, "textDocument/documentSymbol", json!({ "textDocument": { "uri": URI } })),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+                "textDocument": { "uri": URI, "version": 2 },
+                "contentChanges": [{ "text": "MAIN\tSTART\n\tJUMP\tNONE\n\tEND" }],
+            } }),
+            request(6, "shutdown", Value::Null),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ]);
+
+        assert!(out[0]["result"]["capabilities"]["hoverProvider"].as_bool().unwrap());
+        assert_eq!(out[1]["params"]["diagnostics"], json!([]));
+        assert_eq!(out[2]["result"]["range"]["start"], json!({ "line": 4, "character": 0 }));
+        let refs = out[3]["result"].as_array().unwrap();
+        assert_eq!(refs.len(), 2);
+        assert_eq!(refs[1]["range"]["start"], json!({ "line": 4, "character": 13 }));
+        let hover = out[4]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(hover.contains("ADDA r,adr[,x]: 20 rx adr"));
+        assert!(hover.contains("OF, SF, ZF"));
+        let symbols = &out[5]["result"][0];
+        assert_eq!(symbols["name"], "MAIN");
+        assert_eq!(symbols["range"]["end"]["line"], 7);
+        assert_eq!(symbols["children"].as_array().unwrap().len(), 2);
+        let diagnostics = out[6]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["message"], "Unknown label: NONE");
+        assert_eq!(diagnostics[0]["range"]["start"]["character"], 6);
+        assert_eq!(out[7]["id"], 6);
+    }
+
+    #[test]
+    fn test_label_scopes() {
+        let src = "MAIN\tSTART\n\tCALL\tSUB\n\tJUMP\tL\nL\tRET\n\tEND\nSUB\tSTART\n\tJUMP\tL\nL\tRET\n\tEND";
+        let doc = Document::new(src);
+        assert!(doc.diagnostics.is_empty());
+        // 同じ名前でもプログラムごとに別のラベル
+        assert_eq!(doc.label_at(2, 6).as_deref(), Some("MAIN.L"));
+        assert_eq!(doc.label_at(6, 6).as_deref(), Some("SUB.L"));
+        assert_eq!(doc.definitions["SUB.L"].line, 7);
+        assert_eq!(doc.addresses["SUB.L"], 7);
+        assert_eq!(doc.visible_labels(6), vec!["L", "MAIN", "SUB"]);
+    }
+}
@@ -0,0 +1,79 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::{emurator::{casl2::profile::ProfileReport, commet2::{profile::Profile, protection::MemoryProtection}}, grader::harness::Harness};
+
+    /// MAINがSUBを2回呼び、SUBがLEAFを1回呼ぶ
+    const SRC: &str = "MAIN\tSTART
+\tCALL\tSUB
+\tCALL\tSUB
+\tRET
+\tEND
+SUB\tSTART
+\tCALL\tLEAF
+\tRET
+\tEND
+LEAF\tSTART
+\tNOP
+\tRET
+\tEND";
+
+    #[test]
+    fn test_profile() {
+        let mut h = Harness::new(SRC).unwrap();
+        h.cpu.profile = Some(Profile::new());
+        h.call("MAIN");
+        let report = ProfileReport::new(&h.code_gen, h.cpu.profile.as_ref().unwrap());
+        // 戻り番地で止まったサイクルは数えない
+        assert_eq!(report.cycles, h.cpu.state.cycle);
+
+        let row = |name: &str| report.rows.iter().find(|r| r.name == name).unwrap().clone();
+        let (main, sub, leaf) = (row("MAIN"), row("SUB"), row("LEAF"));
+        assert_eq!((main.calls, sub.calls, leaf.calls), (1, 2, 2));
+        assert_eq!(main.total_cycles, report.cycles);
+        assert_eq!(sub.total_cycles, sub.self_cycles + leaf.total_cycles);
+        assert_eq!(main.self_cycles + sub.self_cycles + leaf.self_cycles, report.cycles);
+
+        let folded = report.folded();
+        assert_eq!(folded.lines().map(|l| l.rsplit_once(' ').unwrap().0).collect::<Vec<_>>(), ["MAIN", "MAIN;SUB", "MAIN;SUB;LEAF"]);
+        assert!(folded.contains(&format!("MAIN;SUB;LEAF {}\n", leaf.self_cycles)));
+        assert!(report.table().starts_with("label       self   self%"));
+    }
+
+    /// SUBがSVCの入口KERNELに入り、戻ってからも続ける
+    const GATE: &str = "MAIN\tSTART
+\tCALL\tSUB
+\tRET
+\tEND
+SUB\tSTART
+\tSVC\t7
+\tNOP
+\tRET
+\tEND
+KERNEL\tSTART
+\tPOP\tGR3
+\tRET
+\tEND";
+
+    #[test]
+    fn test_profile_gate() {
+        let profile = |gate: bool| {
+            let mut h = Harness::new(GATE).unwrap();
+            // ホストに任せる方はSVC 7を何もしない
+            h.cpu.supervisor = None;
+            if gate {
+                let mut protection = MemoryProtection::new();
+                protection.gate = h.code_gen.lookup("KERNEL");
+                h.cpu.protection = Some(protection);
+            }
+            h.cpu.profile = Some(Profile::new());
+            h.call("MAIN");
+            ProfileReport::new(&h.code_gen, h.cpu.profile.as_ref().unwrap())
+        };
+        let (host, gate) = (profile(false), profile(true));
+        let folded = gate.folded();
+        assert_eq!(folded.lines().map(|l| l.rsplit_once(' ').unwrap().0).collect::<Vec<_>>(), ["MAIN", "MAIN;SUB", "MAIN;SUB;KERNEL"]);
+        // 入口からのRETでSUBは降ろさない SVCの後のNOPとRETもSUBに入る
+        let self_cycles = |report: &ProfileReport, name: &str| report.rows.iter().find(|r| r.name == name).unwrap().self_cycles;
+        assert_eq!(self_cycles(&gate, "MAIN"), self_cycles(&host, "MAIN"));
+    }
+}
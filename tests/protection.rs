@@ -0,0 +1,68 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::{emurator::{commet2::{bus::AccessKind, protection::{MemoryProtection, Region}}, loader::Loader}, grader::{harness::Harness, Outcome}};
+
+    /// プログラムを保護してMAINを呼ぶ 保護で止まるはず
+    fn run_protected(src: &str) -> Harness {
+        let mut h = Harness::new(src).unwrap();
+        h.cpu.protection = Some(h.code_gen.protection());
+        assert!(matches!(h.try_call("MAIN"), Err(Outcome::Fault(_))));
+        h
+    }
+
+    #[test]
+    fn test_program_protection() {
+        // 命令を書き換える
+        let h = run_protected("MAIN\tSTART\n\tLAD\tGR1,0\n\tST\tGR1,MAIN\n\tRET\nBUF\tDS\t1\n\tEND");
+        let fault = h.cpu.fault.clone().unwrap();
+        assert_eq!((fault.pr, fault.addr, fault.kind), (2, 0, AccessKind::Write));
+        assert_eq!(fault.to_string(), "write to read-only memory: write #0000 at PR #0002");
+        assert_ne!(h.mem("MAIN"), 0);
+
+        // データを実行する DSには書ける
+        let h = run_protected("MAIN\tSTART\n\tST\tGR1,BUF\n\tJUMP\tBUF\nBUF\tDS\t1\n\tEND");
+        let fault = h.cpu.fault.clone().unwrap();
+        assert_eq!((fault.pr, fault.addr, fault.kind), (4, 4, AccessKind::Fetch));
+
+        // ローダーが置いた番地からの保護
+        let mut h = Harness::load("MAIN\tSTART\n\tLAD\tGR1,0\n\tST\tGR1,MAIN\n\tRET\n\tEND", Loader { base: 0x200, ..Loader::new() }).unwrap();
+        let protection = h.code_gen.protection();
+        assert_eq!(protection.check(0x100, AccessKind::Fetch), Some("execute from no-execute memory"));
+        assert_eq!(protection.check(0x200, AccessKind::Fetch), None);
+        h.cpu.protection = Some(protection);
+        assert!(matches!(h.try_call("MAIN"), Err(Outcome::Fault(_))));
+        let fault = h.cpu.fault.clone().unwrap();
+        assert_eq!((fault.pr, fault.addr, fault.kind), (0x202, 0x200, AccessKind::Write));
+    }
+
+    /// KERNELとSECRETはスーパーバイザ専用 SVCの入口からだけ読める
+    const SRC: &str = "MAIN\tSTART
+\tSVC\t7
+\tLD\tGR2,SECRET
+\tRET
+\tEND
+KERNEL\tSTART
+\tPOP\tGR3
+\tLD\tGR1,SECRET
+\tRET
+\tEND
+SECRET\tSTART
+\tDC\t42
+\tEND";
+
+    #[test]
+    fn test_supervisor_mode() {
+        let mut h = Harness::new(SRC).unwrap();
+        let kernel = h.addr("KERNEL");
+        let mut protection = MemoryProtection::new().region(Region::new(kernel, 0xFEFF).supervisor_only());
+        protection.gate = Some(kernel);
+        h.cpu.protection = Some(protection);
+        assert!(matches!(h.try_call("MAIN"), Err(Outcome::Fault(_))));
+        assert_eq!((h.reg("GR1"), h.reg("GR3")), (42, 7));
+        // 戻った後はユーザモード
+        let fault = h.cpu.fault.clone().unwrap();
+        assert_eq!((fault.pr, fault.addr, fault.kind), (2, h.addr("SECRET"), AccessKind::Read));
+        assert!(!h.cpu.protection.as_ref().unwrap().supervisor);
+        assert_eq!(h.reg("GR2"), 0);
+    }
+}
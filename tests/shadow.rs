@@ -0,0 +1,47 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::{emurator::{casl2::shadow::describe, commet2::shadow::Location}, grader::harness::Harness};
+
+    /// SUBはRPUSH/RPOPで未初期化のGRを運ぶだけ INで読んだ所は初期化済み
+    const SRC: &str = "MAIN\tSTART
+\tCALL\tSUB
+\tIN\tIBUF,ILEN
+\tLD\tGR1,IBUF
+\tLD\tGR2,BUF
+\tADDA\tGR1,GR2
+\tRET
+BUF\tDS\t2
+IBUF\tDS\t4
+ILEN\tDS\t1
+\tEND
+SUB\tSTART
+\tRPUSH
+\tRPOP
+\tRET
+\tEND";
+
+    #[test]
+    fn test_uninitialised_read() {
+        let mut h = Harness::new(SRC).unwrap();
+        let mut shadow = h.code_gen.shadow();
+        // ローダーが積んだ戻り番地
+        shadow.define(Location::Memory(h.cpu.state.sp));
+        h.cpu.shadow = Some(shadow);
+        h.input("AB").call("MAIN");
+        let (code_gen, cpu) = (&mut h.code_gen, &h.cpu);
+        let warnings = &cpu.shadow.as_ref().unwrap().warnings;
+        let buf = Location::Memory(code_gen.lookup("BUF").unwrap());
+        assert_eq!(warnings.iter().map(|w| w.location).collect::<Vec<_>>(), [buf, Location::Register(2)]);
+        assert_eq!(warnings[1].value.origin, buf);
+        assert_eq!(
+            describe(code_gen, &warnings[1]),
+            "line 6: ADDA GR1,GR2: ADDA reads uninitialised GR2\n    origin: BUF (line 8: DS 2)\n    via: line 5: LD GR2,BUF\n"
+        );
+        // ローダーが置いた番地からの影
+        code_gen.base = 0x200;
+        let shadow = code_gen.shadow();
+        assert!(shadow.is_defined(Location::Memory(0x200)));
+        assert!(!shadow.is_defined(Location::Memory(0)));
+        assert!(!shadow.is_defined(Location::Memory(code_gen.lookup("BUF").unwrap())));
+    }
+}
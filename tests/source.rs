@@ -0,0 +1,47 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::emurator::casl2::{code_gen::CodeGenerator, source::{SourceEncoding, SourceText}};
+
+    /// `MAIN START ; 漢字` `MSG DC 'ｶﾅ'` をそれぞれの文字コードで
+    fn sample(kanji: &[u8], kana: &[u8]) -> Vec<u8> {
+        [b"MAIN\tSTART\t; ".as_slice(), kanji, b"\r\n\tRET\nMSG\tDC\t'", kana, b"'\n\tEND\n"].concat()
+    }
+
+    #[test]
+    fn test_shift_jis_and_euc_jp() {
+        let sjis = sample(&[0x8A, 0xBF, 0x8E, 0x9A], &[0xB6, 0xC5]);
+        let euc = sample(&[0xB4, 0xC1, 0xBB, 0xFA], &[0x8E, 0xB6, 0x8E, 0xC5]);
+        for (bytes, encoding) in [(sjis, SourceEncoding::ShiftJis), (euc, SourceEncoding::EucJp)] {
+            let src = SourceText::decode(&bytes, None).unwrap();
+            assert_eq!(src.encoding, encoding);
+            assert_eq!(src.text, "MAIN\tSTART\t; 漢字\r\n\tRET\nMSG\tDC\t'ｶﾅ'\n\tEND\n");
+
+            let (code_gen, bin) = CodeGenerator::assemble(&src.text).unwrap();
+            let msg = code_gen.programs[0].labels["MSG"] as usize;
+            assert_eq!(bin[msg..msg + 2], [0xB6, 0xC5]);
+
+            // `ﾅ`の位置 EUC-JPの半角カナは2バイト
+            let line = bytes.windows(3).position(|w| w == b"MSG").unwrap();
+            let kana_len = if encoding == SourceEncoding::EucJp { 2 } else { 1 };
+            assert_eq!(src.byte_offset(2, 9), Some(line + 8 + kana_len));
+            assert_eq!(src.encode(&src.text).unwrap(), bytes);
+        }
+
+        // 指定した文字コードとして読めない
+        assert!(SourceText::decode(&[0x82], Some(SourceEncoding::ShiftJis)).is_err());
+    }
+
+    #[test]
+    fn test_describe_error() {
+        // エラーの位置は元のファイルのバイト位置でも示す
+        let bytes = [b"MAIN\tSTART\t; ".as_slice(), &[0x8A, 0xBF, 0x8E, 0x9A], b"\r\n\tFOO\tGR1\n\tEND\n"].concat();
+        let src = SourceText::decode(&bytes, None).unwrap();
+        let err = CodeGenerator::from_source("prog.cas", &src.text).err().unwrap();
+        assert_eq!(src.describe("prog.cas", &err), "prog.cas:2:2 (byte 20): Invalid instruction: FOO, line: 2\n\t\tFOO\tGR1");
+
+        // 使った位置がわかる未定義のラベル
+        let src = SourceText::decode("MAIN\tSTART\n\tCALL\tSUB\n\tRET\n\tEND\n".as_bytes(), None).unwrap();
+        let err = CodeGenerator::from_source("prog.cas", &src.text).unwrap().generate_standalone().unwrap_err();
+        assert_eq!(src.describe("prog.cas", &err), "prog.cas:2:7 (byte 17): Unknown label: SUB");
+    }
+}
@@ -0,0 +1,62 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::{emurator::{commet2::stack::StackGuard, loader::Loader}, grader::harness::Harness};
+
+    /// スタックを見張ってMAINを呼ぶ 止まってもそのまま返す
+    fn run(src: &str) -> Harness {
+        run_with(src, Loader::new())
+    }
+
+    fn run_with(src: &str, loader: Loader) -> Harness {
+        let mut h = Harness::load(src, loader).unwrap();
+        h.cpu.stack = Some(StackGuard::new(h.image_end, h.cpu.state.sp));
+        let _ = h.try_call("MAIN");
+        h
+    }
+
+    /// DOWNはGR1が0になるまで自分を呼ぶ 1段でGR1の退避と戻り番地の2語
+    const RECURSIVE: &str = "MAIN\tSTART
+\tLAD\tGR1,3
+\tCALL\tDOWN
+\tRET
+\tEND
+DOWN\tSTART
+\tLD\tGR1,GR1
+\tJZE\tFIN
+\tPUSH\t0,GR1
+\tSUBA\tGR1,ONE
+\tCALL\tDOWN
+\tPOP\tGR1
+FIN\tRET
+ONE\tDC\t1
+\tEND";
+
+    #[test]
+    fn test_stack_depth() {
+        let h = run(RECURSIVE);
+        assert_eq!(h.cpu.fault, None);
+        let stack = h.cpu.stack.as_ref().unwrap();
+        // 戻り番地 + MAINのCALL + DOWNが3段
+        assert_eq!(stack.max_depth(), 1 + 1 + 3 * 2);
+        assert_eq!(stack.usage[&h.addr("MAIN")], 1);
+        assert_eq!(stack.usage[&h.addr("DOWN")], 2);
+
+        // 止まらない再帰はプログラムの手前で止まる
+        let h = run("MAIN\tSTART\n\tCALL\tMAIN\n\tEND");
+        let fault = h.cpu.fault.unwrap();
+        assert_eq!((fault.reason.as_str(), fault.addr), ("stack overflow", 1));
+
+        // 積んでいないのに降ろす
+        let h = run("MAIN\tSTART\n\tPOP\tGR1\n\tPOP\tGR1\n\tRET\n\tEND");
+        assert_eq!(h.cpu.fault.unwrap().reason, "stack underflow");
+
+        // スタックの底が#FFFF 最後のRETでSPが0に回るのは降ろしただけ
+        let h = run_with(RECURSIVE, Loader { stack_top: 0, ..Loader::new() });
+        assert_eq!(h.cpu.fault, None);
+        let stack = h.cpu.stack.as_ref().unwrap();
+        assert_eq!(stack.max_depth(), 1 + 1 + 3 * 2);
+        assert_eq!(stack.usage[&h.addr("MAIN")], 1);
+        let h = run_with("MAIN\tSTART\n\tPOP\tGR1\n\tPOP\tGR1\n\tRET\n\tEND", Loader { stack_top: 0, ..Loader::new() });
+        assert_eq!(h.cpu.fault.unwrap().reason, "stack underflow");
+    }
+}
@@ -0,0 +1,63 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::{emurator::{casl2::chrome_trace::chrome_trace, commet2::{cpu::ExecEvent, protection::MemoryProtection, trace::TraceRecorder}}, grader::harness::Harness};
+
+    const SRC: &str = "MAIN\tSTART
+\tCALL\tSUB
+\tRET
+\tEND
+SUB\tSTART
+\tOUT\tMSG,LEN
+\tRET
+MSG\tDC\t'HI'
+LEN\tDC\t2
+\tEND";
+
+    #[test]
+    fn test_chrome_trace() {
+        let mut h = Harness::new(SRC).unwrap();
+        let recorder = TraceRecorder::new(false);
+        h.cpu.tracer = Some(Box::new(recorder.clone()));
+        let entry = h.addr("MAIN");
+        h.call("MAIN");
+        let (code_gen, cpu) = (&h.code_gen, &h.cpu);
+        let events = recorder.events();
+        // 命令は記録しない CALL, SVC, RET, RET
+        assert_eq!(events.len(), 4);
+        assert!(matches!(events[0].1, ExecEvent::Call { .. }));
+        assert!(matches!(events[1].1, ExecEvent::Svc { code: 2, .. }));
+        assert!(events.windows(2).all(|w| w[0].0 <= w[1].0));
+
+        let trace = chrome_trace(code_gen, &events, entry, cpu.state.cycle);
+        let list = trace["traceEvents"].as_array().unwrap();
+        let names: Vec<_> = list.iter().map(|e| (e["ph"].as_str().unwrap(), e["name"].as_str().unwrap())).collect();
+        assert_eq!(names, [("M", "thread_name"), ("B", "MAIN"), ("B", "SUB"), ("i", "OUT"), ("E", ""), ("E", "")]);
+        assert_eq!(list[2]["args"]["from"], "MAIN");
+        assert_eq!(list[5]["ts"], cpu.state.cycle - 1);
+    }
+
+    #[test]
+    fn test_chrome_trace_gate() {
+        // SVCの入口は呼び出しとしてスパンになり、入口からのRETはSUBを閉じない
+        let src = "MAIN\tSTART\n\tCALL\tSUB\n\tRET\n\tEND\nSUB\tSTART\n\tSVC\t7\n\tNOP\n\tRET\n\tEND\nKERNEL\tSTART\n\tPOP\tGR3\n\tRET\n\tEND";
+        let mut h = Harness::new(src).unwrap();
+        let mut protection = MemoryProtection::new();
+        protection.gate = h.code_gen.lookup("KERNEL");
+        h.cpu.protection = Some(protection);
+        let recorder = TraceRecorder::new(false);
+        h.cpu.tracer = Some(Box::new(recorder.clone()));
+        let entry = h.addr("MAIN");
+        h.call("MAIN");
+
+        let trace = chrome_trace(&h.code_gen, &recorder.events(), entry, h.cpu.state.cycle);
+        let list = trace["traceEvents"].as_array().unwrap();
+        let names: Vec<_> = list.iter().map(|e| (e["ph"].as_str().unwrap(), e["name"].as_str().unwrap())).collect();
+        assert_eq!(
+            names,
+            [("M", "thread_name"), ("B", "MAIN"), ("B", "SUB"), ("i", "SVC 7"), ("B", "KERNEL"), ("E", ""), ("E", ""), ("E", "")]
+        );
+        // SUBはNOPとRETの後で閉じる
+        assert!(list[5]["ts"].as_u64() < list[6]["ts"].as_u64());
+        assert!(list[6]["ts"].as_u64() < list[7]["ts"].as_u64());
+    }
+}
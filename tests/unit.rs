@@ -0,0 +1,61 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::grader::{unit::{parse, run_tests}, Outcome};
+
+    const SRC: &str = "MAIN\tSTART
+\tCALL\tSUM
+\tRET
+\tEND
+; GR1からGR2語の合計をGR0に入れて書き出す GR2を壊す
+SUM\tSTART
+\tLAD\tGR0,0
+LOOP\tADDA\tGR0,0,GR1
+\tLAD\tGR1,1,GR1
+\tSUBA\tGR2,ONE
+\tJNZ\tLOOP
+\tOUT\tMSG,LEN
+\tLD\tGR0,GR0
+\tRET
+ONE\tDC\t1
+MSG\tDC\t'OK'
+LEN\tDC\t2
+DAT\tDS\t3
+\tEND";
+
+    const TESTS: &str = "TEST sum ; 1+2+3
+    CALL    SUM
+    SET     SUM.DAT 1,2,3
+    SET     GR1 SUM.DAT
+    SET     GR2 3
+    SET     GR3 -5
+    EXPECT  GR0 6
+    EXPECT  ZF 0
+    EXPECT  SF 0
+    EXPECT  OUTPUT OK
+    PRESERVE GR3
+END
+
+TEST preserved
+    CALL    SUM
+    SET     GR2 1
+    PRESERVE GR2,GR3
+    STEPS   100
+END
+";
+
+    #[test]
+    fn test_unit_tests() {
+        let tests = parse(TESTS).unwrap();
+        let results = run_tests("sum.cas", SRC, &tests);
+        assert_eq!(results[0].outcome, Outcome::Passed);
+        assert_eq!(results[1].outcome, Outcome::Failed(vec!["GR2 not preserved: was #0001, got #0000".to_string()]));
+
+        // 知らないラベルはテストの誤り
+        let results = run_tests("sum.cas", SRC, &parse("TEST typo\n\tCALL SUM\n\tSET SUM.DTA 1\nEND\nTEST entry\n\tCALL SUN\nEND").unwrap());
+        assert_eq!(results[0].outcome, Outcome::CaseError("SUM.DTA: unknown label".to_string()));
+        assert_eq!(results[1].outcome.status(), "case_error");
+
+        assert!(parse("TEST x\n\tSET GR1 1\nEND").is_err());
+        assert!(parse("TEST x\n\tCALL SUM\n\tFOO\n").is_err());
+    }
+}
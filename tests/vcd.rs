@@ -0,0 +1,31 @@
+#[cfg(test)]
+mod tests {
+    use x_casl2::emurator::{commet2::{cpu::CPUExecution, prefix::machine_cycle, vcd::VcdWriter}, loader::Loader};
+
+    #[test]
+    fn test_vcd() {
+        let mut cpu = Loader::new().load_source("MAIN\tSTART\n\tLAD\tGR1,5\n\tRET\n\tEND").unwrap();
+        let mut vcd = VcdWriter::new(Vec::new(), &cpu.state).unwrap();
+        let mut cycles = 0;
+        for _ in 0..100 {
+            if cpu.state.machine_cycle == machine_cycle::END {
+                break;
+            }
+            let notify = cpu.commet2_step();
+            vcd.record(&cpu.state, &notify).unwrap();
+            cycles += 1;
+        }
+        assert_eq!(cpu.state.machine_cycle, machine_cycle::END);
+        // 止まった後は何も書かない
+        let notify = cpu.commet2_step();
+        vcd.record(&cpu.state, &notify).unwrap();
+        assert_eq!(vcd.time, cycles);
+
+        let text = String::from_utf8(vcd.finish().unwrap()).unwrap();
+        assert!(text.contains("$var wire 16 # PR $end\n"));
+        assert!(text.contains("$enddefinitions $end\n#0\n$dumpvars\n"));
+        // LADでGR1が5になる
+        assert!(text.contains("b0000000000000101 +\n"));
+        assert!(text.ends_with(&format!("#{}\n", cycles + 1)));
+    }
+}